@@ -0,0 +1,73 @@
+//! Compares workflow runs with and without `EngineOptions::resolution_cache`.
+//!
+//! Run with `cargo bench --bench ident_resolution`.
+
+use std::{ hint::black_box, time::{ Duration, Instant } };
+use routix_engine::{
+    CoreEngine,
+    engine::{ EngineOptions, lang::ast::Value },
+    models::case::CaseConfig,
+};
+
+const CASES: i32 = 5_000;
+const RUNS: u32 = 20;
+
+const PROGRAM: &str = r#"
+    function base(p) = p * weight + len(category)
+    function adjusted(p) = base(p) + bonus
+    function weighted(p) = adjusted(p) - threshold + adjusted(priority)
+    workflow hot {
+        score {
+            when category == "bug" and priority > threshold then score = weighted(priority)
+            when status == "open" and customer != "" then score = score + bonus
+            when priority < threshold then score = score + weight
+        }
+        sort { by score desc }
+    }
+"#;
+
+fn cases() -> Vec<CaseConfig> {
+    (0..CASES)
+        .map(|id| CaseConfig {
+            id,
+            category: if id % 3 == 0 { "bug" } else { "feature" }.to_string(),
+            status: if id % 2 == 0 { "open" } else { "closed" }.to_string(),
+            priority: id % 7,
+            customer: (id % 5 == 0).then(|| format!("customer_{}", id)),
+            score: 0,
+            ..Default::default()
+        })
+        .collect()
+}
+
+fn time_runs(resolution_cache: bool) -> Duration {
+    let mut engine = CoreEngine::new();
+    engine.set_options(EngineOptions { resolution_cache, ..Default::default() });
+    engine.set_global_variable("weight", Value::Number(3));
+    engine.set_global_variable("bonus", Value::Number(7));
+    engine.set_global_variable("threshold", Value::Number(3));
+    let program = engine.parse_program(PROGRAM).expect("benchmark program parses");
+    engine.register_functions(program.functions);
+    let workflow = &program.workflows[0];
+    let cases = cases();
+
+    let started = Instant::now();
+    for _ in 0..RUNS {
+        engine.clear_cases();
+        engine.add_cases(cases.clone()).expect("unique ids");
+        engine.execute_workflow(workflow).expect("benchmark workflow runs");
+        black_box(engine.get_cases());
+    }
+    started.elapsed()
+}
+
+fn main() {
+    // Warm up allocators and caches before measuring either mode
+    time_runs(false);
+
+    let uncached = time_runs(false);
+    let cached = time_runs(true);
+    let per_case = |total: Duration| total.as_nanos() as f64 / f64::from(RUNS) / f64::from(CASES);
+    println!("uncached: {:>8.2?} ({:.0} ns/case)", uncached, per_case(uncached));
+    println!("cached:   {:>8.2?} ({:.0} ns/case)", cached, per_case(cached));
+}
@@ -0,0 +1,317 @@
+use std::{ fs, io::Write, path::{ Path, PathBuf } };
+use crate::{
+    engine::{ CoreEngine, Diagnostic, EngineError, Severity, ValidationOptions, validation },
+    models::case::CaseConfig,
+    repl::Repl,
+};
+
+#[cfg(test)]
+mod tests;
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_VALIDATION: i32 = 1;
+pub const EXIT_RUNTIME: i32 = 2;
+
+const USAGE: &str = "\
+Usage:
+    routix run --workflow <file> --cases <file.json|file.csv> [--output <file>] [--trace]
+    routix check <file>...
+    routix fmt [--check] <file>...
+    routix repl";
+
+/// Entry point shared by the `routix` binary and tests. Returns the process exit code.
+pub fn run(args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    match args.first().map(String::as_str) {
+        Some("run") => run_command(&args[1..], out, err),
+        Some("check") => check_command(&args[1..], out, err),
+        Some("fmt") => fmt_command(&args[1..], out, err),
+        Some("repl") => repl_command(out, err),
+        _ => {
+            let _ = writeln!(err, "{}", USAGE);
+            EXIT_VALIDATION
+        }
+    }
+}
+
+struct RunArgs {
+    workflow: PathBuf,
+    cases: PathBuf,
+    output: Option<PathBuf>,
+    trace: bool,
+}
+
+fn parse_run_args(args: &[String]) -> Result<RunArgs, String> {
+    let mut workflow = None;
+    let mut cases = None;
+    let mut output = None;
+    let mut trace = false;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--workflow" => workflow = iter.next().map(PathBuf::from),
+            "--cases" => cases = iter.next().map(PathBuf::from),
+            "--output" => output = iter.next().map(PathBuf::from),
+            "--trace" => trace = true,
+            other => return Err(format!("unexpected argument '{}'", other)),
+        }
+    }
+
+    Ok(RunArgs {
+        workflow: workflow.ok_or("missing --workflow <file>")?,
+        cases: cases.ok_or("missing --cases <file>")?,
+        output,
+        trace,
+    })
+}
+
+fn run_command(args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let args = match parse_run_args(args) {
+        Ok(args) => args,
+        Err(msg) => {
+            let _ = writeln!(err, "error: {}\n\n{}", msg, USAGE);
+            return EXIT_VALIDATION;
+        }
+    };
+
+    let mut engine = CoreEngine::new();
+    let program = match engine.parse_program_from_file(&args.workflow) {
+        Ok(program) => program,
+        Err(e) => return report_error(err, &e),
+    };
+    let cases = match load_cases(&args.cases) {
+        Ok(cases) => cases,
+        Err(e) => return report_error(err, &e),
+    };
+
+    engine.set_trace(args.trace);
+    if let Err(e) = engine.add_cases(cases) {
+        return report_error(err, &e);
+    }
+
+    if let Err(e) = engine.execute_program(&program) {
+        let _ = writeln!(err, "error: {}", e);
+        return EXIT_RUNTIME;
+    }
+
+    let json = match engine.export_results_json() {
+        Ok(json) => json,
+        Err(e) => return report_error(err, &e),
+    };
+
+    match &args.output {
+        Some(path) => {
+            if let Err(source) = fs::write(path, json) {
+                return report_error(err, &EngineError::Io { path: path.clone(), source });
+            }
+        }
+        None => {
+            let _ = writeln!(out, "{}", json);
+        }
+    }
+
+    EXIT_SUCCESS
+}
+
+fn check_command(args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    if args.is_empty() {
+        let _ = writeln!(err, "error: missing file to check\n\n{}", USAGE);
+        return EXIT_VALIDATION;
+    }
+
+    let engine = CoreEngine::new();
+    let options = ValidationOptions { check_phase_order: true, ..ValidationOptions::default() };
+    let mut exit_code = EXIT_SUCCESS;
+
+    for path in args {
+        let program = match engine.parse_program_from_file(path) {
+            Ok(program) => program,
+            Err(e) => {
+                exit_code = exit_code.max(report_error(err, &e));
+                continue;
+            }
+        };
+        let diagnostics = validation::validate_program(&program, &options);
+        for diagnostic in &diagnostics {
+            report_diagnostic(err, path, diagnostic);
+        }
+        if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+            exit_code = exit_code.max(EXIT_VALIDATION);
+        } else {
+            let _ = writeln!(out, "{}: ok", path);
+        }
+    }
+
+    exit_code
+}
+
+/// Rewrite each file in canonical format. With `--check`, list the files
+/// that would change instead, failing if there are any.
+fn fmt_command(args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let check = args.first().is_some_and(|arg| arg == "--check");
+    let paths = if check { &args[1..] } else { args };
+    if paths.is_empty() {
+        let _ = writeln!(err, "error: missing file to format\n\n{}", USAGE);
+        return EXIT_VALIDATION;
+    }
+
+    let engine = CoreEngine::new();
+    let mut exit_code = EXIT_SUCCESS;
+
+    for path in paths {
+        let source = match fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(source) => {
+                exit_code = exit_code.max(report_error(err, &EngineError::Io { path: path.into(), source }));
+                continue;
+            }
+        };
+        let formatted = match engine.parse_program_cached(&source, Some(Path::new(path))) {
+            Ok(program) => program.to_string(),
+            Err(e) => {
+                exit_code = exit_code.max(report_error(err, &e));
+                continue;
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+        if check {
+            let _ = writeln!(out, "{}: not formatted", path);
+            exit_code = exit_code.max(EXIT_VALIDATION);
+        } else if let Err(source) = fs::write(path, formatted) {
+            exit_code = exit_code.max(report_error(err, &EngineError::Io { path: path.into(), source }));
+        } else {
+            let _ = writeln!(out, "{}: formatted", path);
+        }
+    }
+
+    exit_code
+}
+
+fn repl_command(out: &mut dyn Write, err: &mut dyn Write) -> i32 {
+    let stdin = std::io::stdin();
+    match Repl::new().run(stdin.lock(), out) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(e) => {
+            let _ = writeln!(err, "error: {}", e);
+            EXIT_RUNTIME
+        }
+    }
+}
+
+/// Print an error as a diagnostic and map it to an exit code.
+fn report_error(err: &mut dyn Write, error: &EngineError) -> i32 {
+    match error {
+        EngineError::Parse { path, line, column, message } => {
+            let location = path
+                .as_ref()
+                .map(|p| p.display().to_string())
+                .unwrap_or_else(|| "<input>".to_string());
+            let _ = writeln!(err, "{}:{}:{}: error: {}", location, line, column, message);
+            EXIT_VALIDATION
+        }
+        other => {
+            let _ = writeln!(err, "error: {}", other);
+            EXIT_RUNTIME
+        }
+    }
+}
+
+/// Print a validation diagnostic, with its position when it has one.
+fn report_diagnostic(err: &mut dyn Write, path: &str, diagnostic: &Diagnostic) {
+    let severity = match diagnostic.severity {
+        Severity::Error => "error",
+        Severity::Warning => "warning",
+    };
+    let location = match (diagnostic.line, diagnostic.column) {
+        (Some(line), Some(column)) => format!("{}:{}:{}", path, line, column),
+        _ => path.to_string(),
+    };
+    let workflow = diagnostic
+        .workflow
+        .as_ref()
+        .map(|name| format!("workflow '{}': ", name))
+        .unwrap_or_default();
+    let _ = writeln!(err, "{}: {}: {}{}", location, severity, workflow, diagnostic.message);
+}
+
+/// Load cases from a JSON array, or from CSV when the file has a `.csv` extension.
+pub fn load_cases(path: &Path) -> Result<Vec<CaseConfig>, EngineError> {
+    let contents = fs::read_to_string(path)
+        .map_err(|source| EngineError::Io { path: path.to_path_buf(), source })?;
+
+    if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("csv")) {
+        parse_csv_cases(&contents).map_err(EngineError::Serialization)
+    } else {
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// Parse cases from CSV with a header row naming the columns. `id`, `category`,
+/// `status` and `priority` are required; `customer`, `score` and `created_at`
+/// are optional.
+/// Fields are split on commas without quoting support.
+pub fn parse_csv_cases(contents: &str) -> Result<Vec<CaseConfig>, String> {
+    let mut lines = contents.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+    let (_, header) = lines.next().ok_or("CSV input is empty")?;
+    let columns: Vec<&str> = header.split(',').map(str::trim).collect();
+
+    let index_of = |name: &str| columns.iter().position(|c| *c == name);
+    let required = |name: &str| index_of(name).ok_or(format!("CSV header is missing column '{}'", name));
+    let id_col = required("id")?;
+    let category_col = required("category")?;
+    let status_col = required("status")?;
+    let priority_col = required("priority")?;
+    let customer_col = index_of("customer");
+    let score_col = index_of("score");
+    let created_at_col = index_of("created_at");
+
+    let mut cases = Vec::new();
+    for (line_index, line) in lines {
+        let line_number = line_index + 1;
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let field = |col: usize| {
+            fields
+                .get(col)
+                .copied()
+                .ok_or(format!("line {}: expected {} fields, got {}", line_number, columns.len(), fields.len()))
+        };
+        let number = |col: usize, name: &str| -> Result<i64, String> {
+            let raw = field(col)?;
+            raw.parse().map_err(|_| format!("line {}: invalid {} '{}'", line_number, name, raw))
+        };
+        // Ids and priorities are i32 on the case, so a wider value is rejected
+        // rather than truncated into another case's id
+        let small_number = |col: usize, name: &str| -> Result<i32, String> {
+            let value = number(col, name)?;
+            i32::try_from(value).map_err(|_| format!("line {}: invalid {} '{}'", line_number, name, value))
+        };
+
+        let customer = match customer_col {
+            Some(col) => Some(field(col)?).filter(|c| !c.is_empty()).map(str::to_string),
+            None => None,
+        };
+        let score = match score_col {
+            Some(col) if !field(col)?.is_empty() => number(col, "score")?,
+            _ => 0,
+        };
+        let created_at = match created_at_col {
+            Some(col) if !field(col)?.is_empty() => Some(number(col, "created_at")?),
+            _ => None,
+        };
+
+        cases.push(CaseConfig {
+            id: small_number(id_col, "id")?,
+            category: field(category_col)?.to_string(),
+            status: field(status_col)?.to_string(),
+            priority: small_number(priority_col, "priority")?,
+            customer,
+            score,
+            created_at,
+            ..Default::default()
+        });
+    }
+
+    Ok(cases)
+}
@@ -0,0 +1,201 @@
+#[cfg(test)]
+mod tests {
+    use crate::cli::{ run, parse_csv_cases, EXIT_SUCCESS, EXIT_VALIDATION, EXIT_RUNTIME };
+    use std::path::PathBuf;
+
+    fn write_temp_file(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("routix_cli_tests_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn run_cli(args: &[&str]) -> (i32, String, String) {
+        let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+        let mut out = Vec::new();
+        let mut err = Vec::new();
+        let code = run(&args, &mut out, &mut err);
+        (code, String::from_utf8(out).unwrap(), String::from_utf8(err).unwrap())
+    }
+
+    const TRIAGE: &str = r#"
+        workflow triage {
+            score {
+                when priority > 3 then score = priority * 10
+                when category == "bug" then score = score + 5
+            }
+            match {
+                when score > 40 then assign to urgent
+                when score > 0 then assign to normal
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_run_with_json_cases() {
+        let workflow = write_temp_file("triage.rtx", TRIAGE);
+        let cases = write_temp_file("cases.json", r#"[
+            {"id": 1, "category": "bug", "status": "open", "priority": 5},
+            {"id": 2, "category": "feature", "status": "open", "priority": 1, "customer": "acme"}
+        ]"#);
+        let output = workflow.with_file_name("results.json");
+
+        let (code, _, err) = run_cli(&[
+            "run",
+            "--workflow", workflow.to_str().unwrap(),
+            "--cases", cases.to_str().unwrap(),
+            "--output", output.to_str().unwrap(),
+        ]);
+        assert_eq!(code, EXIT_SUCCESS, "stderr: {}", err);
+
+        let report: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&output).unwrap()).unwrap();
+        assert_eq!(report["cases"][0]["score"], 55);
+        assert_eq!(report["assignments"]["urgent"], serde_json::json!([1]));
+        assert!(report.get("trace").is_none());
+    }
+
+    #[test]
+    fn test_run_with_csv_cases_and_trace() {
+        let workflow = write_temp_file("triage_trace.rtx", TRIAGE);
+        let cases = write_temp_file(
+            "cases.csv",
+            "id,category,status,priority,customer\n1,bug,open,5,\n2,feature,open,1,acme\n"
+        );
+
+        let (code, out, err) = run_cli(&[
+            "run",
+            "--workflow", workflow.to_str().unwrap(),
+            "--cases", cases.to_str().unwrap(),
+            "--trace",
+        ]);
+        assert_eq!(code, EXIT_SUCCESS, "stderr: {}", err);
+
+        let report: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(report["cases"][1]["customer"], "acme");
+        let trace = report["trace"].as_array().unwrap();
+        // Two score rules per case, plus the match rules evaluated until the first hit
+        assert_eq!(trace.iter().filter(|e| e["phase"] == "score").count(), 4);
+        assert_eq!(trace[0]["rule"], "when priority > 3 then score = priority * 10");
+    }
+
+    #[test]
+    fn test_check_reports_line_and_column() {
+        let good = write_temp_file("good_check.rtx", TRIAGE);
+        let bad = write_temp_file("bad_check.rtx", "workflow bad {\n  score { when then }\n}\n");
+
+        let (code, out, _) = run_cli(&["check", good.to_str().unwrap()]);
+        assert_eq!(code, EXIT_SUCCESS);
+        assert!(out.contains(": ok"));
+
+        let (code, _, err) = run_cli(&["check", bad.to_str().unwrap()]);
+        assert_eq!(code, EXIT_VALIDATION);
+        assert!(err.contains(&format!("{}:2:", bad.display())), "stderr: {}", err);
+    }
+
+    #[test]
+    fn test_check_runs_validation() {
+        let shadowing = write_temp_file("shadowing_check.rtx", "function len(x) = x\nworkflow w { filter { when true } }\n");
+        let (code, out, err) = run_cli(&["check", shadowing.to_str().unwrap()]);
+        assert_eq!(code, EXIT_VALIDATION);
+        assert!(!out.contains(": ok"));
+        assert!(err.contains("error: function 'len' shadows the built-in function"), "stderr: {}", err);
+
+        // Warnings are printed but don't fail the check
+        let misordered = write_temp_file("misordered_check.rtx", r#"
+            workflow ordered {
+                sort { by score desc }
+                score { when true then score = priority }
+            }
+        "#);
+        let (code, out, err) = run_cli(&["check", misordered.to_str().unwrap()]);
+        assert_eq!(code, EXIT_SUCCESS, "stderr: {}", err);
+        assert!(out.contains(": ok"));
+        assert!(err.contains("warning: workflow 'ordered': sort by 'score' runs before"), "stderr: {}", err);
+    }
+
+    #[test]
+    fn test_fmt_rewrites_and_checks() {
+        let messy = "workflow   triage when   count>0 {\n  score {when priority>3 then score=priority*10 otherwise then score = 1}\n  match round_robin { when true then assign to (queue_name) }\n  sort { by score }\n rank { by score desc dense into rank sorted }\n}\nglobal count = 1\nfunction pure double(x) = x*2\nfunction pick(a) { if a > 1 { return a; } else { let b = a + 1; return b; } }\n";
+        let path = write_temp_file("messy_fmt.rtx", messy);
+        let path = path.to_str().unwrap();
+
+        let (code, out, _) = run_cli(&["fmt", "--check", path]);
+        assert_eq!(code, EXIT_VALIDATION);
+        assert!(out.contains("not formatted"));
+
+        let (code, out, err) = run_cli(&["fmt", path]);
+        assert_eq!(code, EXIT_SUCCESS, "stderr: {}", err);
+        assert!(out.contains("formatted"));
+        let formatted = std::fs::read_to_string(path).unwrap();
+        assert_eq!(formatted, "\
+global count = 1
+
+function pure double(x) = x * 2
+
+function pick(a) {
+    if a > 1 {
+        return a;
+    } else {
+        let b = a + 1;
+        return b;
+    }
+}
+
+workflow triage when count > 0 {
+    score {
+        when priority > 3 then score = priority * 10
+        otherwise then score = 1
+    }
+    match round_robin {
+        when true then assign to (queue_name)
+    }
+    sort { by score asc }
+    rank { by score desc dense into rank sorted }
+}
+");
+
+        // Formatting is stable, so a second pass changes nothing
+        let (code, out, _) = run_cli(&["fmt", "--check", path]);
+        assert_eq!(code, EXIT_SUCCESS);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_runtime_error_exit_code() {
+        let workflow = write_temp_file("runtime_error.rtx", r#"
+            workflow broken {
+                score {
+                    when missing_variable > 1 then score = 1
+                }
+            }
+        "#);
+        let cases = write_temp_file("runtime_cases.json", r#"[{"id": 1, "category": "bug", "status": "open", "priority": 1}]"#);
+
+        let (code, _, err) = run_cli(&[
+            "run",
+            "--workflow", workflow.to_str().unwrap(),
+            "--cases", cases.to_str().unwrap(),
+        ]);
+        assert_eq!(code, EXIT_RUNTIME);
+        assert!(err.contains("Undefined variable: missing_variable"));
+    }
+
+    #[test]
+    fn test_usage_errors() {
+        assert_eq!(run_cli(&[]).0, EXIT_VALIDATION);
+        assert_eq!(run_cli(&["run", "--workflow", "x.rtx"]).0, EXIT_VALIDATION);
+    }
+
+    #[test]
+    fn test_parse_csv_cases_errors() {
+        assert!(parse_csv_cases("id,category,status\n1,bug,open\n").unwrap_err().contains("priority"));
+        assert!(parse_csv_cases("id,category,status,priority\nx,bug,open,1\n").unwrap_err().contains("line 2"));
+        assert_eq!(
+            parse_csv_cases("id,category,status,priority\n4294967297,bug,open,1\n").unwrap_err(),
+            "line 2: invalid id '4294967297'"
+        );
+        assert!(parse_csv_cases("id,category,status,priority\n1,bug,open,-2147483649\n").unwrap_err().contains("invalid priority"));
+    }
+}
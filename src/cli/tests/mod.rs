@@ -0,0 +1 @@
+pub mod cli_tests;
\ No newline at end of file
@@ -1,32 +1,140 @@
 use crate::{
-    models::case::CaseConfig,
+    models::{ agent::AgentConfig, case::CaseConfig },
     engine::{
-        vm::CoreVM,
+        vm::{ CoreVM, context::VmContext, environment::Environment, stack::VmStack, trace::TraceEvent, rng::Rng, clock::Clock, evaluators::{ ExprEvaluator, WorkflowEvaluator, workflow_evaluator::BATCH_AGGREGATES_VAR, BuiltinFunctions, builtin_functions::FunctionSignature } },
+        error::{ EngineError, CaseError },
+        options::EngineOptions,
+        scorer::Scorer,
+        describe::{ self, WorkflowDescription },
+        validation::{ self, Diagnostic, Severity, ValidationOptions },
+        report::ExecutionReport,
+        program_cache::ProgramCache,
         lang::{
-            ast::{Workflow, Expr, Value, Program, FunctionDef},
-            parser::{WorkflowParser, Rule},
+            ast::{Workflow, Expr, Value, ValueType, Program, FunctionDef, FunctionBody, Phase, PhaseKind},
+            parser::{self, WorkflowParser, Rule},
             builders::builder_workflow,
         },
     },
 };
 use pest::Parser;
+use serde::Serialize;
+use std::{
+    collections::{ BTreeMap, HashMap, HashSet },
+    fs,
+    hash::Hash,
+    ops::RangeInclusive,
+    path::{ Path, PathBuf },
+    sync::{ Arc, Mutex },
+    time::Duration,
+};
 
+/// `CoreEngine` is `Send`, so an engine can be moved into a worker thread.
+/// To run the same setup on several threads, prepare one engine and hand each
+/// thread a `fork()` of it.
 pub struct CoreEngine {
     vm: CoreVM,
+    search_paths: Vec<PathBuf>,
+    /// Recently parsed programs keyed by source text, shared with forks
+    program_cache: Arc<Mutex<ProgramCache>>,
+    /// Workflows kept for execution by name, in registration order
+    registered_workflows: Vec<Workflow>,
 }
 
 impl CoreEngine {
     pub fn new() -> Self {
         let mut vm = CoreVM::new();
-        vm.context.env.enter_scope();
-        Self { vm }
+        vm.context.env = Self::fresh_environment();
+        Self {
+            vm,
+            search_paths: Vec::new(),
+            program_cache: Arc::new(Mutex::new(ProgramCache::new())),
+            registered_workflows: Vec::new(),
+        }
+    }
+
+    /// The built-in scope plus an empty scope for the caller's variables and
+    /// functions, as every new engine starts with.
+    fn fresh_environment() -> Environment {
+        let mut env = CoreVM::builtin_environment();
+        env.enter_scope();
+        env
+    }
+
+    /// Create an engine whose random-dependent features (`sample` phases,
+    /// `shuffle()`, `random_int()`) start from `seed`.
+    pub fn with_seed(seed: u64) -> Self {
+        let mut engine = Self::new();
+        engine.set_random_seed(seed);
+        engine
+    }
+
+    /// Create an engine with a copy of this one's functions, variables,
+    /// registered workflows, agent pool, search paths, options and
+    /// timing/trace settings, but no cases, agent, routing counts, logs or
+    /// results.
+    /// The parsed-program cache is shared, so a source parsed by any fork is
+    /// parsed only once.
+    ///
+    /// ```
+    /// use routix_engine::{ CoreEngine, engine::lang::ast::Value };
+    ///
+    /// let mut template = CoreEngine::new();
+    /// template.set_variable("threshold", Value::Number(3));
+    ///
+    /// let workers: Vec<_> = (0..2)
+    ///     .map(|_| {
+    ///         let mut engine = template.fork();
+    ///         std::thread::spawn(move || engine.evaluate_expression_from_string("threshold * 2"))
+    ///     })
+    ///     .collect();
+    /// for worker in workers {
+    ///     assert!(worker.join().unwrap().is_ok());
+    /// }
+    /// ```
+    pub fn fork(&self) -> Self {
+        let context = &self.vm.context;
+        let mut stack = VmStack::default();
+        stack.agents = context.stack.agents.clone();
+        let mut forked = VmContext::new(stack, context.env.clone());
+        forked.timing = context.timing;
+        forked.options = context.options.clone();
+        forked.rng = context.rng.clone();
+        forked.clock = context.clock;
+        forked.priority_weights = context.priority_weights.clone();
+        forked.memoized = context.memoized.clone();
+        forked.globals = context.globals.clone();
+        forked.trace = context.trace.as_ref().map(|_| Vec::new());
+
+        Self {
+            vm: CoreVM { context: forked },
+            search_paths: self.search_paths.clone(),
+            program_cache: Arc::clone(&self.program_cache),
+            registered_workflows: self.registered_workflows.clone(),
+        }
+    }
+
+    /// Deep copy of the engine for what-if runs: everything `fork()` copies,
+    /// plus the cases, agent, logs, match-phase routing and the cases routed
+    /// to each pool agent. Changes to the
+    /// copy never reach this engine.
+    pub fn clone_state(&self) -> Self {
+        let mut snapshot = self.fork();
+        let (context, copy) = (&self.vm.context, &mut snapshot.vm.context);
+        copy.stack.agent = context.stack.agent.clone();
+        copy.stack.cases = context.stack.cases.clone();
+        copy.agent_loads = context.agent_loads.clone();
+        copy.logs = context.logs.clone();
+        copy.assignments = context.assignments.clone();
+        copy.routed = context.routed.clone();
+        snapshot
     }
 
     pub fn parse_workflow(&self, source: &str) -> Result<Vec<Workflow>, String> {
         let pairs = WorkflowParser::parse(Rule::program, source)
             .map_err(|e| format!("Parse error: {}", e))?;
         
-        let workflows = builder_workflow::build_workflows(pairs);
+        let workflows = builder_workflow::build_workflows(pairs)
+            .map_err(|e| format!("Parse error at {}:{}: {}", e.line, e.column, e.message))?;
         
         if workflows.is_empty() {
             Err("No workflows found in source".to_string())
@@ -35,20 +143,142 @@ impl CoreEngine {
         }
     }
 
-    pub fn add_case(&mut self, case: CaseConfig) {
+    /// Add a case. Duplicate ids are accepted unless the
+    /// `reject_duplicate_ids` option is set, in which case a collision returns
+    /// `EngineError::DuplicateCase` and nothing is added.
+    pub fn add_case(&mut self, case: CaseConfig) -> Result<(), EngineError> {
+        if self.vm.context.options.reject_duplicate_ids && self.vm.context.stack.contains_id(case.id) {
+            return Err(EngineError::DuplicateCase(case.id));
+        }
         self.vm.add_case(case);
+        Ok(())
     }
 
-    pub fn add_cases(&mut self, cases: Vec<CaseConfig>) {
+    /// Add several cases. With `reject_duplicate_ids`, the whole batch is
+    /// checked first (against loaded cases and itself) so a collision adds none.
+    pub fn add_cases(&mut self, cases: Vec<CaseConfig>) -> Result<(), EngineError> {
+        if self.vm.context.options.reject_duplicate_ids {
+            let mut seen = HashSet::new();
+            for case in &cases {
+                if !seen.insert(case.id) || self.vm.context.stack.contains_id(case.id) {
+                    return Err(EngineError::DuplicateCase(case.id));
+                }
+            }
+        }
         for case in cases {
             self.vm.add_case(case);
         }
+        Ok(())
+    }
+
+    /// Insert `case`, or replace the first loaded case with the same id and
+    /// return the old one. A replacement keeps the old score when the new case
+    /// arrives unscored (score 0); a non-zero score on the new case wins.
+    pub fn add_case_upsert(&mut self, mut case: CaseConfig) -> Option<CaseConfig> {
+        match self.vm.context.stack.case_by_id_mut(case.id) {
+            Some(existing) => {
+                if case.score == 0 {
+                    case.score = existing.score;
+                }
+                Some(std::mem::replace(existing, case))
+            }
+            None => {
+                self.vm.add_case(case);
+                None
+            }
+        }
+    }
+
+    /// Case ids need not be unique. The lookups below act on the first case
+    /// with a matching id.
+    pub fn get_case_by_id(&self, id: i32) -> Option<&CaseConfig> {
+        self.vm.context.stack.case_by_id(id)
+    }
+
+    pub fn get_case_by_id_mut(&mut self, id: i32) -> Option<&mut CaseConfig> {
+        self.vm.context.stack.case_by_id_mut(id)
+    }
+
+    pub fn update_case(&mut self, id: i32, update: impl FnOnce(&mut CaseConfig)) -> Result<(), EngineError> {
+        let case = self
+            .get_case_by_id_mut(id)
+            .ok_or_else(|| EngineError::NotFound(format!("case {}", id)))?;
+        update(case);
+        Ok(())
+    }
+
+    pub fn remove_case(&mut self, id: i32) -> Option<CaseConfig> {
+        self.vm.context.stack.remove_case(id)
+    }
+
+    /// Set one field of the first case with `id`. `category` and `status` take
+    /// strings, `priority` and `score` numbers, and `customer` and `created_at`
+    /// a string or number respectively, or null to clear them. The id itself can't be changed.
+    pub fn set_case_field(&mut self, id: i32, field: &str, value: Value) -> Result<(), String> {
+        let case = self.get_case_by_id_mut(id).ok_or_else(|| format!("Case {} not found", id))?;
+        match (field, value) {
+            ("category", Value::String(s)) => case.category = s,
+            ("status", Value::String(s)) => case.status = s,
+            ("customer", Value::String(s)) => case.customer = Some(s),
+            ("customer", Value::Null) => case.customer = None,
+            ("score", Value::Number(n)) => case.score = n,
+            ("created_at", Value::Number(n)) => case.created_at = Some(n),
+            ("created_at", Value::Null) => case.created_at = None,
+            ("priority", Value::Number(n)) => {
+                case.priority = i32::try_from(n).map_err(|_| format!("Priority {} is out of range", n))?;
+            }
+            ("category" | "status" | "customer" | "score" | "priority" | "created_at", value) => {
+                return Err(format!("Invalid value for case field '{}': {}", field, value));
+            }
+            ("id", _) => return Err("Case field 'id' cannot be changed".to_string()),
+            _ => return Err(format!("Unknown case field '{}'", field)),
+        }
+        Ok(())
+    }
+
+    pub fn set_agent(&mut self, agent: AgentConfig) {
+        self.vm.context.stack.set_agent(agent);
+    }
+
+    pub fn set_agent_from_json(&mut self, json: &str) -> Result<(), String> {
+        let agent: AgentConfig = serde_json::from_str(json)
+            .map_err(|e| format!("Invalid agent JSON: {}", e))?;
+        self.set_agent(agent);
+        Ok(())
+    }
+
+    pub fn get_agent(&self) -> Option<&AgentConfig> {
+        self.vm.context.stack.agent.as_ref()
+    }
+
+    /// Replace the pool `route to least_loaded` picks agents from. Counts of
+    /// cases already routed are kept.
+    pub fn set_agents(&mut self, agents: Vec<AgentConfig>) {
+        self.vm.context.stack.agents = agents;
+    }
+
+    pub fn get_agents(&self) -> &[AgentConfig] {
+        &self.vm.context.stack.agents
+    }
+
+    /// Cases routed to each agent of the pool since the last `reset_cases`,
+    /// by agent id. Agents without any are left out.
+    pub fn agent_assignments(&self) -> &BTreeMap<String, u32> {
+        &self.vm.context.agent_loads
     }
 
     pub fn execute_workflow(&mut self, workflow: &Workflow) -> Result<(), String> {
+        self.vm.context.case_errors.clear();
         self.vm.execute_workflow(workflow)
     }
 
+    /// Like `execute_workflow`, but runs a guarded workflow even when its
+    /// `when` guard is falsy.
+    pub fn force_execute_workflow(&mut self, workflow: &Workflow) -> Result<(), String> {
+        self.vm.context.case_errors.clear();
+        self.vm.force_execute_workflow(workflow)
+    }
+
     pub fn execute_workflow_from_source(&mut self, source: &str) -> Result<(), String> {
         let workflows = self.parse_workflow(source)?;
         
@@ -59,30 +289,312 @@ impl CoreEngine {
         self.execute_workflow(&workflows[0])
     }
 
+    /// Parse `source` (through the program cache), register its functions and
+    /// execute only the workflow called `name`.
+    pub fn execute_workflow_from_source_by_name(&mut self, source: &str, name: &str) -> Result<(), EngineError> {
+        let program = self.parse_program_cached(source, None)?;
+        let workflow = program.workflows
+            .iter()
+            .find(|w| w.name == name)
+            .ok_or_else(|| workflow_not_found(name, &program.workflows))?;
+        self.register_functions(program.functions.clone());
+        self.execute_workflow(workflow).map_err(EngineError::Runtime)
+    }
+
     pub fn execute_workflows(&mut self, workflows: &[Workflow]) -> Result<(), String> {
+        self.vm.context.case_errors.clear();
         for workflow in workflows {
-            self.execute_workflow(workflow)?;
+            self.vm.execute_workflow(workflow)?;
         }
         Ok(())
     }
 
+    /// Execute `workflow` and report how the case stats changed.
+    pub fn execute_workflow_with_stats(&mut self, workflow: &Workflow) -> Result<StatsDelta, EngineError> {
+        self.vm.context.case_errors.clear();
+        self.run_with_stats(workflow)
+    }
+
+    /// Run `workflow` over each batch of cases independently and return the
+    /// processed cases per batch. Every batch runs on a `fork()` of this engine,
+    /// so routing variables never carry over between batches and this engine's
+    /// own cases and variables are left untouched. Each batch draws random
+    /// numbers from its own seed, derived from this engine's seed and the
+    /// batch index, and routes to the same agent pool with fresh counts.
+    pub fn batch_execute(&self, batches: Vec<Vec<CaseConfig>>, workflow: &Workflow) -> Result<Vec<Vec<CaseConfig>>, String> {
+        batches
+            .into_iter()
+            .enumerate()
+            .map(|(index, cases)| {
+                let mut engine = self.fork();
+                engine.vm.context.rng = self.vm.context.rng.derive(index as u64);
+                engine.add_cases(cases).map_err(|e| format!("Batch {}: {}", index, e))?;
+                engine.execute_workflow(workflow).map_err(|e| format!("Batch {}: {}", index, e))?;
+                Ok(engine.vm.context.stack.cases)
+            })
+            .collect()
+    }
+
+    /// Execute `workflows` in order, reporting the stats change of each one and
+    /// of the whole run.
+    pub fn execute_workflows_with_stats(&mut self, workflows: &[Workflow]) -> Result<RunStatsDelta, EngineError> {
+        self.vm.context.case_errors.clear();
+        let (before, before_scores) = (self.get_stats(), self.category_scores());
+
+        let mut per_workflow = Vec::with_capacity(workflows.len());
+        for workflow in workflows {
+            per_workflow.push((workflow.name.clone(), self.run_with_stats(workflow)?));
+        }
+
+        let total = StatsDelta::between(before, self.get_stats(), &before_scores, &self.category_scores());
+        Ok(RunStatsDelta { workflows: per_workflow, total })
+    }
+
+    fn run_with_stats(&mut self, workflow: &Workflow) -> Result<StatsDelta, EngineError> {
+        let (before, before_scores) = (self.get_stats(), self.category_scores());
+        self.vm.execute_workflow(workflow).map_err(EngineError::Runtime)?;
+        Ok(StatsDelta::between(before, self.get_stats(), &before_scores, &self.category_scores()))
+    }
+
+    fn category_scores(&self) -> BTreeMap<String, i64> {
+        let mut scores = BTreeMap::new();
+        for case in self.get_cases() {
+            *scores.entry(case.category.clone()).or_insert(0) += case.score;
+        }
+        scores
+    }
+
     pub fn execute_workflows_from_source(&mut self, source: &str) -> Result<(), String> {
         let workflows = self.parse_workflow(source)?;
         self.execute_workflows(&workflows)
     }
 
+    /// Parse `source`, register its functions and keep its workflows for
+    /// `execute_registered`. A workflow replaces any registered one with the
+    /// same name.
+    pub fn register_workflows_from_source(&mut self, source: &str) -> Result<(), EngineError> {
+        let program = self.parse_program_cached(source, None)?;
+        self.register_functions(program.functions);
+        for workflow in program.workflows {
+            match self.registered_workflows.iter_mut().find(|w| w.name == workflow.name) {
+                Some(existing) => *existing = workflow,
+                None => self.registered_workflows.push(workflow),
+            }
+        }
+        Ok(())
+    }
+
+    /// The version and optional capabilities of this build.
+    pub fn dsl_features(&self) -> DslFeatures {
+        DslFeatures {
+            version: env!("CARGO_PKG_VERSION"),
+            base64: cfg!(feature = "base64"),
+            serde: true,
+            regex: false,
+            floats: false,
+            rayon: false,
+        }
+    }
+
+    /// Freeze the engine into a `Scorer` that runs the registered workflows
+    /// on one case at a time. Cases, logs and results are dropped.
+    pub fn into_scorer(self) -> Scorer {
+        Scorer::new(self.registered_workflows, self.vm.context)
+    }
+
+    /// Names of the registered workflows, in registration order.
+    pub fn list_workflows(&self) -> Vec<String> {
+        self.registered_workflows.iter().map(|w| w.name.clone()).collect()
+    }
+
+    pub fn execute_registered(&mut self, name: &str) -> Result<(), EngineError> {
+        let workflow = self
+            .registered_workflows
+            .iter()
+            .find(|w| w.name == name)
+            .cloned()
+            .ok_or_else(|| workflow_not_found(name, &self.registered_workflows))?;
+        self.execute_workflow(&workflow).map_err(EngineError::Runtime)
+    }
+
+    /// Remove a registered workflow, returning whether it was present. Its
+    /// functions stay registered.
+    pub fn unregister_workflow(&mut self, name: &str) -> bool {
+        let before = self.registered_workflows.len();
+        self.registered_workflows.retain(|w| w.name != name);
+        self.registered_workflows.len() != before
+    }
+
     pub fn parse_program(&self, source: &str) -> Result<Program, String> {
+        self.parse_program_cached(source, None).map_err(|e| e.to_string())
+    }
+
+    pub(crate) fn parse_program_cached(&self, source: &str, path: Option<&Path>) -> Result<Program, EngineError> {
+        if let Some(program) = self.program_cache.lock().unwrap().get(source) {
+            return Ok(program);
+        }
+
         let pairs = WorkflowParser::parse(Rule::program, source)
-            .map_err(|e| format!("Parse error: {}", e))?;
-        
-        let program = builder_workflow::build_program(pairs);
+            .map_err(|e| EngineError::from_pest(&e, path))?;
+        let program = builder_workflow::build_program(pairs).map_err(|e| EngineError::Parse {
+            path: path.map(Path::to_path_buf),
+            line: e.line,
+            column: e.column,
+            message: e.message,
+        })?;
+
+        self.program_cache.lock().unwrap().insert(source, program.clone());
         Ok(program)
     }
 
+    /// Names of the workflows in `source`, in order, from the parse tree alone:
+    /// no AST is built, so this is cheap enough for populating a selector.
+    pub fn workflow_names_from_source(&self, source: &str) -> Result<Vec<String>, String> {
+        let pairs = WorkflowParser::parse(Rule::program, source)
+            .map_err(|e| EngineError::from_pest(&e, None).to_string())?;
+        Ok(pairs
+            .flat_map(|program| program.into_inner())
+            .filter(|pair| pair.as_rule() == Rule::workflow)
+            .filter_map(|workflow| workflow.into_inner().next())
+            .map(|name| name.as_str().to_string())
+            .collect())
+    }
+
+    /// Check `source` without executing it. A syntax error is reported as a
+    /// single error diagnostic; otherwise functions are checked for shadowed
+    /// names and the checks enabled in `options` run.
+    pub fn validate_program(&self, source: &str, options: &ValidationOptions) -> Vec<Diagnostic> {
+        match self.parse_program_cached(source, None) {
+            Ok(program) => validation::validate_program(&program, options),
+            Err(EngineError::Parse { line, column, message, .. }) => vec![Diagnostic {
+                severity: Severity::Error,
+                workflow: None,
+                line: Some(line),
+                column: Some(column),
+                message,
+            }],
+            Err(other) => vec![Diagnostic {
+                severity: Severity::Error,
+                workflow: None,
+                line: None,
+                column: None,
+                message: other.to_string(),
+            }],
+        }
+    }
+
+    /// Summarize `workflow` phase by phase: each rule's condition and action
+    /// as DSL source, and the variables and functions they reference. The
+    /// engine's registered functions and variables are included too.
+    pub fn describe_workflow(&self, workflow: &Workflow) -> WorkflowDescription {
+        let constants = self.dump_variables().iter()
+            .filter_map(|(name, value)| Some((name.clone(), serde_json::Value::try_from(value).ok()?)))
+            .collect();
+        describe::describe_workflow(workflow, self.get_user_function_names(), constants)
+    }
+
+    /// Directories consulted, in order, for relative paths that don't exist
+    /// relative to the working directory.
+    pub fn set_search_paths(&mut self, paths: Vec<PathBuf>) {
+        self.search_paths = paths;
+    }
+
+    pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+        self.search_paths.push(path.into());
+    }
+
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    fn resolve_path(&self, path: &Path) -> PathBuf {
+        if path.is_absolute() || path.exists() {
+            return path.to_path_buf();
+        }
+        self.search_paths
+            .iter()
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.exists())
+            .unwrap_or_else(|| path.to_path_buf())
+    }
+
+    pub fn parse_program_from_file(&self, path: impl AsRef<Path>) -> Result<Program, EngineError> {
+        let resolved = self.resolve_path(path.as_ref());
+        let source = fs::read_to_string(&resolved)
+            .map_err(|source| EngineError::Io { path: resolved.clone(), source })?;
+        self.parse_program_cached(&source, Some(&resolved))
+    }
+
+    /// Register the file's functions and execute its single workflow.
+    pub fn execute_workflow_from_file(&mut self, path: impl AsRef<Path>) -> Result<(), EngineError> {
+        let program = self.parse_program_from_file(path.as_ref())?;
+
+        match program.workflows.len() {
+            0 => Err(EngineError::Runtime(
+                format!("No workflows found in {}", path.as_ref().display())
+            )),
+            1 => {
+                self.register_functions(program.functions);
+                self.execute_workflow(&program.workflows[0]).map_err(EngineError::Runtime)
+            }
+            _ => Err(EngineError::Runtime(
+                format!("Multiple workflows found in {}. Use parse_program_from_file() and execute_program().", path.as_ref().display())
+            )),
+        }
+    }
+
+    /// String-error counterpart of `parse_program_from_file`. Read failures are
+    /// reported as "Failed to read ..." and syntax errors as "Parse error ...".
+    pub fn load_program_from_file(&self, path: &Path) -> Result<Program, String> {
+        self.parse_program_from_file(path).map_err(|e| e.to_string())
+    }
+
+    /// Load a program from disk and execute all of its workflows in order.
+    pub fn execute_program_from_file(&mut self, path: &Path) -> Result<(), String> {
+        let program = self.load_program_from_file(path)?;
+        self.execute_program(&program)
+    }
+
     pub fn execute_program(&mut self, program: &Program) -> Result<(), String> {
+        if self.vm.context.options.replace_user_functions {
+            self.vm.clear_user_functions();
+        }
+        self.vm.context.case_errors.clear();
         self.vm.execute_program(program)
     }
 
+    /// Reseed the generator used by `sample` phases, `shuffle()` and
+    /// `random_int()`. Engines start from
+    /// `rng::DEFAULT_SEED`, so runs are reproducible unless this is changed.
+    pub fn set_random_seed(&mut self, seed: u64) {
+        self.vm.context.rng = Rng::new(seed);
+    }
+
+    /// Set the time source for `now()`. Engines use the system clock until a
+    /// `Clock::Fixed` time is set.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.vm.context.clock = clock;
+    }
+
+    /// Set the table used by `priority_weight()`, replacing any previous one.
+    /// Priorities missing from the table weigh their own value.
+    pub fn set_priority_weights(&mut self, weights: HashMap<i32, i64>) {
+        self.vm.context.priority_weights = Arc::new(weights);
+    }
+
+    pub fn set_options(&mut self, options: EngineOptions) {
+        self.vm.context.options = options;
+    }
+
+    pub fn options(&self) -> &EngineOptions {
+        &self.vm.context.options
+    }
+
+    /// Cases skipped during the most recent execution under `OnCaseError::Skip`.
+    pub fn last_case_errors(&self) -> &[CaseError] {
+        &self.vm.context.case_errors
+    }
+
     pub fn execute_program_from_source(&mut self, source: &str) -> Result<(), String> {
         let program = self.parse_program(source)?;
         self.execute_program(&program)
@@ -96,10 +608,111 @@ impl CoreEngine {
         self.vm.register_functions(functions);
     }
 
+    /// Parse a single `function ...` definition and register it. Anything
+    /// else in `source`, such as a second definition or a workflow, is an error.
+    pub fn register_function_from_source(&mut self, source: &str) -> Result<(), String> {
+        let mut pairs = WorkflowParser::parse(Rule::function_source, source)
+            .map_err(|e| EngineError::from_pest(&e, None).to_string())?;
+        let definition = pairs.next().unwrap().into_inner().next().unwrap();
+        let function = builder_workflow::build_function_def(definition)?;
+        self.register_function(function);
+        Ok(())
+    }
+
+    /// Remove the user function `name`, returning whether one was bound.
+    /// Built-ins are never touched, so `remove_function("len")` is `false`;
+    /// use `unregister_function` to have that reported as an error.
+    pub fn remove_function(&mut self, name: &str) -> bool {
+        self.vm.unregister_function(name)
+    }
+
+    /// Remove the user function `name`. Returns `Ok(false)` when no user
+    /// function has that name; built-in functions can't be removed.
+    pub fn unregister_function(&mut self, name: &str) -> Result<bool, EngineError> {
+        if self.vm.unregister_function(name) {
+            return Ok(true);
+        }
+        let builtin = matches!(self.vm.context.env.lookup(name), Some(Value::BuiltinFunction(_)))
+            || BuiltinFunctions::context_function(name).is_some();
+        if builtin {
+            return Err(EngineError::Runtime(format!("cannot unregister built-in function '{}'", name)));
+        }
+        Ok(false)
+    }
+
+    /// Cache the results of user function `name` by argument, as if it were
+    /// declared `function pure`. Only safe for functions whose result depends
+    /// on their arguments alone; calls with list or map arguments still run.
+    pub fn memoize_function(&mut self, name: &str) -> Result<(), EngineError> {
+        if !matches!(self.vm.context.env.lookup(name), Some(Value::UserFunction(_))) {
+            return Err(EngineError::NotFound(format!("user function '{}'", name)));
+        }
+        Arc::make_mut(&mut self.vm.context.memoized).insert(name.to_string());
+        Ok(())
+    }
+
+    /// Remove every user function, keeping built-ins and variables.
+    pub fn clear_user_functions(&mut self) {
+        self.vm.clear_user_functions();
+    }
+
     pub fn get_user_function_names(&self) -> Vec<String> {
         self.vm.get_user_function_names()
     }
 
+    /// Every callable function, sorted by name. Where a name is bound more
+    /// than once, the visible binding is described.
+    pub fn get_function_info(&self) -> Vec<FunctionInfo> {
+        let mut names: Vec<String> = self.vm.get_function_names();
+        for name in BuiltinFunctions::context_function_names() {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        names.iter().filter_map(|name| self.get_function(name)).collect()
+    }
+
+    /// The function `name` would call, or `None` if it is not a function.
+    pub fn get_function(&self, name: &str) -> Option<FunctionInfo> {
+        let info = match self.vm.context.env.lookup(name) {
+            Some(Value::UserFunction(function)) => FunctionInfo {
+                name: name.to_string(),
+                kind: FunctionKind::User,
+                params: function.params.clone(),
+                body: Some(match function.body {
+                    FunctionBody::Expression(_) => BodyKind::Expression,
+                    FunctionBody::Block(_) => BodyKind::Block,
+                }),
+                signature: Some(FunctionSignature::for_user_function(function)),
+            },
+            Some(Value::BuiltinFunction(_)) => match BuiltinFunctions::signature(name) {
+                Some(signature) => FunctionInfo::builtin(signature),
+                None => FunctionInfo {
+                    name: name.to_string(),
+                    kind: FunctionKind::Native,
+                    params: Vec::new(),
+                    body: None,
+                    signature: None,
+                },
+            },
+            Some(_) => return None,
+            None => {
+                BuiltinFunctions::context_function(name)?;
+                FunctionInfo::builtin(BuiltinFunctions::signature(name)?)
+            }
+        };
+        Some(info)
+    }
+
+    /// The definition of user function `name`, if that is what `name` is bound to.
+    pub fn get_user_function_def(&self, name: &str) -> Option<FunctionDef> {
+        match self.vm.context.env.lookup(name)? {
+            Value::UserFunction(function) => Some(function.clone()),
+            _ => None,
+        }
+    }
+
     pub fn get_cases(&self) -> &[CaseConfig] {
         self.vm.get_cases()
     }
@@ -124,46 +737,256 @@ impl CoreEngine {
         self.vm.evaluate_expr(expr)
     }
 
+    pub fn parse_expression(&self, source: &str) -> Result<Expr, String> {
+        parser::parse_expression(source).map_err(|e| format!("Expression parse error: {}", e))
+    }
+
     pub fn evaluate_expression_from_string(&mut self, expr_str: &str) -> Result<Value, String> {
-        let full_source = format!("workflow temp {{ score {{ when {} then score = 1 }} }}", expr_str);
-        let pairs = WorkflowParser::parse(Rule::program, &full_source)
-            .map_err(|e| format!("Expression parse error: {}", e))?;
-        
-        let workflows = builder_workflow::build_workflows(pairs);
-        if workflows.is_empty() || workflows[0].phases.is_empty() {
-            return Err("Failed to parse expression".to_string());
-        }
-        
-        if let crate::engine::lang::ast::Phase::Score(rules) = &workflows[0].phases[0] {
-            if !rules.is_empty() {
-                return self.vm.evaluate_expr(&rules[0].condition);
+        let expr = self.parse_expression(expr_str)?;
+        self.vm.evaluate_expr(&expr)
+    }
+
+    /// Evaluate `source`, returning the value of each sub-expression other
+    /// than literals as `(source, value)`, innermost first and the whole
+    /// expression last. Shows why a condition like `(priority * 2) + 1 > 5`
+    /// came out the way it did. Each source is printed from the parsed
+    /// expression, so it may differ from `source` in spacing and parentheses.
+    pub fn trace_expression(&mut self, source: &str) -> Result<Vec<(String, Value)>, String> {
+        let expr = self.parse_expression(source)?;
+        let mut steps = Vec::new();
+        ExprEvaluator::evaluate_expr_traced(&mut self.vm.context, &expr, &mut steps)?;
+        Ok(steps)
+    }
+
+    /// Evaluate an expression with `case` bound the same way workflow rules see it.
+    pub fn evaluate_expression_for_case(&mut self, expr: &Expr, case: &CaseConfig) -> Result<Value, String> {
+        WorkflowEvaluator::setup_case_context(&mut self.vm.context, case)?;
+        let result = self.vm.evaluate_expr(expr);
+        self.vm.context.env.exit_scope();
+        result
+    }
+
+    /// Cases for which the DSL `predicate` is truthy, in stored order. The
+    /// predicate sees each case the way workflow rules do, and the environment
+    /// is restored after every case. Errors name the case that failed.
+    pub fn query_cases(&mut self, predicate: &str) -> Result<Vec<&CaseConfig>, String> {
+        let indices = self.query_indices(predicate)?;
+        let cases = self.get_cases();
+        Ok(indices.into_iter().map(|i| &cases[i]).collect())
+    }
+
+    /// Owned counterpart of `query_cases`.
+    pub fn query_cases_cloned(&mut self, predicate: &str) -> Result<Vec<CaseConfig>, String> {
+        Ok(self.query_cases(predicate)?.into_iter().cloned().collect())
+    }
+
+    fn query_indices(&mut self, predicate: &str) -> Result<Vec<usize>, String> {
+        let expr = self.parse_expression(predicate)?;
+        let mut indices = Vec::new();
+        for index in 0..self.case_count() {
+            let case = self.get_cases()[index].clone();
+            let value = self
+                .evaluate_expression_for_case(&expr, &case)
+                .map_err(|e| format!("Case {}: {}", case.id, e))?;
+            if ExprEvaluator::is_truthy(&value) {
+                indices.push(index);
             }
         }
-        
-        Err("Failed to extract expression".to_string())
+        Ok(indices)
+    }
+
+    /// Fold the number `expr_over_case` gives for each case into `init`,
+    /// with `op` one of `+`, `max` or `min`. The expression sees each case the
+    /// way workflow rules do; errors name the case that failed.
+    pub fn aggregate(&mut self, expr_over_case: &str, init: i64, op: &str) -> Result<i64, String> {
+        let fold: fn(i64, i64) -> Option<i64> = match op {
+            "+" => i64::checked_add,
+            "max" => |acc, n| Some(acc.max(n)),
+            "min" => |acc, n| Some(acc.min(n)),
+            other => return Err(format!("Unknown aggregate operator '{}': expected +, max or min", other)),
+        };
+        let expr = self.parse_expression(expr_over_case)?;
+        let mut acc = init;
+        for index in 0..self.case_count() {
+            let case = self.get_cases()[index].clone();
+            let n = match self.evaluate_expression_for_case(&expr, &case) {
+                Ok(Value::Number(n)) => n,
+                Ok(other) => return Err(format!("Case {}: expected a number, got {}", case.id, other.value_type())),
+                Err(e) => return Err(format!("Case {}: {}", case.id, e)),
+            };
+            acc = fold(acc, n).ok_or_else(|| format!("Case {}: aggregate overflowed", case.id))?;
+        }
+        Ok(acc)
     }
 
     pub fn get_variable(&self, name: &str) -> Option<Value> {
         self.vm.context.env.lookup(name).cloned()
     }
 
+    /// Signature of the function `name` would call: a user function, or a
+    /// built-in. `None` when `name` is not a function.
+    pub fn describe_function(&self, name: &str) -> Option<FunctionSignature> {
+        self.get_function(name)?.signature
+    }
+
+    pub fn get_variable_type(&self, name: &str) -> Option<ValueType> {
+        self.vm.context.env.lookup(name).map(Value::value_type)
+    }
+
+    /// The variable as a number, or `None` if it is missing or of another type.
+    pub fn get_number(&self, name: &str) -> Option<i64> {
+        self.try_get_number(name).ok()
+    }
+
+    /// Like `get_number`, but says why the value could not be returned.
+    pub fn try_get_number(&self, name: &str) -> Result<i64, EngineError> {
+        match self.lookup_variable(name)? {
+            Value::Number(n) => Ok(*n),
+            other => Err(type_mismatch(name, ValueType::Number, other)),
+        }
+    }
+
+    pub fn get_string(&self, name: &str) -> Option<String> {
+        match self.vm.context.env.lookup(name)? {
+            Value::String(s) => Some(s.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.vm.context.env.lookup(name)? {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn get_list(&self, name: &str) -> Option<Vec<Value>> {
+        match self.vm.context.env.lookup(name)? {
+            Value::List(items) => Some(items.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn get_map(&self, name: &str) -> Option<BTreeMap<String, Value>> {
+        match self.vm.context.env.lookup(name)? {
+            Value::Map(map) => Some(map.clone()),
+            _ => None,
+        }
+    }
+
+    fn lookup_variable(&self, name: &str) -> Result<&Value, EngineError> {
+        self.vm
+            .context
+            .env
+            .lookup(name)
+            .ok_or_else(|| EngineError::NotFound(format!("variable '{}'", name)))
+    }
+
+    /// Bind `name` in the current scope. Same as `set_scoped_variable`; prefer
+    /// that or `set_global_variable` in new code so the lifetime is explicit.
     pub fn set_variable(&mut self, name: impl Into<String>, value: Value) {
+        self.set_scoped_variable(name, value);
+    }
+
+    /// Like `set_variable`, but refuses function values, which could then be
+    /// called from rules. Register functions with `register_function`.
+    pub fn set_data_variable(&mut self, name: impl Into<String>, value: Value) -> Result<(), EngineError> {
+        let name = name.into();
+        if matches!(value, Value::BuiltinFunction(_) | Value::UserFunction(_)) {
+            return Err(EngineError::Runtime(format!(
+                "cannot set '{}' to a {}: register functions with register_function",
+                name,
+                value.value_type()
+            )));
+        }
+        self.set_variable(name, value);
+        Ok(())
+    }
+
+    /// Bind `name` in the current scope. The binding is dropped by the
+    /// matching `exit_scope`.
+    pub fn set_scoped_variable(&mut self, name: impl Into<String>, value: Value) {
+        let name = name.into();
+        self.warn_if_batch_aggregates(&name);
         self.vm.context.env.insert(name, value);
     }
 
-    pub fn get_variable_names(&self) -> Vec<String> {
-        let mut names = Vec::new();
-        for scope in &self.vm.context.env.env {
-            for key in scope.keys() {
-                if !names.contains(key) {
-                    names.push(key.clone());
-                }
+    /// Bind `name` in the outermost scope, alongside the built-ins, whatever
+    /// the current nesting. Inner bindings of the same name still shadow it.
+    pub fn set_global_variable(&mut self, name: impl Into<String>, value: Value) {
+        let name = name.into();
+        self.warn_if_batch_aggregates(&name);
+        self.vm.context.env.insert_global(name, value);
+    }
+
+    /// Rules see the batch aggregates rather than a variable of the same name
+    /// while `batch_aggregates` is on, so binding one is most likely a mistake
+    fn warn_if_batch_aggregates(&self, name: &str) {
+        if self.vm.context.options.batch_aggregates && name == BATCH_AGGREGATES_VAR {
+            tracing::warn!("variable '{}' is hidden by the batch aggregates while phases run", name);
+        }
+    }
+
+    pub fn set_variable_json(&mut self, name: impl Into<String>, json: &serde_json::Value) -> Result<(), EngineError> {
+        let value = Value::try_from(json.clone())?;
+        self.set_variable(name, value);
+        Ok(())
+    }
+
+    /// Serialize every visible non-function variable to a JSON object, for
+    /// `import_variables` to restore later. Fails if a list or map holds a function.
+    pub fn export_variables(&self) -> Result<String, EngineError> {
+        let variables = self
+            .vm
+            .context
+            .env
+            .flatten()
+            .iter()
+            .map(|(name, value)| Ok((name.clone(), serde_json::Value::try_from(value)?)))
+            .collect::<Result<BTreeMap<_, _>, EngineError>>()?;
+        Ok(serde_json::to_string_pretty(&variables)?)
+    }
+
+    /// Insert the variables of a JSON object, as written by `export_variables`,
+    /// into the global scope. Names currently bound to a function or built-in
+    /// are left alone and returned. Nothing is inserted if the input is invalid.
+    pub fn import_variables(&mut self, json: &str) -> Result<Vec<String>, EngineError> {
+        let serde_json::Value::Object(fields) = serde_json::from_str(json)? else {
+            return Err(EngineError::Serialization("Expected a JSON object of variables".to_string()));
+        };
+        let mut variables = Vec::with_capacity(fields.len());
+        for (name, value) in fields {
+            variables.push((name, Value::try_from(value)?));
+        }
+
+        let mut skipped = Vec::new();
+        for (name, value) in variables {
+            let is_function = matches!(
+                self.vm.context.env.lookup(&name),
+                Some(Value::BuiltinFunction(_) | Value::UserFunction(_))
+            );
+            if is_function {
+                skipped.push(name);
+            } else {
+                self.vm.context.env.insert_global(name, value);
             }
         }
+        skipped.sort();
+        Ok(skipped)
+    }
+
+    /// Names of all visible variables, sorted. Functions are not included.
+    pub fn get_variable_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.vm.context.env.flatten().into_keys().collect();
         names.sort();
         names
     }
 
+    /// Every visible variable with its value, with inner scopes shadowing outer ones.
+    pub fn dump_variables(&self) -> BTreeMap<String, Value> {
+        self.vm.context.env.flatten().into_iter().collect()
+    }
+
     pub fn enter_scope(&mut self) {
         self.vm.context.env.enter_scope();
     }
@@ -172,27 +995,168 @@ impl CoreEngine {
         self.vm.context.env.exit_scope();
     }
 
+    /// Every scope, outermost first, functions included. The scopes are
+    /// borrowed, so nothing is copied.
+    pub fn dump_environment(&self) -> Vec<&HashMap<String, Value>> {
+        self.vm.context.env.env.iter().map(|scope| scope.as_ref()).collect()
+    }
+
+    pub fn environment_depth(&self) -> usize {
+        self.vm.context.env.depth()
+    }
+
+    /// Readable listing of every scope; see `Environment::describe`.
+    pub fn describe_environment(&self) -> String {
+        self.vm.context.env.describe()
+    }
+
+    /// Return the engine to its freshly created state: no cases, logs,
+    /// results, variables, user functions or registered workflows. Built-in
+    /// functions stay available.
     pub fn reset(&mut self) {
+        self.reset_cases();
+        self.vm.context.logs.clear();
+        self.reset_variables(false);
+        self.registered_workflows.clear();
+    }
+
+    /// Drop the cases and everything routed or recorded for them, keeping
+    /// variables, functions, logs and registered workflows.
+    pub fn reset_cases(&mut self) {
         self.vm.clear_cases();
-        self.vm.context.env = crate::engine::vm::environment::Environment::new();
+        self.vm.context.assignments.clear();
+        self.vm.context.routed.clear();
+        self.vm.context.agent_loads.clear();
+        self.vm.context.case_errors.clear();
+    }
+
+    /// Drop every variable and close any open scopes. Built-in functions are
+    /// always kept; user functions are kept when `keep_user_functions` is set.
+    pub fn reset_variables(&mut self, keep_user_functions: bool) {
+        let mut env = Self::fresh_environment();
+        if keep_user_functions {
+            // Outer scopes first, so a shadowing definition wins
+            for scope in &self.vm.context.env.env {
+                for (name, value) in scope.iter() {
+                    if matches!(value, Value::UserFunction(_)) {
+                        env.insert(name.clone(), value.clone());
+                    }
+                }
+            }
+        }
+        self.vm.context.env = env;
+        self.vm.context.globals = Arc::default();
+        self.vm.context.memo.clear();
+        if !keep_user_functions {
+            self.vm.context.memoized = Arc::default();
+        }
     }
 
     pub fn get_stats(&self) -> EngineStats {
-        let cases = self.get_cases();
-        let total_score: i64 = cases.iter().map(|c| c.score).sum();
-        let avg_score = if cases.is_empty() { 0.0 } else { total_score as f64 / cases.len() as f64 };
-        
-        let max_score = cases.iter().map(|c| c.score).max().unwrap_or(0);
-        let min_score = cases.iter().map(|c| c.score).min().unwrap_or(0);
-        
-        EngineStats {
-            case_count: cases.len(),
-            total_score,
-            average_score: avg_score,
-            max_score,
-            min_score,
-            variable_count: self.get_variable_names().len(),
+        let cases: Vec<&CaseConfig> = self.get_cases().iter().collect();
+        EngineStats::from_cases(&cases, self.get_variable_names().len())
+    }
+
+    /// Stats per group of cases sharing `key_fn(case)`. Only keys with at least
+    /// one case appear. `variable_count` is the engine-wide count in every group.
+    pub fn get_stats_by<F>(&self, key_fn: F) -> HashMap<String, EngineStats>
+    where
+        F: Fn(&CaseConfig) -> String,
+    {
+        let variable_count = self.get_variable_names().len();
+        self.group_cases_by(key_fn)
+            .into_iter()
+            .map(|(key, cases)| (key, EngineStats::from_cases(&cases, variable_count)))
+            .collect()
+    }
+
+    /// Median of the current case scores, or `None` without cases. An even
+    /// number of cases averages the two middle scores.
+    pub fn median_score(&self) -> Option<f64> {
+        self.percentile(50.0)
+    }
+
+    /// The `p`th percentile (0–100) of the current case scores, interpolating
+    /// linearly between the closest ranks. `None` without cases or for a `p`
+    /// outside 0–100.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if !(0.0..=100.0).contains(&p) || self.get_cases().is_empty() {
+            return None;
+        }
+        let mut scores: Vec<i64> = self.get_cases().iter().map(|c| c.score).collect();
+        scores.sort_unstable();
+
+        let rank = p / 100.0 * (scores.len() - 1) as f64;
+        let lower = scores[rank.floor() as usize] as f64;
+        let upper = scores[rank.ceil() as usize] as f64;
+        Some(lower + (upper - lower) * rank.fract())
+    }
+
+    /// Count scores in buckets of `bucket_size`, aligned to multiples of it
+    /// (so `-10..=-1`, `0..=9`, ... for size 10), lowest first. Only buckets
+    /// holding a score are listed, so no cases gives an empty histogram, and
+    /// buckets reaching past `i64::MIN` or `i64::MAX` are cut there. A
+    /// `bucket_size` that isn't positive is an error.
+    pub fn histogram(&self, bucket_size: i64) -> Result<Vec<(RangeInclusive<i64>, usize)>, String> {
+        if bucket_size <= 0 {
+            return Err(format!("histogram bucket size must be positive, got {}", bucket_size));
+        }
+        let mut counts: BTreeMap<i64, usize> = BTreeMap::new();
+        for case in self.get_cases() {
+            *counts.entry(case.score.div_euclid(bucket_size)).or_default() += 1;
         }
+        let clamp = |n: i128| n.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64;
+        Ok(counts
+            .into_iter()
+            .map(|(bucket, count)| {
+                let start = i128::from(bucket) * i128::from(bucket_size);
+                (clamp(start)..=clamp(start + i128::from(bucket_size) - 1), count)
+            })
+            .collect())
+    }
+
+    /// Bucket cases by `key_fn`. Each bucket keeps the stored case order.
+    pub fn group_cases_by<K, F>(&self, key_fn: F) -> HashMap<K, Vec<&CaseConfig>>
+    where
+        K: Eq + Hash,
+        F: Fn(&CaseConfig) -> K,
+    {
+        let mut groups: HashMap<K, Vec<&CaseConfig>> = HashMap::new();
+        for case in self.get_cases() {
+            groups.entry(key_fn(case)).or_default().push(case);
+        }
+        groups
+    }
+
+    pub fn group_by_category(&self) -> HashMap<String, Vec<&CaseConfig>> {
+        self.group_cases_by(|case| case.category.clone())
+    }
+
+    pub fn group_by_status(&self) -> HashMap<String, Vec<&CaseConfig>> {
+        self.group_cases_by(|case| case.status.clone())
+    }
+
+    /// Bucket cases by score bands of `band_size`, keyed by each band's lowest
+    /// score. Bands align to multiples of `band_size` like `histogram`, so -5
+    /// falls in the band keyed -10 for a size of 10, and a band starting
+    /// below `i64::MIN` is keyed `i64::MIN`. A `band_size` that isn't positive
+    /// is an error.
+    pub fn group_by_score_band(&self, band_size: i64) -> Result<HashMap<i64, Vec<&CaseConfig>>, String> {
+        if band_size <= 0 {
+            return Err(format!("score band size must be positive, got {}", band_size));
+        }
+        Ok(self.group_cases_by(|case| {
+            let start = i128::from(case.score.div_euclid(band_size)) * i128::from(band_size);
+            start.clamp(i128::from(i64::MIN), i128::from(i64::MAX)) as i64
+        }))
+    }
+
+    pub fn get_stats_by_category(&self) -> HashMap<String, EngineStats> {
+        self.get_stats_by(|case| case.category.clone())
+    }
+
+    pub fn get_stats_by_status(&self) -> HashMap<String, EngineStats> {
+        self.get_stats_by(|case| case.status.clone())
     }
 
     pub fn score_cases<F>(&mut self, scoring_fn: F) -> Result<(), String>
@@ -211,6 +1175,29 @@ impl CoreEngine {
         Ok(())
     }
 
+    /// Set each case's score to the DSL `expression`, evaluated in the case's
+    /// context. The expression is parsed once. Every case must produce a
+    /// number; on the first failure no score is changed.
+    pub fn score_cases_with_expression(&mut self, expression: &str) -> Result<(), String> {
+        let expr = self.parse_expression(expression)?;
+        let mut scores = Vec::with_capacity(self.case_count());
+        for index in 0..self.case_count() {
+            let case = self.get_cases()[index].clone();
+            match self.evaluate_expression_for_case(&expr, &case) {
+                Ok(Value::Number(score)) => scores.push(score),
+                Ok(other) => {
+                    return Err(format!("Case {}: score expression returned {}, expected a number", case.id, other));
+                }
+                Err(e) => return Err(format!("Case {}: {}", case.id, e)),
+            }
+        }
+
+        for (case, score) in self.vm.context.stack.cases.iter_mut().zip(scores) {
+            case.score = score;
+        }
+        Ok(())
+    }
+
     pub fn filter_cases<F>(&mut self, predicate: F)
     where
         F: Fn(&CaseConfig) -> bool,
@@ -218,6 +1205,29 @@ impl CoreEngine {
         self.vm.context.stack.cases.retain(predicate);
     }
 
+    /// Keep only cases scoring within `min..=max`.
+    pub fn retain_score_range(&mut self, min: i64, max: i64) {
+        self.filter_cases(|case| (min..=max).contains(&case.score));
+    }
+
+    /// Rescale every score proportionally so the highest becomes `to_max`,
+    /// rounding to the nearest integer (halves away from zero). Scores are
+    /// left unchanged when the highest is zero or negative.
+    pub fn normalize_scores(&mut self, to_max: i64) {
+        let Some(max) = self.vm.context.stack.cases.iter().map(|case| case.score).max() else {
+            return;
+        };
+        if max <= 0 {
+            return;
+        }
+        let (to_max, max) = (to_max as i128, max as i128);
+        for case in &mut self.vm.context.stack.cases {
+            let scaled = case.score as i128 * to_max;
+            let rounded = (scaled + scaled.signum() * max / 2) / max;
+            case.score = rounded.clamp(i64::MIN as i128, i64::MAX as i128) as i64;
+        }
+    }
+
     pub fn sort_cases_by<F, K>(&mut self, key_fn: F)
     where
         F: Fn(&CaseConfig) -> K,
@@ -227,21 +1237,62 @@ impl CoreEngine {
     }
 
     pub fn sort_cases_by_score_desc(&mut self) {
-        self.vm.context.stack.cases.sort_by(|a, b| b.score.cmp(&a.score));
+        self.vm.context.stack.cases.sort_by_key(|c| std::cmp::Reverse(c.score));
     }
 
     pub fn sort_cases_by_score_asc(&mut self) {
-        self.vm.context.stack.cases.sort_by(|a, b| a.score.cmp(&b.score));
+        self.vm.context.stack.cases.sort_by_key(|c| c.score);
     }
 
+    /// Cases scoring strictly above `threshold`; a case scoring exactly
+    /// `threshold` is left out. See `get_cases_with_score_at_least`.
     pub fn get_high_score_cases(&self, threshold: i64) -> Vec<&CaseConfig> {
         self.get_cases().iter().filter(|c| c.score > threshold).collect()
     }
 
+    /// Cases scoring strictly below `threshold`; a case scoring exactly
+    /// `threshold` is left out. See `get_cases_with_score_at_most`.
     pub fn get_low_score_cases(&self, threshold: i64) -> Vec<&CaseConfig> {
         self.get_cases().iter().filter(|c| c.score < threshold).collect()
     }
 
+    /// Cases scoring `min` or more, in stored order.
+    pub fn get_cases_with_score_at_least(&self, min: i64) -> Vec<&CaseConfig> {
+        self.get_cases().iter().filter(|c| c.score >= min).collect()
+    }
+
+    /// Cases scoring `max` or less, in stored order.
+    pub fn get_cases_with_score_at_most(&self, max: i64) -> Vec<&CaseConfig> {
+        self.get_cases().iter().filter(|c| c.score <= max).collect()
+    }
+
+    /// The `n` highest-scoring cases, best first, ties broken by ascending id.
+    /// The stored case order is left as is.
+    pub fn top_n_cases(&self, n: usize) -> Vec<&CaseConfig> {
+        self.top_n_by(n, |case| case.score)
+    }
+
+    /// The `n` lowest-scoring cases, lowest first, ties broken by ascending id.
+    pub fn bottom_n_cases(&self, n: usize) -> Vec<&CaseConfig> {
+        let mut cases: Vec<&CaseConfig> = self.get_cases().iter().collect();
+        cases.sort_by_key(|case| (case.score, case.id));
+        cases.truncate(n);
+        cases
+    }
+
+    /// The `n` cases with the largest `key_fn` values, largest first, ties
+    /// broken by ascending id.
+    pub fn top_n_by<K, F>(&self, n: usize, key_fn: F) -> Vec<&CaseConfig>
+    where
+        K: Ord,
+        F: Fn(&CaseConfig) -> K,
+    {
+        let mut cases: Vec<&CaseConfig> = self.get_cases().iter().collect();
+        cases.sort_by(|a, b| key_fn(b).cmp(&key_fn(a)).then(a.id.cmp(&b.id)));
+        cases.truncate(n);
+        cases
+    }
+
     pub fn get_cases_by_category(&self, category: &str) -> Vec<&CaseConfig> {
         self.get_cases().iter().filter(|c| c.category == category).collect()
     }
@@ -253,6 +1304,152 @@ impl CoreEngine {
     pub fn run(&mut self) -> Result<Vec<CaseConfig>, String> {
         Ok(self.get_cases_copy())
     }
+
+    pub fn set_timing(&mut self, enabled: bool) {
+        self.vm.context.timing = enabled;
+    }
+
+    pub fn phase_timings(&self) -> Vec<(PhaseKind, Duration)> {
+        self.vm.context.phase_timings.clone()
+    }
+
+    pub fn clear_phase_timings(&mut self) {
+        self.vm.context.phase_timings.clear();
+    }
+
+    /// Start or stop recording rule evaluations. Enabling clears any previous trace.
+    pub fn set_trace(&mut self, enabled: bool) {
+        self.vm.context.trace = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    pub fn trace(&self) -> &[TraceEvent] {
+        self.vm.context.trace.as_deref().unwrap_or(&[])
+    }
+
+    /// Describe how `source` treats the case with `id`, one line per rule that
+    /// fired. The program runs over the whole batch on a copy of the engine,
+    /// so batch-wide phases behave as in a real run while the engine's own
+    /// cases and results are left untouched.
+    pub fn explain_case(&self, id: i32, source: &str) -> Result<Vec<String>, String> {
+        if !self.get_cases().iter().any(|case| case.id == id) {
+            return Err(format!("Case {} not found", id));
+        }
+
+        let mut engine = self.clone_state();
+        engine.set_trace(true);
+        engine.execute_program_from_source(source)?;
+
+        let lines = engine
+            .trace()
+            .iter()
+            .filter(|event| event.case_id == id)
+            .filter_map(|event| match (event.phase, event.fired) {
+                (PhaseKind::Filter, true) => None,
+                (PhaseKind::Filter, false) => Some(format!("filtered out: {}", event.rule)),
+                (_, true) => Some(format!("rule fired: {} → score now {}", event.rule, event.score)),
+                (_, false) => None,
+            })
+            .collect();
+        Ok(lines)
+    }
+
+    /// Run `source` over the loaded cases with tracing on and count the cases
+    /// each score and match rule fired for, as `(rule_index, match_count)`.
+    /// Rules are numbered through the program in order, with a score phase's
+    /// `otherwise` after its rules, so a count of 0 marks a rule that never
+    /// fired. The run updates the engine like `execute_program_from_source`.
+    pub fn rules_coverage(&mut self, source: &str) -> Result<Vec<(usize, usize)>, String> {
+        let program = self.parse_program(source)?;
+
+        // Trace events name a rule by workflow, phase kind, position and text
+        let mut rules = Vec::new();
+        for workflow in &program.workflows {
+            for phase in &workflow.phases {
+                match phase {
+                    Phase::Score { rules: score_rules, otherwise } => {
+                        for (position, rule) in score_rules.iter().enumerate() {
+                            rules.push((&workflow.name, PhaseKind::Score, position, rule.to_string()));
+                        }
+                        if let Some(action) = otherwise {
+                            let rule = format!("otherwise then {}", action);
+                            rules.push((&workflow.name, PhaseKind::Score, score_rules.len(), rule));
+                        }
+                    }
+                    Phase::Match(match_rules, _) => {
+                        for (position, rule) in match_rules.iter().enumerate() {
+                            rules.push((&workflow.name, PhaseKind::Match, position, rule.to_string()));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let previous = self.vm.context.trace.replace(Vec::new());
+        let result = self.execute_program(&program);
+        let events = std::mem::replace(&mut self.vm.context.trace, previous).unwrap_or_default();
+        result?;
+
+        let mut fired: HashMap<(&str, PhaseKind, usize, &str), usize> = HashMap::new();
+        for event in events.iter().filter(|event| event.fired) {
+            let key = (event.workflow.as_str(), event.phase, event.rule_index, event.rule.as_str());
+            *fired.entry(key).or_default() += 1;
+        }
+        let coverage = rules
+            .iter()
+            .enumerate()
+            .map(|(index, (workflow, phase, position, rule))| {
+                let key = (workflow.as_str(), *phase, *position, rule.as_str());
+                (index, fired.get(&key).copied().unwrap_or(0))
+            })
+            .collect();
+
+        // Keep the events when the caller was already tracing
+        if let Some(trace) = &mut self.vm.context.trace {
+            trace.extend(events);
+        }
+        Ok(coverage)
+    }
+
+    /// Cases routed by match phases, keyed by target name. Each case is a
+    /// snapshot taken when it was assigned, so later phases don't change it.
+    pub fn get_assignments(&self) -> HashMap<String, Vec<CaseConfig>> {
+        self.vm.context.routed
+            .iter()
+            .map(|(name, cases)| (name.clone(), cases.clone()))
+            .collect()
+    }
+
+    /// The cases routed to a single target, in assignment order.
+    pub fn get_assignment(&self, name: &str) -> Option<&[CaseConfig]> {
+        self.vm.context.routed.get(name).map(Vec::as_slice)
+    }
+
+    pub fn get_logs(&self) -> &[String] {
+        &self.vm.context.logs
+    }
+
+    pub fn build_report(&self) -> ExecutionReport {
+        let variables = self
+            .dump_variables()
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, serde_json::Value::try_from(&value).ok()?)))
+            .collect();
+
+        ExecutionReport {
+            cases: self.get_cases_copy(),
+            assignments: self.vm.context.assignments.clone(),
+            logs: self.vm.context.logs.clone(),
+            stats: self.get_stats(),
+            variables,
+            trace: self.vm.context.trace.clone(),
+            case_errors: self.vm.context.case_errors.clone(),
+        }
+    }
+
+    pub fn export_results_json(&self) -> Result<String, EngineError> {
+        self.build_report().to_json()
+    }
 }
 
 impl Default for CoreEngine {
@@ -261,7 +1458,71 @@ impl Default for CoreEngine {
     }
 }
 
-#[derive(Debug, Clone)]
+fn workflow_not_found(name: &str, available: &[Workflow]) -> EngineError {
+    let names: Vec<&str> = available.iter().map(|w| w.name.as_str()).collect();
+    EngineError::NotFound(format!("workflow '{}' (available: {})", name, names.join(", ")))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    Builtin,
+    User,
+    /// A Rust function bound as a variable, without a registered signature
+    Native,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyKind {
+    Expression,
+    Block,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionInfo {
+    pub name: String,
+    pub kind: FunctionKind,
+    /// Parameter names; empty for native functions, whose parameters are unknown
+    pub params: Vec<String>,
+    /// How a user function's body is written
+    pub body: Option<BodyKind>,
+    pub signature: Option<FunctionSignature>,
+}
+
+impl FunctionInfo {
+    fn builtin(signature: FunctionSignature) -> Self {
+        FunctionInfo {
+            name: signature.name.clone(),
+            kind: FunctionKind::Builtin,
+            params: signature.params.iter().map(|p| p.name.clone()).collect(),
+            body: None,
+            signature: Some(signature),
+        }
+    }
+}
+
+fn type_mismatch(name: &str, expected: ValueType, found: &Value) -> EngineError {
+    EngineError::TypeMismatch { name: name.to_string(), expected, found: found.value_type() }
+}
+
+/// The engine version and which optional capabilities this build has, so a
+/// host can check before relying on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct DslFeatures {
+    /// Crate version, e.g. "0.1.0"
+    pub version: &'static str,
+    /// `base64_encode`/`base64_decode`, behind the `base64` cargo feature
+    pub base64: bool,
+    /// JSON import and export of cases, variables and results
+    pub serde: bool,
+    /// Regular expression matching; not available yet
+    pub regex: bool,
+    /// Fractional numbers; numbers are 64-bit integers
+    pub floats: bool,
+    /// Parallel case evaluation; not available yet
+    pub rayon: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
 pub struct EngineStats {
     pub case_count: usize,
     pub total_score: i64,
@@ -270,3 +1531,68 @@ pub struct EngineStats {
     pub min_score: i64,
     pub variable_count: usize,
 }
+
+/// How case stats changed across a workflow run.
+#[derive(Debug, Clone, Serialize)]
+pub struct StatsDelta {
+    pub before: EngineStats,
+    pub after: EngineStats,
+    /// Cases dropped by the run, e.g. by filter phases
+    pub cases_removed: usize,
+    /// Change in the total score of all cases
+    pub score_change: i64,
+    /// Change in total score per category. Categories present on either side
+    /// are listed; a category whose cases were all removed loses its whole total.
+    pub category_score_change: BTreeMap<String, i64>,
+}
+
+impl StatsDelta {
+    fn between(
+        before: EngineStats,
+        after: EngineStats,
+        before_scores: &BTreeMap<String, i64>,
+        after_scores: &BTreeMap<String, i64>
+    ) -> Self {
+        let mut category_score_change = BTreeMap::new();
+        for category in before_scores.keys().chain(after_scores.keys()) {
+            let old = before_scores.get(category).copied().unwrap_or(0);
+            let new = after_scores.get(category).copied().unwrap_or(0);
+            category_score_change.insert(category.clone(), new - old);
+        }
+
+        StatsDelta {
+            cases_removed: before.case_count.saturating_sub(after.case_count),
+            score_change: after.total_score - before.total_score,
+            category_score_change,
+            before,
+            after,
+        }
+    }
+}
+
+/// Stats changes for a multi-workflow run: one delta per workflow, in
+/// execution order, plus the delta across the whole run.
+#[derive(Debug, Clone, Serialize)]
+pub struct RunStatsDelta {
+    pub workflows: Vec<(String, StatsDelta)>,
+    pub total: StatsDelta,
+}
+
+impl EngineStats {
+    fn from_cases(cases: &[&CaseConfig], variable_count: usize) -> Self {
+        let total_score: i64 = cases.iter().map(|c| c.score).sum();
+        let avg_score = if cases.is_empty() { 0.0 } else { total_score as f64 / cases.len() as f64 };
+
+        let max_score = cases.iter().map(|c| c.score).max().unwrap_or(0);
+        let min_score = cases.iter().map(|c| c.score).min().unwrap_or(0);
+
+        EngineStats {
+            case_count: cases.len(),
+            total_score,
+            average_score: avg_score,
+            max_score,
+            min_score,
+            variable_count,
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+use crate::engine::{
+    lang::ast::{ Action, Expr, GlobalUpdate, MatchAction, MatchStrategy, Phase, PhaseKind, RankMode, SampleRule, Workflow },
+    validation::References,
+};
+
+/// A structured summary of a workflow, built by `CoreEngine::describe_workflow`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WorkflowDescription {
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guard: Option<String>,
+    pub phases: Vec<PhaseDescription>,
+    /// Every variable read anywhere in the workflow, sorted
+    pub variables: Vec<String>,
+    /// Every function called anywhere in the workflow, sorted
+    pub functions: Vec<String>,
+    /// User functions registered on the engine
+    pub defined_functions: Vec<String>,
+    /// Engine variables other than functions
+    pub constants: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PhaseDescription {
+    pub kind: PhaseKind,
+    /// The settings of a phase without rules, e.g. `by score desc` for a sort
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub settings: Option<String>,
+    pub rule_count: usize,
+    pub rules: Vec<RuleDescription>,
+    pub variables: Vec<String>,
+    pub functions: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleDescription {
+    /// `otherwise` for the fallback action of a score phase
+    pub condition: String,
+    pub action: String,
+    pub variables: Vec<String>,
+    pub functions: Vec<String>,
+}
+
+pub(crate) fn describe_workflow(
+    workflow: &Workflow,
+    defined_functions: Vec<String>,
+    constants: BTreeMap<String, serde_json::Value>,
+) -> WorkflowDescription {
+    let mut references = References::default();
+    if let Some(guard) = &workflow.guard {
+        references.collect(guard);
+    }
+    let phases = workflow.phases.iter()
+        .map(|phase| {
+            let (description, phase_references) = describe_phase(phase);
+            references.extend(&phase_references);
+            description
+        })
+        .collect();
+    WorkflowDescription {
+        name: workflow.name.clone(),
+        guard: workflow.guard.as_ref().map(Expr::to_string),
+        phases,
+        variables: references.variables.into_iter().collect(),
+        functions: references.functions.into_iter().collect(),
+        defined_functions,
+        constants,
+    }
+}
+
+fn describe_phase(phase: &Phase) -> (PhaseDescription, References) {
+    let mut references = References::default();
+    let mut rules = Vec::new();
+    let mut settings = None;
+    match phase {
+        Phase::Score { rules: score_rules, otherwise } => {
+            for rule in score_rules {
+                rules.push(describe_rule(rule.condition.to_string(), rule.action.to_string(), Some(&rule.condition), action_references(&rule.action)));
+            }
+            if let Some(action) = otherwise {
+                rules.push(describe_rule("otherwise".to_string(), action.to_string(), None, action_references(action)));
+            }
+        }
+        Phase::Match(match_rules, strategy) => {
+            if *strategy == MatchStrategy::RoundRobin {
+                settings = Some("round_robin".to_string());
+            }
+            for rule in match_rules {
+                rules.push(describe_rule(rule.condition.to_string(), rule.action.to_string(), Some(&rule.condition), match_action_references(&rule.action)));
+            }
+        }
+        Phase::Filter(filter) => {
+            rules.push(describe_rule(filter.condition.to_string(), "keep".to_string(), Some(&filter.condition), References::default()));
+        }
+        Phase::Sort(sort) => {
+            references.collect(&sort.key);
+            settings = Some(format!("by {} {}", sort.key, sort.order));
+        }
+        Phase::Group(group) => {
+            for key in &group.keys {
+                references.collect(key);
+            }
+            let keys: Vec<String> = group.keys.iter().map(Expr::to_string).collect();
+            settings = Some(format!("by {}", keys.join(", ")));
+        }
+        Phase::Sample(SampleRule::Count(count)) => settings = Some(count.to_string()),
+        Phase::Sample(SampleRule::Percent(percent)) => settings = Some(format!("{} percent", percent)),
+        Phase::Rank(rank) => {
+            references.collect(&rank.key);
+            let mut text = format!("by {} {}", rank.key, rank.order);
+            if rank.mode == RankMode::Dense {
+                text.push_str(" dense");
+            }
+            text.push_str(" into rank");
+            if rank.sorted {
+                text.push_str(" sorted");
+            }
+            settings = Some(text);
+        }
+        Phase::Normalize(normalize) => settings = Some(format!("to {}..{}", normalize.low, normalize.high)),
+        Phase::Combine(expr) => {
+            references.collect(expr);
+            settings = Some(format!("score = {}", expr));
+        }
+    }
+    for rule in &rules {
+        references.variables.extend(rule.variables.iter().cloned());
+        references.functions.extend(rule.functions.iter().cloned());
+    }
+    let description = PhaseDescription {
+        kind: phase.kind(),
+        settings,
+        rule_count: rules.len(),
+        rules,
+        variables: references.variables.iter().cloned().collect(),
+        functions: references.functions.iter().cloned().collect(),
+    };
+    (description, references)
+}
+
+fn describe_rule(condition: String, action: String, condition_expr: Option<&Expr>, mut references: References) -> RuleDescription {
+    if let Some(expr) = condition_expr {
+        references.collect(expr);
+    }
+    RuleDescription {
+        condition,
+        action,
+        variables: references.variables.into_iter().collect(),
+        functions: references.functions.into_iter().collect(),
+    }
+}
+
+fn action_references(action: &Action) -> References {
+    let mut references = References::default();
+    match action {
+        Action::AssignScore(expr) | Action::Assert(expr) | Action::AssignSubScore(_, expr) => references.collect(expr),
+        Action::UpdateGlobal(update) => global_update_references(update, &mut references),
+        Action::Log(_) | Action::Assign(_) => {}
+    }
+    references
+}
+
+fn match_action_references(action: &MatchAction) -> References {
+    let mut references = References::default();
+    match action {
+        MatchAction::AssignToDynamic(expr) | MatchAction::RouteToLeastLoaded(Some(expr)) => references.collect(expr),
+        MatchAction::UpdateGlobal(update) => global_update_references(update, &mut references),
+        MatchAction::AssignTo(_) | MatchAction::RouteToLeastLoaded(None) => {}
+    }
+    references
+}
+
+fn global_update_references(update: &GlobalUpdate, references: &mut References) {
+    match update {
+        GlobalUpdate::Set(_, expr) => references.collect(expr),
+        // An increment reads the global before writing it
+        GlobalUpdate::Increment(name) => {
+            references.variables.insert(name.clone());
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::{ fmt, path::{ Path, PathBuf } };
+use serde::Serialize;
+use crate::engine::lang::{ ast::{ PhaseKind, ValueType }, parser::Rule };
+
+#[derive(Debug)]
+pub enum EngineError {
+    Parse {
+        path: Option<PathBuf>,
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Runtime(String),
+    Serialization(String),
+    NotFound(String),
+    DuplicateCase(i32),
+    TypeMismatch {
+        name: String,
+        expected: ValueType,
+        found: ValueType,
+    },
+}
+
+impl EngineError {
+    pub fn from_pest(err: &pest::error::Error<Rule>, path: Option<&Path>) -> Self {
+        let (line, column) = match err.line_col {
+            pest::error::LineColLocation::Pos(pos) => pos,
+            pest::error::LineColLocation::Span(start, _) => start,
+        };
+        EngineError::Parse {
+            path: path.map(Path::to_path_buf),
+            line,
+            column,
+            message: err.variant.message().into_owned(),
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Parse { path: Some(path), line, column, message } => {
+                write!(f, "Parse error in {} at {}:{}: {}", path.display(), line, column, message)
+            }
+            EngineError::Parse { path: None, line, column, message } => {
+                write!(f, "Parse error at {}:{}: {}", line, column, message)
+            }
+            EngineError::Io { path, source } => {
+                write!(f, "Failed to read {}: {}", path.display(), source)
+            }
+            EngineError::Runtime(msg) => write!(f, "Runtime error: {}", msg),
+            EngineError::Serialization(msg) => write!(f, "Serialization error: {}", msg),
+            EngineError::NotFound(what) => write!(f, "Not found: {}", what),
+            EngineError::DuplicateCase(id) => write!(f, "Duplicate case id: {}", id),
+            EngineError::TypeMismatch { name, expected, found } => {
+                write!(f, "Type mismatch for '{}': expected {}, found {}", name, expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for EngineError {
+    fn from(err: serde_json::Error) -> Self {
+        EngineError::Serialization(err.to_string())
+    }
+}
+
+/// A per-case failure recorded instead of aborting when running with
+/// `OnCaseError::Skip`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CaseError {
+    pub case_id: i32,
+    pub phase: PhaseKind,
+    pub error: String,
+}
@@ -1,9 +1,20 @@
-use std::collections::HashMap;
+use std::{ collections::BTreeMap, fmt };
+use serde::Serialize;
 
 #[derive(Debug, Clone)]
 pub struct Program {
     pub functions: Vec<FunctionDef>,
     pub workflows: Vec<Workflow>,
+    /// `global name = expr` declarations, in order
+    pub globals: Vec<GlobalDecl>,
+}
+
+/// A variable that score and match rules may update, bound in the global
+/// scope so it outlives each case and is seen by later workflows
+#[derive(Debug, Clone)]
+pub struct GlobalDecl {
+    pub name: String,
+    pub value: Expr,
 }
 
 #[derive(Debug, Clone)]
@@ -11,6 +22,8 @@ pub struct FunctionDef {
     pub name: String,
     pub params: Vec<String>,
     pub body: FunctionBody,
+    /// Declared `function pure ...`: results are cached by argument
+    pub pure: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -29,27 +42,79 @@ pub enum Statement {
         name: String,
         value: Expr,
     },
+    /// `object.property = value`, where `object` holds a map
+    MemberAssign {
+        object: String,
+        property: String,
+        value: Expr,
+    },
     If {
         condition: Expr,
         then_body: Vec<Statement>,
         else_body: Option<Vec<Statement>>,
     },
     Return(Expr),
+    /// Fails the call with "assertion failed" when the condition is falsy
+    Assert(Expr),
     Expression(Expr),
 }
 
 #[derive(Debug, Clone)]
 pub struct Workflow {
     pub name: String,
+    /// `workflow name when <expr> { ... }`: the workflow only runs when the
+    /// expression is truthy in the global environment
+    pub guard: Option<Expr>,
     pub phases: Vec<Phase>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Phase {
-    Score(Vec<Rule>),
-    Match(Vec<MatchRule>),
+    Score {
+        rules: Vec<Rule>,
+        /// Run when none of the rules fired for a case
+        otherwise: Option<Action>,
+    },
+    Match(Vec<MatchRule>, MatchStrategy),
     Filter(FilterRule),
     Sort(SortRule),
+    Group(GroupRule),
+    Sample(SampleRule),
+    Rank(RankRule),
+    Normalize(NormalizeRule),
+    /// Sets every case's score to the expression, typically a weighted sum
+    /// of its sub-scores
+    Combine(Expr),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PhaseKind {
+    Score,
+    Match,
+    Filter,
+    Sort,
+    Group,
+    Sample,
+    Rank,
+    Normalize,
+    Combine,
+}
+
+impl Phase {
+    pub fn kind(&self) -> PhaseKind {
+        match self {
+            Phase::Score { .. } => PhaseKind::Score,
+            Phase::Match(..) => PhaseKind::Match,
+            Phase::Filter(_) => PhaseKind::Filter,
+            Phase::Sort(_) => PhaseKind::Sort,
+            Phase::Group(_) => PhaseKind::Group,
+            Phase::Sample(_) => PhaseKind::Sample,
+            Phase::Rank(_) => PhaseKind::Rank,
+            Phase::Normalize(_) => PhaseKind::Normalize,
+            Phase::Combine(_) => PhaseKind::Combine,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -58,6 +123,17 @@ pub struct Rule {
     pub action: Action,
 }
 
+/// How a match phase picks among the assignment rules a case satisfies
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchStrategy {
+    /// The first satisfied rule wins
+    #[default]
+    FirstMatch,
+    /// `match round_robin { ... }`: each case takes the next satisfied rule
+    /// after the one the previous case took, so cases spread evenly
+    RoundRobin,
+}
+
 #[derive(Debug, Clone)]
 pub struct MatchRule {
     pub condition: Expr,
@@ -69,11 +145,28 @@ pub enum Action {
     AssignScore(Expr),
     Log(String),
     Assign(String),
+    Assert(Expr),
+    /// Sets the named sub-score
+    AssignSubScore(String, Expr),
+    UpdateGlobal(GlobalUpdate),
+}
+
+/// A write to a variable declared with `global`
+#[derive(Debug, Clone)]
+pub enum GlobalUpdate {
+    Set(String, Expr),
+    Increment(String),
 }
 
 #[derive(Debug, Clone)]
 pub enum MatchAction {
     AssignTo(String),
+    AssignToDynamic(Expr),
+    /// Doesn't count as the rule that matched, so later rules still run
+    UpdateGlobal(GlobalUpdate),
+    /// `route to least_loaded [where <expr>]`: stamps the case with the
+    /// least loaded agent the condition holds for
+    RouteToLeastLoaded(Option<Expr>),
 }
 
 #[derive(Debug, Clone)]
@@ -87,6 +180,46 @@ pub struct SortRule {
     pub order: SortOrder,
 }
 
+/// Counts cases per distinct combination of key values
+#[derive(Debug, Clone)]
+pub struct GroupRule {
+    pub keys: Vec<Expr>,
+}
+
+/// Keeps a random subset of the cases
+#[derive(Debug, Clone)]
+pub enum SampleRule {
+    Count(usize),
+    /// Percentage of the batch, rounded to the nearest case
+    Percent(u32),
+}
+
+/// Numbers the cases by a key, storing the position in `CaseConfig::rank`
+#[derive(Debug, Clone)]
+pub struct RankRule {
+    pub key: Expr,
+    pub order: SortOrder,
+    pub mode: RankMode,
+    /// Reorder the cases by rank instead of keeping their order
+    pub sorted: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RankMode {
+    /// Ties share a rank and the ranks after them are skipped: 1, 2, 2, 4
+    Competition,
+    /// Ties share a rank and no ranks are skipped: 1, 2, 2, 3
+    Dense,
+}
+
+/// Rescales the batch's scores linearly so the lowest becomes `low` and the
+/// highest `high`
+#[derive(Debug, Clone)]
+pub struct NormalizeRule {
+    pub low: i64,
+    pub high: i64,
+}
+
 #[derive(Debug, Clone)]
 pub enum SortOrder {
     Asc,
@@ -117,6 +250,18 @@ pub enum Expr {
     Number(i64),
     String(String),
     Bool(bool),
+    /// `let name = value in body`, binding `name` only while evaluating `body`
+    LetIn {
+        name: String,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `start..end` or `start..=end`, only valid on the right of `in`
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -134,6 +279,8 @@ pub enum BinaryOperator {
     Sub,
     Mul,
     Div,
+    /// `??`: the left side unless it is null or an empty string
+    Coalesce,
 }
 
 #[derive(Debug, Clone)]
@@ -142,6 +289,8 @@ pub enum UnaryOperator {
     Not,
 }
 
+pub type BuiltinFn = fn(&[Value]) -> Result<Value, String>;
+
 #[derive(Debug, Clone)]
 pub enum Value {
     Number(i64),
@@ -149,11 +298,61 @@ pub enum Value {
     Bool(bool),
     List(Vec<Value>),
     Null,
-    Map(HashMap<String, Value>),
-    BuiltinFunction(fn(&[Value]) -> Result<Value, String>),
+    /// Keyed in sorted order, so iteration, `Display` and JSON output are stable
+    Map(BTreeMap<String, Value>),
+    Bytes(Vec<u8>),
+    BuiltinFunction(BuiltinFn),
     UserFunction(FunctionDef),
 }
 
+/// The kind of a `Value`, without its payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ValueType {
+    Number,
+    String,
+    Bool,
+    List,
+    Null,
+    Map,
+    Bytes,
+    BuiltinFunction,
+    UserFunction,
+}
+
+impl Value {
+    pub fn value_type(&self) -> ValueType {
+        match self {
+            Value::Number(_) => ValueType::Number,
+            Value::String(_) => ValueType::String,
+            Value::Bool(_) => ValueType::Bool,
+            Value::List(_) => ValueType::List,
+            Value::Null => ValueType::Null,
+            Value::Map(_) => ValueType::Map,
+            Value::Bytes(_) => ValueType::Bytes,
+            Value::BuiltinFunction(_) => ValueType::BuiltinFunction,
+            Value::UserFunction(_) => ValueType::UserFunction,
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ValueType::Number => "number",
+            ValueType::String => "string",
+            ValueType::Bool => "bool",
+            ValueType::List => "list",
+            ValueType::Null => "null",
+            ValueType::Map => "map",
+            ValueType::Bytes => "bytes",
+            ValueType::BuiltinFunction => "builtin function",
+            ValueType::UserFunction => "function",
+        };
+        f.write_str(name)
+    }
+}
+
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -163,6 +362,7 @@ impl PartialEq for Value {
             (Value::List(a), Value::List(b)) => a == b,
             (Value::Null, Value::Null) => true,
             (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
             (Value::BuiltinFunction(a), Value::BuiltinFunction(b)) => {
                 std::ptr::eq(a as *const _, b as *const _)
             }
@@ -173,3 +373,372 @@ impl PartialEq for Value {
         }
     }
 }
+
+/// Renders values in DSL literal form; map keys are sorted so output is stable.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Number(n) => write!(f, "{}", n),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Null => write!(f, "null"),
+            Value::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Value::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", key, value)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Bytes(bytes) => {
+                write!(f, "0x")?;
+                for byte in bytes {
+                    write!(f, "{:02x}", byte)?;
+                }
+                Ok(())
+            }
+            Value::BuiltinFunction(_) => write!(f, "<builtin function>"),
+            Value::UserFunction(func) => write!(f, "<function {}({})>", func.name, func.params.join(", ")),
+        }
+    }
+}
+
+impl BinaryOperator {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOperator::Eq => "==",
+            BinaryOperator::Neq => "!=",
+            BinaryOperator::In => "in",
+            BinaryOperator::Gt => ">",
+            BinaryOperator::Lt => "<",
+            BinaryOperator::Ge => ">=",
+            BinaryOperator::Le => "<=",
+            BinaryOperator::And => "and",
+            BinaryOperator::Or => "or",
+            BinaryOperator::Add => "+",
+            BinaryOperator::Sub => "-",
+            BinaryOperator::Mul => "*",
+            BinaryOperator::Div => "/",
+            BinaryOperator::Coalesce => "??",
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOperator::Or => 1,
+            BinaryOperator::And => 2,
+            BinaryOperator::Eq
+            | BinaryOperator::Neq
+            | BinaryOperator::In
+            | BinaryOperator::Gt
+            | BinaryOperator::Lt
+            | BinaryOperator::Ge
+            | BinaryOperator::Le => 3,
+            BinaryOperator::Coalesce => 4,
+            BinaryOperator::Add | BinaryOperator::Sub => 5,
+            BinaryOperator::Mul | BinaryOperator::Div => 6,
+        }
+    }
+}
+
+impl Expr {
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::BinaryOp { op, .. } => op.precedence(),
+            Expr::LetIn { .. } => 0,
+            Expr::UnaryOp { .. } => 7,
+            _ => 8,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, min_precedence: u8) -> fmt::Result {
+        if self.precedence() < min_precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+/// Renders expressions back to DSL source, adding parentheses only where
+/// precedence requires them.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::BinaryOp { left, op, right } => {
+                let precedence = op.precedence();
+                // Comparisons don't chain, so both sides must bind tighter
+                let left_min = if precedence == 3 { precedence + 1 } else { precedence };
+                left.fmt_operand(f, left_min)?;
+                write!(f, " {} ", op.symbol())?;
+                right.fmt_operand(f, precedence + 1)
+            }
+            Expr::UnaryOp { op, expr } => {
+                match op {
+                    UnaryOperator::Neg => write!(f, "-")?,
+                    UnaryOperator::Not => write!(f, "!")?,
+                }
+                expr.fmt_operand(f, 7)
+            }
+            Expr::FunctionCall { name, args } => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+            Expr::MemberAccess { object, property } => write!(f, "{}.{}", object, property),
+            Expr::LetIn { name, value, body } => {
+                write!(f, "let {} = ", name)?;
+                // Parenthesise comparisons so an `in` test can't end the binding early
+                value.fmt_operand(f, 4)?;
+                write!(f, " in {}", body)
+            }
+            Expr::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Expr::Range { start, end, inclusive } => {
+                start.fmt_operand(f, 5)?;
+                write!(f, "{}", if *inclusive { "..=" } else { ".." })?;
+                end.fmt_operand(f, 5)
+            }
+            Expr::Ident(name) => write!(f, "{}", name),
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::String(s) => write!(f, "\"{}\"", s),
+            Expr::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::AssignScore(expr) => write!(f, "score = {}", expr),
+            Action::Log(message) => write!(f, "log \"{}\"", message),
+            Action::Assign(name) => write!(f, "{} = true", name),
+            Action::Assert(expr) => write!(f, "assert {}", expr),
+            Action::AssignSubScore(name, expr) => write!(f, "score.{} = {}", name, expr),
+            Action::UpdateGlobal(update) => write!(f, "{}", update),
+        }
+    }
+}
+
+impl fmt::Display for GlobalUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GlobalUpdate::Set(name, expr) => write!(f, "{} = {}", name, expr),
+            GlobalUpdate::Increment(name) => write!(f, "increment {}", name),
+        }
+    }
+}
+
+impl fmt::Display for MatchAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchAction::AssignTo(name) => write!(f, "assign to {}", name),
+            MatchAction::AssignToDynamic(expr @ (Expr::FunctionCall { .. } | Expr::MemberAccess { .. })) => {
+                write!(f, "assign to {}", expr)
+            }
+            MatchAction::AssignToDynamic(expr) => write!(f, "assign to ({})", expr),
+            MatchAction::UpdateGlobal(update) => write!(f, "{}", update),
+            MatchAction::RouteToLeastLoaded(None) => write!(f, "route to least_loaded"),
+            MatchAction::RouteToLeastLoaded(Some(filter)) => write!(f, "route to least_loaded where {}", filter),
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "when {} then {}", self.condition, self.action)
+    }
+}
+
+impl fmt::Display for MatchRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "when {} then {}", self.condition, self.action)
+    }
+}
+
+/// Indentation of one nesting level in formatted source
+const INDENT: &str = "    ";
+
+fn write_block<T: fmt::Display>(f: &mut fmt::Formatter<'_>, header: &str, lines: &[T]) -> fmt::Result {
+    if lines.is_empty() {
+        return writeln!(f, "{}{} {{}}", INDENT, header);
+    }
+    writeln!(f, "{}{} {{", INDENT, header)?;
+    for line in lines {
+        writeln!(f, "{}{}{}", INDENT, INDENT, line)?;
+    }
+    writeln!(f, "{}}}", INDENT)
+}
+
+fn write_statements(f: &mut fmt::Formatter<'_>, statements: &[Statement], depth: usize) -> fmt::Result {
+    let indent = INDENT.repeat(depth);
+    for statement in statements {
+        match statement {
+            Statement::Let { name, value } => writeln!(f, "{}let {} = {};", indent, name, value)?,
+            Statement::Assign { name, value } => writeln!(f, "{}{} = {};", indent, name, value)?,
+            Statement::MemberAssign { object, property, value } => {
+                writeln!(f, "{}{}.{} = {};", indent, object, property, value)?
+            }
+            Statement::If { condition, then_body, else_body } => {
+                writeln!(f, "{}if {} {{", indent, condition)?;
+                write_statements(f, then_body, depth + 1)?;
+                match else_body {
+                    Some(else_body) => {
+                        writeln!(f, "{}}} else {{", indent)?;
+                        write_statements(f, else_body, depth + 1)?;
+                        writeln!(f, "{}}}", indent)?;
+                    }
+                    None => writeln!(f, "{}}}", indent)?,
+                }
+            }
+            Statement::Return(expr) => writeln!(f, "{}return {};", indent, expr)?,
+            Statement::Assert(expr) => writeln!(f, "{}assert {};", indent, expr)?,
+            Statement::Expression(expr) => writeln!(f, "{}{};", indent, expr)?,
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortOrder::Asc => write!(f, "asc"),
+            SortOrder::Desc => write!(f, "desc"),
+        }
+    }
+}
+
+/// Renders a phase as the indented lines of a workflow body, ending in a newline.
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Score { rules, otherwise } => {
+                let mut lines: Vec<String> = rules.iter().map(Rule::to_string).collect();
+                if let Some(action) = otherwise {
+                    lines.push(format!("otherwise then {}", action));
+                }
+                write_block(f, "score", &lines)
+            }
+            Phase::Match(rules, strategy) => {
+                let header = match strategy {
+                    MatchStrategy::FirstMatch => "match",
+                    MatchStrategy::RoundRobin => "match round_robin",
+                };
+                write_block(f, header, rules)
+            }
+            Phase::Filter(filter) => writeln!(f, "{}filter {{ when {} }}", INDENT, filter.condition),
+            Phase::Sort(sort) => writeln!(f, "{}sort {{ by {} {} }}", INDENT, sort.key, sort.order),
+            Phase::Group(group) => {
+                let keys: Vec<String> = group.keys.iter().map(Expr::to_string).collect();
+                writeln!(f, "{}group {{ by {} count }}", INDENT, keys.join(", "))
+            }
+            Phase::Sample(SampleRule::Count(count)) => writeln!(f, "{}sample {{ {} }}", INDENT, count),
+            Phase::Sample(SampleRule::Percent(percent)) => writeln!(f, "{}sample {{ {} percent }}", INDENT, percent),
+            Phase::Rank(rank) => {
+                let dense = if rank.mode == RankMode::Dense { " dense" } else { "" };
+                let sorted = if rank.sorted { " sorted" } else { "" };
+                writeln!(
+                    f,
+                    "{}rank {{ by {} {}{} into rank{} }}",
+                    INDENT, rank.key, rank.order, dense, sorted
+                )
+            }
+            Phase::Normalize(normalize) => writeln!(f, "{}normalize {{ to {}..{} }}", INDENT, normalize.low, normalize.high),
+            Phase::Combine(expr) => writeln!(f, "{}combine {{ score = {} }}", INDENT, expr),
+        }
+    }
+}
+
+impl fmt::Display for Workflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "workflow {}", self.name)?;
+        if let Some(guard) = &self.guard {
+            write!(f, " when {}", guard)?;
+        }
+        if self.phases.is_empty() {
+            return writeln!(f, " {{}}");
+        }
+        writeln!(f, " {{")?;
+        for phase in &self.phases {
+            write!(f, "{}", phase)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+impl fmt::Display for FunctionDef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pure = if self.pure { "pure " } else { "" };
+        write!(f, "function {}{}({})", pure, self.name, self.params.join(", "))?;
+        match &self.body {
+            FunctionBody::Expression(expr) => writeln!(f, " = {}", expr),
+            FunctionBody::Block(statements) if statements.is_empty() => writeln!(f, " {{}}"),
+            FunctionBody::Block(statements) => {
+                writeln!(f, " {{")?;
+                write_statements(f, statements, 1)?;
+                writeln!(f, "}}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for GlobalDecl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "global {} = {}", self.name, self.value)
+    }
+}
+
+/// Renders a whole program as canonically formatted source: globals, then
+/// functions, then workflows, separated by blank lines. Comments are not
+/// part of the AST, so they don't survive.
+impl fmt::Display for Program {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        let mut separate = |f: &mut fmt::Formatter<'_>| -> fmt::Result {
+            if !std::mem::replace(&mut first, false) {
+                writeln!(f)?;
+            }
+            Ok(())
+        };
+        if !self.globals.is_empty() {
+            separate(f)?;
+            for global in &self.globals {
+                write!(f, "{}", global)?;
+            }
+        }
+        for function in &self.functions {
+            separate(f)?;
+            write!(f, "{}", function)?;
+        }
+        for workflow in &self.workflows {
+            separate(f)?;
+            write!(f, "{}", workflow)?;
+        }
+        Ok(())
+    }
+}
@@ -8,11 +8,38 @@ pub fn build_action(pair: Pair<Rule>) -> ast::Action {
     match inner.as_rule() {
         Rule::expr => { ast::Action::AssignScore(build_expr(inner)) }
         Rule::string => { ast::Action::Log(inner.as_str().trim_matches('"').to_string()) }
+        Rule::assert_action => { ast::Action::Assert(build_expr(inner.into_inner().next().unwrap())) }
+        Rule::global_update => ast::Action::UpdateGlobal(build_global_update(inner)),
+        Rule::sub_score_action => {
+            let mut parts = inner.into_inner();
+            let name = parts.next().unwrap().as_str().to_string();
+            ast::Action::AssignSubScore(name, build_expr(parts.next().unwrap()))
+        }
         _ => unreachable!("Unexpected action rule: {:?}", inner.as_rule()),
     }
 }
 
 pub fn build_match_action(pair: Pair<Rule>) -> ast::MatchAction {
-    let ident_pair = pair.into_inner().next().unwrap();
-    ast::MatchAction::AssignTo(ident_pair.as_str().to_string())
+    let target = pair.into_inner().next().unwrap();
+    match target.as_rule() {
+        Rule::ident => ast::MatchAction::AssignTo(target.as_str().to_string()),
+        Rule::global_update => ast::MatchAction::UpdateGlobal(build_global_update(target)),
+        Rule::least_loaded_route => ast::MatchAction::RouteToLeastLoaded(target.into_inner().next().map(build_expr)),
+        Rule::queue_expr => {
+            ast::MatchAction::AssignToDynamic(build_expr(target.into_inner().next().unwrap()))
+        }
+        _ => unreachable!("Unexpected match action target: {:?}", target.as_rule()),
+    }
+}
+
+fn build_global_update(pair: Pair<Rule>) -> ast::GlobalUpdate {
+    let update = pair.into_inner().next().unwrap();
+    let kind = update.as_rule();
+    let mut parts = update.into_inner();
+    let name = parts.next().unwrap().as_str().to_string();
+    match kind {
+        Rule::increment_update => ast::GlobalUpdate::Increment(name),
+        Rule::set_update => ast::GlobalUpdate::Set(name, build_expr(parts.next().unwrap())),
+        _ => unreachable!("Unexpected global update: {:?}", kind),
+    }
 }
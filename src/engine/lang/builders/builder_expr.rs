@@ -43,8 +43,16 @@ pub fn build_expr(pair: Pair<Rule>) -> ast::Expr {
             }
         }
         Rule::expr | Rule::primary_expr => build_expr(pair.into_inner().next().unwrap()),
-        Rule::or_expr => build_binary_chain(pair, ast::BinaryOperator::Or),
-        Rule::and_expr => build_binary_chain(pair, ast::BinaryOperator::And),
+        Rule::or_expr | Rule::let_value => build_binary_chain(pair, ast::BinaryOperator::Or),
+        Rule::and_expr | Rule::let_and_expr => build_binary_chain(pair, ast::BinaryOperator::And),
+        Rule::coalesce_expr => build_binary_chain(pair, ast::BinaryOperator::Coalesce),
+        Rule::let_in_expr => {
+            let mut inner = pair.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            let value = build_expr(inner.next().unwrap());
+            let body = build_expr(inner.next().unwrap());
+            ast::Expr::LetIn { name, value: Box::new(value), body: Box::new(body) }
+        }
         Rule::add_expr =>
             build_binary_from_text(
                 pair,
@@ -61,8 +69,15 @@ pub fn build_expr(pair: Pair<Rule>) -> ast::Expr {
                     ("/", ast::BinaryOperator::Div),
                 ]
             ),
-        Rule::comp_expr => build_comparison(pair),
+        Rule::comp_expr | Rule::let_comp_expr => build_comparison(pair),
         Rule::unary_expr => build_unary_expr(pair),
+        Rule::range => {
+            let mut inner = pair.into_inner();
+            let start = build_expr(inner.next().unwrap());
+            let inclusive = inner.next().unwrap().as_str() == "..=";
+            let end = build_expr(inner.next().unwrap());
+            ast::Expr::Range { start: Box::new(start), end: Box::new(end), inclusive }
+        }
         _ => unreachable!("Unexpected expr: {:?}", pair.as_rule()),
     }
 }
@@ -140,28 +155,16 @@ fn build_comparison(pair: Pair<Rule>) -> ast::Expr {
 }
 
 fn build_unary_expr(pair: Pair<Rule>) -> ast::Expr {
-    let full_text = pair.as_str();
-    let mut inner = pair.into_inner();
-    let expr = build_expr(inner.next().unwrap());
-
-    let prefix = &full_text[..full_text.len() - expr_to_str_len(&expr)];
-    prefix
-        .chars()
-        .rev()
-        .fold(expr, |acc, ch| {
-            match ch {
-                '-' => ast::Expr::UnaryOp { op: ast::UnaryOperator::Neg, expr: Box::new(acc) },
-                '!' => ast::Expr::UnaryOp { op: ast::UnaryOperator::Not, expr: Box::new(acc) },
-                _ => acc,
-            }
-        })
-}
-
-fn expr_to_str_len(expr: &ast::Expr) -> usize {
-    match expr {
-        ast::Expr::Number(n) => n.to_string().len(),
-        ast::Expr::String(s) => s.len() + 2,
-        ast::Expr::Ident(s) => s.len(),
-        _ => 1, // fallback
-    }
+    let mut inner: Vec<Pair<Rule>> = pair.into_inner().collect();
+    let expr = build_expr(inner.pop().unwrap());
+
+    // The operator nearest the operand applies first
+    inner.iter().rev().fold(expr, |acc, op| {
+        let op = match op.as_str() {
+            "-" => ast::UnaryOperator::Neg,
+            "!" => ast::UnaryOperator::Not,
+            other => unreachable!("Unexpected unary operator: {}", other),
+        };
+        ast::Expr::UnaryOp { op, expr: Box::new(acc) }
+    })
 }
@@ -3,50 +3,69 @@ use crate::engine::lang::ast;
 use crate::engine::lang::parser::Rule;
 use crate::engine::lang::builders::builder_rule::{ build_rule, build_match_rule };
 use crate::engine::lang::builders::builder_expr::build_expr;
+use crate::engine::lang::builders::builder_action::build_action;
 
-pub fn build_program(pairs: Pairs<Rule>) -> ast::Program {
+/// A program that parsed but could not be turned into an AST.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+pub fn build_program(pairs: Pairs<Rule>) -> Result<ast::Program, BuildError> {
     let mut functions = Vec::new();
     let mut workflows = Vec::new();
+    let mut globals = Vec::new();
 
     for pair in pairs {
         if pair.as_rule() == Rule::program {
             for inner in pair.into_inner() {
                 match inner.as_rule() {
-                    Rule::function_def => functions.push(build_function_def(inner)),
-                    Rule::workflow => workflows.push(build_workflow(inner)),
+                    Rule::function_def => {
+                        let (line, column) = inner.line_col();
+                        let function = build_function_def(inner)
+                            .map_err(|message| BuildError { line, column, message })?;
+                        functions.push(function);
+                    }
+                    Rule::workflow => workflows.push(build_workflow(inner)?),
+                    Rule::global_decl => {
+                        let mut parts = inner.into_inner();
+                        let name = parts.next().unwrap().as_str().to_string();
+                        globals.push(ast::GlobalDecl { name, value: build_expr(parts.next().unwrap()) });
+                    }
                     _ => {}
                 }
             }
         }
     }
 
-    ast::Program { functions, workflows }
+    Ok(ast::Program { functions, workflows, globals })
 }
 
-pub fn build_workflows(pairs: Pairs<Rule>) -> Vec<ast::Workflow> {
-    pairs
-        .flat_map(|pair| {
-            if pair.as_rule() == Rule::program {
-                pair.into_inner()
-                    .filter(|p| p.as_rule() == Rule::workflow)
-                    .map(build_workflow)
-                    .collect::<Vec<_>>()
-            } else if pair.as_rule() == Rule::workflow {
-                vec![build_workflow(pair)]
-            } else {
-                vec![]
+pub fn build_workflows(pairs: Pairs<Rule>) -> Result<Vec<ast::Workflow>, BuildError> {
+    let mut workflows = Vec::new();
+    for pair in pairs {
+        if pair.as_rule() == Rule::program {
+            for inner in pair.into_inner().filter(|p| p.as_rule() == Rule::workflow) {
+                workflows.push(build_workflow(inner)?);
             }
-        })
-        .collect()
+        } else if pair.as_rule() == Rule::workflow {
+            workflows.push(build_workflow(pair)?);
+        }
+    }
+    Ok(workflows)
 }
 
-pub fn build_function_def(pair: Pair<Rule>) -> ast::FunctionDef {
+pub fn build_function_def(pair: Pair<Rule>) -> Result<ast::FunctionDef, String> {
     let mut name = String::new();
     let mut params = Vec::new();
     let mut body = None;
+    let mut pure = false;
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
+            Rule::pure_kw => pure = true,
             Rule::ident => {
                 name = inner.as_str().to_string();
             }
@@ -58,59 +77,70 @@ pub fn build_function_def(pair: Pair<Rule>) -> ast::FunctionDef {
                     .collect();
             }
             Rule::function_body => {
-                body = Some(build_function_body(inner));
+                body = Some(build_function_body(inner).map_err(|e| format!("function '{}': {}", name, e))?);
             }
             _ => {}
         }
     }
 
-    ast::FunctionDef {
-        name,
+    Ok(ast::FunctionDef {
+        name: name.clone(),
         params,
-        body: body.unwrap(),
-    }
+        body: body.ok_or_else(|| format!("function '{}' is missing a body", name))?,
+        pure,
+    })
 }
 
-pub fn build_function_body(pair: Pair<Rule>) -> ast::FunctionBody {
-    let mut inner_pairs = pair.into_inner();
-    let first = inner_pairs.next().unwrap();
+pub fn build_function_body(pair: Pair<Rule>) -> Result<ast::FunctionBody, String> {
+    let mut inner_pairs = pair.into_inner().peekable();
+    let first = inner_pairs.peek().ok_or("body has no statements")?;
 
-    match first.as_rule() {
-        Rule::expr => ast::FunctionBody::Expression(build_expr(first)),
-        Rule::statement => {
-            let mut statements = vec![build_statement(first)];
-            statements.extend(inner_pairs.map(build_statement));
-            ast::FunctionBody::Block(statements)
-        }
-        _ => {
-            let mut statements = Vec::new();
-            statements.push(build_statement(first));
-            statements.extend(inner_pairs.map(build_statement));
-            ast::FunctionBody::Block(statements)
-        }
+    if first.as_rule() == Rule::expr {
+        let expr = inner_pairs.next().unwrap();
+        return Ok(ast::FunctionBody::Expression(build_expr(expr)));
     }
+    let statements = inner_pairs.map(build_statement).collect::<Result<_, _>>()?;
+    Ok(ast::FunctionBody::Block(statements))
 }
 
-pub fn build_statement(pair: Pair<Rule>) -> ast::Statement {
-    let inner = pair.into_inner().next().unwrap();
+pub fn build_statement(pair: Pair<Rule>) -> Result<ast::Statement, String> {
+    let inner = pair.into_inner().next().ok_or("empty statement")?;
     match inner.as_rule() {
         Rule::let_statement => build_let_statement(inner),
         Rule::assign_statement => build_assign_statement(inner),
+        Rule::member_assign_statement => build_member_assign_statement(inner),
         Rule::if_statement => build_if_statement(inner),
         Rule::return_statement => build_return_statement(inner),
+        Rule::assert_statement => build_assert_statement(inner),
         Rule::expr_statement => build_expr_statement(inner),
-        _ => unreachable!("Unexpected statement type: {:?}", inner.as_rule()),
+        other => Err(format!("unexpected statement type {:?}", other)),
     }
 }
 
-pub fn build_let_statement(pair: Pair<Rule>) -> ast::Statement {
-    let mut name = String::new();
+pub fn build_let_statement(pair: Pair<Rule>) -> Result<ast::Statement, String> {
+    let (name, value) = build_binding(pair);
+    Ok(ast::Statement::Let {
+        value: value.ok_or_else(|| format!("let statement for '{}' missing value expression", name))?,
+        name,
+    })
+}
+
+pub fn build_assign_statement(pair: Pair<Rule>) -> Result<ast::Statement, String> {
+    let (name, value) = build_binding(pair);
+    Ok(ast::Statement::Assign {
+        value: value.ok_or_else(|| format!("assignment to '{}' missing value expression", name))?,
+        name,
+    })
+}
+
+pub fn build_member_assign_statement(pair: Pair<Rule>) -> Result<ast::Statement, String> {
+    let mut target = None;
     let mut value = None;
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
-            Rule::ident => {
-                name = inner.as_str().to_string();
+            Rule::member_access => {
+                target = Some(inner.as_str().to_string());
             }
             Rule::expr => {
                 value = Some(build_expr(inner));
@@ -119,13 +149,19 @@ pub fn build_let_statement(pair: Pair<Rule>) -> ast::Statement {
         }
     }
 
-    ast::Statement::Let {
-        name,
-        value: value.unwrap(),
-    }
+    let target = target.ok_or("member assignment missing target")?;
+    let parts: Vec<&str> = target.split('.').map(str::trim).collect();
+    let [object, property] = parts[..] else {
+        return Err(format!("assignment to '{}' only supports a single level (object.property)", target));
+    };
+    Ok(ast::Statement::MemberAssign {
+        object: object.to_string(),
+        property: property.to_string(),
+        value: value.ok_or_else(|| format!("assignment to '{}' missing value expression", target))?,
+    })
 }
 
-pub fn build_assign_statement(pair: Pair<Rule>) -> ast::Statement {
+fn build_binding(pair: Pair<Rule>) -> (String, Option<ast::Expr>) {
     let mut name = String::new();
     let mut value = None;
 
@@ -141,70 +177,57 @@ pub fn build_assign_statement(pair: Pair<Rule>) -> ast::Statement {
         }
     }
 
-    ast::Statement::Assign {
-        name,
-        value: value.unwrap(),
-    }
+    (name, value)
 }
 
-pub fn build_if_statement(pair: Pair<Rule>) -> ast::Statement {
+pub fn build_if_statement(pair: Pair<Rule>) -> Result<ast::Statement, String> {
     let mut condition = None;
     let mut then_body = Vec::new();
     let mut else_body = None;
-    let mut in_else = false;
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
             Rule::expr if condition.is_none() => {
                 condition = Some(build_expr(inner));
             }
-            Rule::statement => {
-                if in_else {
-                    if else_body.is_none() {
-                        else_body = Some(Vec::new());
-                    }
-                    else_body.as_mut().unwrap().push(build_statement(inner));
-                } else {
-                    then_body.push(build_statement(inner));
-                }
-            }
-            _ => {
-                if inner.as_str() == "else" {
-                    in_else = true;
-                }
+            Rule::statement => then_body.push(build_statement(inner)?),
+            Rule::else_clause => {
+                else_body = Some(inner.into_inner().map(build_statement).collect::<Result<_, _>>()?);
             }
+            _ => {}
         }
     }
 
-    ast::Statement::If {
-        condition: condition.unwrap(),
+    Ok(ast::Statement::If {
+        condition: condition.ok_or("if statement missing condition expression")?,
         then_body,
         else_body,
-    }
+    })
 }
 
-pub fn build_return_statement(pair: Pair<Rule>) -> ast::Statement {
-    let expr = pair
-        .into_inner()
-        .find(|p| p.as_rule() == Rule::expr)
-        .map(build_expr)
-        .unwrap();
+pub fn build_return_statement(pair: Pair<Rule>) -> Result<ast::Statement, String> {
+    single_expr(pair, "return statement").map(ast::Statement::Return)
+}
+
+pub fn build_assert_statement(pair: Pair<Rule>) -> Result<ast::Statement, String> {
+    single_expr(pair, "assert statement").map(ast::Statement::Assert)
+}
 
-    ast::Statement::Return(expr)
+pub fn build_expr_statement(pair: Pair<Rule>) -> Result<ast::Statement, String> {
+    single_expr(pair, "expression statement").map(ast::Statement::Expression)
 }
 
-pub fn build_expr_statement(pair: Pair<Rule>) -> ast::Statement {
-    let expr = pair
+fn single_expr(pair: Pair<Rule>, what: &str) -> Result<ast::Expr, String> {
+    pair
         .into_inner()
         .find(|p| p.as_rule() == Rule::expr)
         .map(build_expr)
-        .unwrap();
-
-    ast::Statement::Expression(expr)
+        .ok_or_else(|| format!("{} missing expression", what))
 }
 
-pub fn build_workflow(pair: Pair<Rule>) -> ast::Workflow {
+pub fn build_workflow(pair: Pair<Rule>) -> Result<ast::Workflow, BuildError> {
     let mut name = String::new();
+    let mut guard = None;
     let mut phases = Vec::new();
 
     for inner in pair.into_inner() {
@@ -212,39 +235,50 @@ pub fn build_workflow(pair: Pair<Rule>) -> ast::Workflow {
             Rule::ident => {
                 name = inner.as_str().to_string();
             }
-            Rule::phase => phases.push(build_phase(inner)),
+            Rule::workflow_guard => guard = inner.into_inner().next().map(build_expr),
+            Rule::phase => phases.push(build_phase(inner)?),
             _ => {}
         }
     }
 
-    ast::Workflow { name, phases }
+    Ok(ast::Workflow { name, guard, phases })
 }
 
-pub fn build_phase(pair: Pair<Rule>) -> ast::Phase {
+pub fn build_phase(pair: Pair<Rule>) -> Result<ast::Phase, BuildError> {
     let inner = pair.into_inner().next().unwrap();
-    match inner.as_rule() {
+    let phase = match inner.as_rule() {
         Rule::score_phase => {
-            let rules = inner
-                .into_inner()
-                .filter(|p| p.as_rule() == Rule::rule)
-                .map(build_rule)
-                .collect();
-            ast::Phase::Score(rules)
+            let mut rules = Vec::new();
+            let mut otherwise = None;
+            for inner_pair in inner.into_inner() {
+                match inner_pair.as_rule() {
+                    Rule::rule => rules.push(build_rule(inner_pair)),
+                    Rule::otherwise_rule => {
+                        otherwise = inner_pair.into_inner().next().map(build_action);
+                    }
+                    _ => {}
+                }
+            }
+            ast::Phase::Score { rules, otherwise }
         }
         Rule::match_phase => {
-            let rules = inner
-                .into_inner()
-                .filter(|p| p.as_rule() == Rule::match_rule)
-                .map(build_match_rule)
-                .collect();
-            ast::Phase::Match(rules)
+            let mut rules = Vec::new();
+            let mut strategy = ast::MatchStrategy::FirstMatch;
+            for pair in inner.into_inner() {
+                match pair.as_rule() {
+                    Rule::match_rule => rules.push(build_match_rule(pair)),
+                    Rule::round_robin => strategy = ast::MatchStrategy::RoundRobin,
+                    _ => {}
+                }
+            }
+            ast::Phase::Match(rules, strategy)
         }
         Rule::filter_phase => {
             let condition = inner
                 .into_inner()
                 .find(|p| p.as_rule() == Rule::expr)
                 .map(build_expr)
-                .unwrap();
+                .unwrap_or(ast::Expr::Bool(true));
             ast::Phase::Filter(ast::FilterRule { condition })
         }
         Rule::sort_phase => {
@@ -271,6 +305,66 @@ pub fn build_phase(pair: Pair<Rule>) -> ast::Phase {
                 order,
             })
         }
+        Rule::group_phase => {
+            let keys = inner
+                .into_inner()
+                .filter(|p| p.as_rule() == Rule::expr)
+                .map(build_expr)
+                .collect();
+            ast::Phase::Group(ast::GroupRule { keys })
+        }
+        Rule::sample_phase => {
+            let mut inner = inner.into_inner();
+            let amount = inner.next().unwrap();
+            if inner.next().is_some() {
+                ast::Phase::Sample(ast::SampleRule::Percent(parse_literal(&amount, "sample percent")?))
+            } else {
+                ast::Phase::Sample(ast::SampleRule::Count(parse_literal(&amount, "sample count")?))
+            }
+        }
+        Rule::rank_phase => {
+            let mut key = None;
+            let mut order = ast::SortOrder::Asc;
+            let mut mode = ast::RankMode::Competition;
+            let mut sorted = false;
+
+            for inner_pair in inner.into_inner() {
+                match inner_pair.as_rule() {
+                    Rule::expr => key = Some(build_expr(inner_pair)),
+                    Rule::sort_order if inner_pair.as_str() == "desc" => order = ast::SortOrder::Desc,
+                    Rule::rank_dense => mode = ast::RankMode::Dense,
+                    Rule::rank_sorted => sorted = true,
+                    _ => {}
+                }
+            }
+
+            ast::Phase::Rank(ast::RankRule {
+                key: key.unwrap(),
+                order,
+                mode,
+                sorted,
+            })
+        }
+        Rule::combine_phase => {
+            ast::Phase::Combine(build_expr(inner.into_inner().next().unwrap()))
+        }
+        Rule::normalize_phase => {
+            let mut bounds = inner.into_inner();
+            ast::Phase::Normalize(ast::NormalizeRule {
+                low: parse_literal(&bounds.next().unwrap(), "normalize bound")?,
+                high: parse_literal(&bounds.next().unwrap(), "normalize bound")?,
+            })
+        }
         _ => unreachable!("Unexpected phase type: {:?}", inner.as_rule()),
-    }
+    };
+    Ok(phase)
+}
+
+/// Parse a numeric literal the grammar has already matched; only a value
+/// outside the target type's range can fail.
+fn parse_literal<T: std::str::FromStr>(pair: &Pair<Rule>, what: &str) -> Result<T, BuildError> {
+    pair.as_str().parse().map_err(|_| {
+        let (line, column) = pair.line_col();
+        BuildError { line, column, message: format!("{} '{}' is out of range", what, pair.as_str()) }
+    })
 }
@@ -1,6 +1,7 @@
 pub mod ast;
 pub mod parser;
 pub mod builders;
+pub mod value_json;
 
 #[cfg(test)]
 mod tests;
@@ -1,5 +1,6 @@
 use pest::Parser;
 use pest_derive::Parser;
+use crate::engine::lang::{ ast::Expr, builders::builder_expr::build_expr };
 
 #[derive(Parser)]
 #[grammar = "engine/lang/workflow.pest"]
@@ -7,6 +8,13 @@ pub struct WorkflowParser;
 
 pub fn parse_workflow(
     input: &str
-) -> Result<pest::iterators::Pairs<Rule>, pest::error::Error<Rule>> {
-    WorkflowParser::parse(Rule::program, input)
+) -> Result<pest::iterators::Pairs<'_, Rule>, Box<pest::error::Error<Rule>>> {
+    WorkflowParser::parse(Rule::program, input).map_err(Box::new)
+}
+
+/// Parse a single expression such as `priority * 10 > 50`.
+pub fn parse_expression(input: &str) -> Result<Expr, Box<pest::error::Error<Rule>>> {
+    let mut pairs = WorkflowParser::parse(Rule::expression, input).map_err(Box::new)?;
+    let expr = pairs.next().unwrap().into_inner().next().unwrap();
+    Ok(build_expr(expr))
 }
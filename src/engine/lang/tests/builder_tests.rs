@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(clippy::bool_assert_comparison)]
 mod tests {
     use crate::engine::lang::ast::*;
     use crate::engine::lang::builders::builder_workflow;
@@ -9,7 +10,7 @@ mod tests {
     fn parse_workflow(input: &str) -> Vec<Workflow> {
         let pairs = WorkflowParser::parse(Rule::program, input)
             .expect("Failed to parse input");
-        builder_workflow::build_workflows(pairs)
+        builder_workflow::build_workflows(pairs).expect("Failed to build workflows")
     }
 
     #[test]
@@ -30,7 +31,7 @@ mod tests {
         assert_eq!(workflow.phases.len(), 1);
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 assert_eq!(rules.len(), 1);
                 let rule = &rules[0];
                 
@@ -69,7 +70,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 let rule = &rules[0];
                 match &rule.action {
                     Action::Log(message) => assert_eq!(message, "test message"),
@@ -96,7 +97,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Match(rules) => {
+            Phase::Match(rules, _) => {
                 assert_eq!(rules.len(), 1);
                 let rule = &rules[0];
                 
@@ -117,6 +118,7 @@ mod tests {
                 // Check match action
                 match &rule.action {
                     MatchAction::AssignTo(var) => assert_eq!(var, "result"),
+                    _ => panic!("Expected static assignment"),
                 }
             },
             _ => panic!("Expected Match phase"),
@@ -139,7 +141,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 let rule = &rules[0];
                 
                 // Check the complex condition: x + y * 2 == 10
@@ -194,7 +196,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 let rule = &rules[0];
                 
                 // Check the logical AND condition
@@ -251,7 +253,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 let rule = &rules[0];
                 
                 // Check condition: !active
@@ -285,6 +287,34 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_unary_operators_only_take_the_leading_prefix() {
+        // A '-' or '!' inside the operand's text, such as a string argument,
+        // is not an operator
+        let workflows = parse_workflow(r#"
+            workflow unary_call {
+                score {
+                    when !contains(["a-b", "c!"], tag) then score = -len("x-y")
+                    when !!is_business_hours(now(), "09:00-17:00", "Mon-Fri") then score = --priority
+                }
+            }
+        "#);
+        let Phase::Score { rules, .. } = &workflows[0].phases[0] else { panic!("Expected Score phase") };
+        assert_eq!(rules[0].to_string(), r#"when !contains(["a-b", "c!"], tag) then score = -len("x-y")"#);
+
+        let Action::AssignScore(Expr::UnaryOp { op: UnaryOperator::Neg, expr }) = &rules[0].action else {
+            panic!("Expected a single negation")
+        };
+        assert!(matches!(expr.as_ref(), Expr::FunctionCall { name, .. } if name == "len"));
+
+        // Stacked operators nest, the one nearest the operand innermost
+        let Expr::UnaryOp { op: UnaryOperator::Not, expr } = &rules[1].condition else { panic!("Expected NOT") };
+        let Expr::UnaryOp { op: UnaryOperator::Not, expr } = expr.as_ref() else { panic!("Expected NOT NOT") };
+        assert!(matches!(expr.as_ref(), Expr::FunctionCall { name, .. } if name == "is_business_hours"));
+        let Action::AssignScore(Expr::UnaryOp { op: UnaryOperator::Neg, expr }) = &rules[1].action else { panic!("Expected negation") };
+        assert!(matches!(expr.as_ref(), Expr::UnaryOp { op: UnaryOperator::Neg, expr } if matches!(expr.as_ref(), Expr::Ident(name) if name == "priority")));
+    }
+
     #[test]
     fn test_function_call_expressions() {
         let input = r#"
@@ -299,7 +329,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 let rule = &rules[0];
                 
                 // Check condition: contains(list, item)
@@ -358,7 +388,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 let rule = &rules[0];
                 
                 // Check condition: item in [1, 2, 3]
@@ -413,7 +443,7 @@ mod tests {
         
         // Check first phase (score)
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 assert_eq!(rules.len(), 2);
                 
                 // First rule: when x > 0 then score = x
@@ -433,17 +463,19 @@ mod tests {
         
         // Check second phase (match)
         match &workflow.phases[1] {
-            Phase::Match(rules) => {
+            Phase::Match(rules, _) => {
                 assert_eq!(rules.len(), 2);
                 
                 // First rule: when score > 5 then assign to high
                 match &rules[0].action {
                     MatchAction::AssignTo(var) => assert_eq!(var, "high"),
+                    _ => panic!("Expected static assignment"),
                 }
                 
                 // Second rule: when score > 0 then assign to low
                 match &rules[1].action {
                     MatchAction::AssignTo(var) => assert_eq!(var, "low"),
+                    _ => panic!("Expected static assignment"),
                 }
             },
             _ => panic!("Expected Match phase"),
@@ -474,13 +506,13 @@ mod tests {
         
         // Check first workflow has score phase
         match &workflows[0].phases[0] {
-            Phase::Score(_) => {},
+            Phase::Score { .. } => {},
             _ => panic!("Expected Score phase in first workflow"),
         }
         
         // Check second workflow has match phase
         match &workflows[1].phases[0] {
-            Phase::Match(_) => {},
+            Phase::Match(..) => {},
             _ => panic!("Expected Match phase in second workflow"),
         }
     }
@@ -500,7 +532,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 // First rule condition: true
                 match &rules[0].condition {
                     Expr::Bool(b) => assert_eq!(*b, true),
@@ -531,7 +563,7 @@ mod tests {
         let workflow = &workflows[0];
         
         match &workflow.phases[0] {
-            Phase::Score(rules) => {
+            Phase::Score { rules, .. } => {
                 let rule = &rules[0];
                 
                 // Check condition: name == "test"
@@ -145,6 +145,9 @@ mod tests {
         assert_parses(Rule::match_action, "assign to result");
         assert_parses(Rule::match_action, "assign to output_var");
         assert_parses(Rule::match_action, "assign to var123");
+        assert_parses(Rule::match_action, "assign to queue_for(category)");
+        assert_parses(Rule::match_action, "assign to case.category");
+        assert_parses(Rule::match_action, "assign to (prefix + category)");
         
         assert_fails(Rule::match_action, "assign result"); // missing "to"
         assert_fails(Rule::match_action, "assign to"); // missing identifier
@@ -365,4 +368,12 @@ mod tests {
         assert_fails(Rule::list, "[1, 2"); // unclosed bracket
         assert_fails(Rule::list, "1, 2]"); // missing opening bracket
     }
+
+    #[test]
+    fn test_standalone_expression() {
+        assert_parses(Rule::expression, "priority * 10 + 5");
+        assert_parses(Rule::expression, "  len([1, 2]) == 2  ");
+        assert_fails(Rule::expression, "1 + 2 extra"); // trailing input
+        assert_fails(Rule::expression, "");
+    }
 }
\ No newline at end of file
@@ -0,0 +1,101 @@
+use std::{ collections::BTreeMap, fmt::Write };
+use crate::engine::{ error::EngineError, lang::ast::Value };
+
+/// Key of the single-field object that stands for `Value::Bytes` in JSON,
+/// holding the bytes as lowercase hex, e.g. `{"$bytes": "00ff"}`.
+pub const BYTES_TAG: &str = "$bytes";
+
+fn bytes_to_json(bytes: &[u8]) -> serde_json::Value {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    serde_json::json!({ BYTES_TAG: hex })
+}
+
+fn bytes_from_hex(hex: &str) -> Result<Vec<u8>, EngineError> {
+    let invalid = || EngineError::Serialization(format!("Invalid {} value '{}': expected hex digit pairs", BYTES_TAG, hex));
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+        .collect()
+}
+
+/// JSON numbers must be integral and fit in an `i64`; floats are rejected
+/// rather than silently truncated. An object whose only field is `$bytes`
+/// is read back as `Value::Bytes`.
+impl TryFrom<serde_json::Value> for Value {
+    type Error = EngineError;
+
+    fn try_from(json: serde_json::Value) -> Result<Self, Self::Error> {
+        match json {
+            serde_json::Value::Null => Ok(Value::Null),
+            serde_json::Value::Bool(b) => Ok(Value::Bool(b)),
+            serde_json::Value::Number(n) => n
+                .as_i64()
+                .map(Value::Number)
+                .ok_or_else(|| {
+                    EngineError::Serialization(format!("Cannot represent number {} as an integer", n))
+                }),
+            serde_json::Value::String(s) => Ok(Value::String(s)),
+            serde_json::Value::Array(items) => {
+                let values = items
+                    .into_iter()
+                    .map(Value::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(Value::List(values))
+            }
+            serde_json::Value::Object(fields) => {
+                if fields.len() == 1
+                    && let Some(serde_json::Value::String(hex)) = fields.get(BYTES_TAG)
+                {
+                    return bytes_from_hex(hex).map(Value::Bytes);
+                }
+                let mut map = BTreeMap::new();
+                for (key, value) in fields {
+                    map.insert(key, Value::try_from(value)?);
+                }
+                Ok(Value::Map(map))
+            }
+        }
+    }
+}
+
+/// Functions have no JSON representation and produce an error, including
+/// when nested inside a list or map. Bytes become a `$bytes` object.
+impl TryFrom<&Value> for serde_json::Value {
+    type Error = EngineError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => Ok(serde_json::Value::from(*n)),
+            Value::String(s) => Ok(serde_json::Value::String(s.clone())),
+            Value::Bool(b) => Ok(serde_json::Value::Bool(*b)),
+            Value::Null => Ok(serde_json::Value::Null),
+            Value::List(items) => {
+                let values = items
+                    .iter()
+                    .map(serde_json::Value::try_from)
+                    .collect::<Result<Vec<_>, _>>()?;
+                Ok(serde_json::Value::Array(values))
+            }
+            Value::Map(map) => {
+                let mut fields = serde_json::Map::new();
+                for (key, value) in map {
+                    fields.insert(key.clone(), serde_json::Value::try_from(value)?);
+                }
+                Ok(serde_json::Value::Object(fields))
+            }
+            Value::Bytes(bytes) => Ok(bytes_to_json(bytes)),
+            Value::BuiltinFunction(_) => {
+                Err(EngineError::Serialization("Cannot convert a builtin function to JSON".to_string()))
+            }
+            Value::UserFunction(f) => {
+                Err(EngineError::Serialization(format!("Cannot convert function '{}' to JSON", f.name)))
+            }
+        }
+    }
+}
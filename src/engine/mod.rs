@@ -1,6 +1,22 @@
 pub mod core;
 pub mod vm;
 pub mod lang;
+pub mod error;
+pub mod report;
+pub mod options;
+pub mod validation;
+pub mod scorer;
+pub mod describe;
+pub mod program_cache;
+
+#[cfg(test)]
+mod tests;
 
 pub use core::CoreEngine;
 pub use vm::CoreVM;
+pub use error::{ EngineError, CaseError };
+pub use report::ExecutionReport;
+pub use options::{ EngineOptions, FunctionScope, OnCaseError };
+pub use validation::{ Diagnostic, Severity, ValidationOptions };
+pub use scorer::Scorer;
+pub use describe::WorkflowDescription;
@@ -0,0 +1,54 @@
+/// What a run does when evaluating one case fails.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnCaseError {
+    /// Stop the run and return the error
+    #[default]
+    Abort,
+    /// Record the error and carry on with the remaining cases
+    Skip,
+}
+
+/// Which bindings a user function body sees besides its parameters and locals.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FunctionScope {
+    /// Everything visible at the call site, including the case being
+    /// evaluated and the caller's locals
+    #[default]
+    Dynamic,
+    /// Only the scopes the function was defined in, so a case field or
+    /// caller local can't hide a global the function reads
+    Lexical,
+}
+
+/// Settings that change how workflows execute.
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    pub on_case_error: OnCaseError,
+    /// With `OnCaseError::Skip`, remove failed cases from the batch instead of
+    /// keeping them as they were before the failing phase
+    pub drop_failed_cases: bool,
+    /// Make `CoreEngine::add_case` fail instead of adding a second case with an
+    /// id that is already loaded
+    pub reject_duplicate_ids: bool,
+    /// Make `CoreEngine::execute_program` remove the user functions of earlier
+    /// programs before registering its own, so reloaded rules can't call stale ones
+    pub replace_user_functions: bool,
+    /// What user function bodies can see besides their own frame; dynamic by
+    /// default, see `FunctionScope`
+    pub function_scope: FunctionScope,
+    /// Bind the `cases` map of batch aggregates (`cases.count`,
+    /// `cases.avg_priority`, ...) in a scope of its own around every phase,
+    /// hiding any variable named `cases` until the phase ends. Off by default
+    /// since it costs a pass over the batch per phase
+    pub batch_aggregates: bool,
+    /// Track which scope binds each name during a workflow run, so variable
+    /// reads skip walking the scope stack. Every binding then costs an extra
+    /// map update, so it only pays off when rules read names bound far below
+    /// the innermost scope much more often than they bind names; compare with
+    /// `cargo bench --bench ident_resolution` before turning it on
+    pub resolution_cache: bool,
+    /// Offset of local time from UTC in minutes, e.g. `-300` for UTC-5.
+    /// `day_of_week` and `is_business_hours` read timestamps in this local
+    /// time; it is fixed, so daylight saving changes are not applied
+    pub utc_offset_minutes: i32,
+}
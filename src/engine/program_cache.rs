@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use crate::engine::lang::ast::Program;
+
+/// Most programs kept; storing one more evicts the least recently used.
+pub const PROGRAM_CACHE_CAPACITY: usize = 64;
+
+/// Parsed programs keyed by their full source text, so two sources can never
+/// share an entry. Only programs that parsed are stored.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramCache {
+    programs: HashMap<String, (Program, u64)>,
+    /// Bumped on every hit and insert; an entry's stamp records its last use
+    clock: u64,
+}
+
+impl ProgramCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, source: &str) -> Option<Program> {
+        self.clock += 1;
+        let (program, last_used) = self.programs.get_mut(source)?;
+        *last_used = self.clock;
+        Some(program.clone())
+    }
+
+    pub fn insert(&mut self, source: &str, program: Program) {
+        self.clock += 1;
+        if self.programs.len() >= PROGRAM_CACHE_CAPACITY && !self.programs.contains_key(source) {
+            let oldest = self.programs
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(source, _)| source.clone());
+            if let Some(oldest) = oldest {
+                self.programs.remove(&oldest);
+            }
+        }
+        self.programs.insert(source.to_string(), (program, self.clock));
+    }
+
+    pub fn contains(&self, source: &str) -> bool {
+        self.programs.contains_key(source)
+    }
+
+    pub fn len(&self) -> usize {
+        self.programs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+}
@@ -0,0 +1,32 @@
+use std::collections::BTreeMap;
+use serde::Serialize;
+use crate::{
+    engine::{
+        core::EngineStats,
+        error::{ CaseError, EngineError },
+        vm::trace::TraceEvent,
+    },
+    models::case::CaseConfig,
+};
+
+/// Everything a run produced, in a shape that serializes with a stable field order.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExecutionReport {
+    pub cases: Vec<CaseConfig>,
+    pub assignments: BTreeMap<String, Vec<i32>>,
+    pub logs: Vec<String>,
+    pub stats: EngineStats,
+    pub variables: BTreeMap<String, serde_json::Value>,
+    /// Present only when tracing was enabled for the run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<Vec<TraceEvent>>,
+    /// Cases skipped under `OnCaseError::Skip`; omitted when empty
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub case_errors: Vec<CaseError>,
+}
+
+impl ExecutionReport {
+    pub fn to_json(&self) -> Result<String, EngineError> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
@@ -0,0 +1,71 @@
+use std::{ collections::{ HashMap, HashSet }, sync::Arc };
+use crate::{
+    engine::{
+        lang::ast::Workflow,
+        options::{ EngineOptions, OnCaseError },
+        vm::{ context::VmContext, environment::Environment, stack::VmStack, rng::Rng, clock::Clock, evaluators::WorkflowEvaluator },
+    },
+    models::case::CaseConfig,
+};
+
+/// A frozen engine that scores one case at a time, built by
+/// `CoreEngine::into_scorer`. It runs the engine's registered workflows with
+/// its functions, variables and settings, and never changes afterwards, so a
+/// single `Scorer` can be shared between threads.
+pub struct Scorer {
+    workflows: Vec<Workflow>,
+    env: Environment,
+    options: EngineOptions,
+    rng: Rng,
+    clock: Clock,
+    priority_weights: Arc<HashMap<i32, i64>>,
+    memoized: Arc<HashSet<String>>,
+    globals: Arc<HashSet<String>>,
+}
+
+impl Scorer {
+    pub(crate) fn new(workflows: Vec<Workflow>, context: VmContext) -> Self {
+        Self {
+            workflows,
+            env: context.env,
+            options: EngineOptions { on_case_error: OnCaseError::Abort, ..context.options },
+            rng: context.rng,
+            clock: context.clock,
+            priority_weights: context.priority_weights,
+            memoized: context.memoized,
+            globals: context.globals,
+        }
+    }
+
+    /// Run every registered workflow, in registration order, on `case` alone
+    /// and return its final score. Calls share the frozen scopes and copy one
+    /// only when a rule writes to it, so they can't see each other's changes.
+    /// Random draws are seeded from the engine's seed and the case id: each
+    /// case gets its own numbers, and scoring it again draws the same ones. A
+    /// failing case is always an error, whatever `on_case_error` says, and so
+    /// is a case that a `filter` or `sample` phase drops.
+    pub fn score(&self, case: &CaseConfig) -> Result<i64, String> {
+        let mut context = VmContext::new(VmStack::default(), self.env.clone());
+        context.env.enter_scope();
+        context.options = self.options.clone();
+        context.rng = self.rng.derive(case.id as u64);
+        context.clock = self.clock;
+        context.priority_weights = Arc::clone(&self.priority_weights);
+        context.memoized = Arc::clone(&self.memoized);
+        context.globals = Arc::clone(&self.globals);
+
+        let mut cases = vec![case.clone()];
+        for workflow in &self.workflows {
+            cases = WorkflowEvaluator::execute_workflow(&mut context, workflow, cases)?;
+            if cases.is_empty() {
+                return Err(format!("Case {} was dropped by workflow '{}'", case.id, workflow.name));
+            }
+        }
+        Ok(cases[0].score)
+    }
+
+    /// Names of the workflows `score` runs, in order.
+    pub fn workflow_names(&self) -> Vec<String> {
+        self.workflows.iter().map(|w| w.name.clone()).collect()
+    }
+}
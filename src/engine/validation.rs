@@ -0,0 +1,235 @@
+use std::collections::BTreeSet;
+use serde::Serialize;
+use crate::engine::{
+    lang::ast::{ Expr, FunctionBody, FunctionDef, Phase, Program, Statement, Workflow },
+    vm::evaluators::{ BuiltinFunctions, workflow_evaluator::BATCH_AGGREGATES_VAR },
+};
+
+/// Names bound for every case while rules run. A parameter or local of the
+/// same name hides the case field inside the function.
+pub const CASE_FIELDS: &[&str] = &["id", "category", "status", "priority", "score", "customer", "created_at", "rank", "scores", "assigned_to"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in workflow source.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Workflow the problem belongs to, when it is tied to one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workflow: Option<String>,
+    /// 1-based source position, known for parse errors
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    /// A diagnostic that isn't tied to a workflow or a source position.
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Diagnostic { severity, workflow: None, line: None, column: None, message: message.into() }
+    }
+
+    pub fn warning(workflow: &Workflow, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            workflow: Some(workflow.name.clone()),
+            line: None,
+            column: None,
+            message: message.into(),
+        }
+    }
+}
+
+/// Optional checks run by `validate_program`. All are off by default, since
+/// they can flag programs that are intentionally written that way.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationOptions {
+    /// Run `validate_phase_order` on every workflow
+    pub check_phase_order: bool,
+    /// Report user functions named after a built-in as warnings, not errors
+    pub allow_builtin_shadowing: bool,
+}
+
+/// Check every function with `validate_function`, then run the checks
+/// enabled in `options`.
+pub fn validate_program(program: &Program, options: &ValidationOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for function in &program.functions {
+        diagnostics.extend(validate_function(function, options));
+    }
+    for global in &program.globals {
+        if CASE_FIELDS.contains(&global.name.as_str()) {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                format!("global '{}' is hidden by the case field of the same name while rules run", global.name),
+            ));
+        }
+        if global.name == BATCH_AGGREGATES_VAR {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                format!("global '{}' is hidden by the batch aggregates while phases run with batch_aggregates on", global.name),
+            ));
+        }
+    }
+    if options.check_phase_order {
+        for workflow in &program.workflows {
+            diagnostics.extend(validate_phase_order(workflow));
+        }
+    }
+    diagnostics
+}
+
+/// Report names in `function` that hide something else: the function's own
+/// name when it matches a built-in (an error, unless
+/// `allow_builtin_shadowing` is set) or a case field, and parameters or
+/// `let` locals named after a case field.
+pub fn validate_function(function: &FunctionDef, options: &ValidationOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let name = function.name.as_str();
+
+    if BuiltinFunctions::is_builtin(name) {
+        let severity = if options.allow_builtin_shadowing { Severity::Warning } else { Severity::Error };
+        diagnostics.push(Diagnostic::new(
+            severity,
+            format!("function '{}' shadows the built-in function of the same name", name),
+        ));
+    }
+    if CASE_FIELDS.contains(&name) {
+        diagnostics.push(Diagnostic::new(
+            Severity::Warning,
+            format!("function '{}' is hidden by the case field of the same name while rules run", name),
+        ));
+    }
+
+    for param in &function.params {
+        if CASE_FIELDS.contains(&param.as_str()) {
+            diagnostics.push(Diagnostic::new(
+                Severity::Warning,
+                format!("function '{}': parameter '{}' shadows the case field of the same name", name, param),
+            ));
+        }
+    }
+
+    if let FunctionBody::Block(statements) = &function.body {
+        let mut locals = Vec::new();
+        collect_locals(statements, &mut locals);
+        for local in locals {
+            if CASE_FIELDS.contains(&local) {
+                diagnostics.push(Diagnostic::new(
+                    Severity::Warning,
+                    format!("function '{}': local '{}' shadows the case field of the same name", name, local),
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+fn collect_locals<'a>(statements: &'a [Statement], locals: &mut Vec<&'a str>) {
+    for statement in statements {
+        match statement {
+            Statement::Let { name, .. } => locals.push(name),
+            Statement::If { then_body, else_body, .. } => {
+                collect_locals(then_body, locals);
+                if let Some(else_body) = else_body {
+                    collect_locals(else_body, locals);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Warn about `sort` phases keyed on `score` that run before a later `score`
+/// phase of the same workflow, since they order cases by stale scores.
+pub fn validate_phase_order(workflow: &Workflow) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    for (index, phase) in workflow.phases.iter().enumerate() {
+        let Phase::Sort(sort_rule) = phase else { continue };
+        let scored_later = workflow.phases[index + 1..]
+            .iter()
+            .any(|later| matches!(later, Phase::Score { .. }));
+
+        if scored_later && mentions_score(&sort_rule.key) {
+            diagnostics.push(Diagnostic::warning(
+                workflow,
+                format!("sort by '{}' runs before a score phase that changes the score", sort_rule.key),
+            ));
+        }
+    }
+    diagnostics
+}
+
+/// Variables an expression reads and functions it calls. Member access is
+/// recorded as `object.property`; names bound by `let ... in` are left out
+/// inside their body.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct References {
+    pub variables: BTreeSet<String>,
+    pub functions: BTreeSet<String>,
+}
+
+impl References {
+    pub fn collect(&mut self, expr: &Expr) {
+        self.collect_unbound(expr, &mut Vec::new());
+    }
+
+    pub fn extend(&mut self, other: &References) {
+        self.variables.extend(other.variables.iter().cloned());
+        self.functions.extend(other.functions.iter().cloned());
+    }
+
+    fn collect_unbound(&mut self, expr: &Expr, bound: &mut Vec<String>) {
+        match expr {
+            Expr::Ident(name) => {
+                if !bound.contains(name) {
+                    self.variables.insert(name.clone());
+                }
+            }
+            Expr::MemberAccess { object, property } => {
+                if !bound.contains(object) {
+                    self.variables.insert(format!("{}.{}", object, property));
+                }
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                self.collect_unbound(left, bound);
+                self.collect_unbound(right, bound);
+            }
+            Expr::UnaryOp { expr, .. } => self.collect_unbound(expr, bound),
+            Expr::FunctionCall { name, args } => {
+                self.functions.insert(name.clone());
+                for arg in args {
+                    self.collect_unbound(arg, bound);
+                }
+            }
+            Expr::List(items) => {
+                for item in items {
+                    self.collect_unbound(item, bound);
+                }
+            }
+            Expr::LetIn { name, value, body } => {
+                self.collect_unbound(value, bound);
+                bound.push(name.clone());
+                self.collect_unbound(body, bound);
+                bound.pop();
+            }
+            Expr::Range { start, end, .. } => {
+                self.collect_unbound(start, bound);
+                self.collect_unbound(end, bound);
+            }
+            Expr::Number(_) | Expr::String(_) | Expr::Bool(_) => {}
+        }
+    }
+}
+
+fn mentions_score(expr: &Expr) -> bool {
+    let mut references = References::default();
+    references.collect(expr);
+    references.variables.contains("score") || references.variables.contains("case.score")
+}
@@ -0,0 +1,23 @@
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+/// Source of the current time for `now()`, in seconds since the Unix epoch.
+/// Tests and replays use `Fixed` so time-based rules are deterministic.
+/// `System` needs a platform clock and panics on wasm32-unknown-unknown, so
+/// browser hosts set a fixed time with `WasmEngine::set_time`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Clock {
+    #[default]
+    System,
+    Fixed(i64),
+}
+
+impl Clock {
+    pub fn now(&self) -> i64 {
+        match self {
+            Clock::System => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs() as i64),
+            Clock::Fixed(secs) => *secs,
+        }
+    }
+}
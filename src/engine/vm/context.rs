@@ -1,27 +1,118 @@
-use crate::engine::vm::{ stack::VmStack, environment::Environment };
+use std::{ collections::{ BTreeMap, HashMap, HashSet }, sync::Arc, time::Duration };
+use crate::models::case::CaseConfig;
+use crate::engine::{
+    error::CaseError,
+    lang::ast::PhaseKind,
+    options::{ EngineOptions, OnCaseError },
+    vm::{ stack::VmStack, environment::Environment, trace::TraceEvent, rng::Rng, clock::Clock, memo::MemoCache },
+};
 
 #[derive(Default)]
 pub struct VmContext {
     pub stack: VmStack,
     pub env: Environment,
+    /// Messages emitted by `log` actions, in execution order
+    pub logs: Vec<String>,
+    /// Case ids routed by match phases, keyed by target name
+    pub assignments: BTreeMap<String, Vec<i32>>,
+    /// Cases as they were when routed by match phases, keyed by target name
+    pub routed: BTreeMap<String, Vec<CaseConfig>>,
+    /// When set, each executed phase records its duration in `phase_timings`
+    pub timing: bool,
+    pub phase_timings: Vec<(PhaseKind, Duration)>,
+    /// Rule evaluations, recorded only while tracing is enabled
+    pub trace: Option<Vec<TraceEvent>>,
+    /// Name of the workflow currently executing
+    pub current_workflow: String,
+    pub options: EngineOptions,
+    /// Failures of individual cases skipped under `OnCaseError::Skip`
+    pub case_errors: Vec<CaseError>,
+    /// Source of randomness for sampling; seeded so runs are reproducible
+    pub rng: Rng,
+    /// Time source for `now()`
+    pub clock: Clock,
+    /// Weights returned by `priority_weight()`; unlisted priorities weigh their own value
+    pub priority_weights: Arc<HashMap<i32, i64>>,
+    /// User functions cached as if declared `pure`, by name
+    pub memoized: Arc<HashSet<String>>,
+    /// Cached results of pure and memoized user functions
+    pub memo: MemoCache,
+    /// Variables declared with `global`, which rules may update
+    pub globals: Arc<HashSet<String>>,
+    /// Index of the rule after the one the last case took in the running
+    /// round-robin match phase
+    pub round_robin_next: usize,
+    /// Cases routed to each agent of the pool, by agent id
+    pub agent_loads: BTreeMap<String, u32>,
 }
 
 impl VmContext {
     pub fn new(stack: VmStack, env: Environment) -> Self {
-        Self { stack, env }
+        Self {
+            stack,
+            env,
+            logs: Vec::new(),
+            assignments: BTreeMap::new(),
+            routed: BTreeMap::new(),
+            timing: false,
+            phase_timings: Vec::new(),
+            trace: None,
+            current_workflow: String::new(),
+            options: EngineOptions::default(),
+            case_errors: Vec::new(),
+            rng: Rng::default(),
+            clock: Clock::default(),
+            priority_weights: Arc::default(),
+            memoized: Arc::default(),
+            memo: MemoCache::new(),
+            globals: Arc::default(),
+            round_robin_next: 0,
+            agent_loads: BTreeMap::new(),
+        }
     }
 
-    pub fn default() -> Self 
-    where
-        VmStack: Default,
-        Environment: Default,
-    {
-        Self {
-            stack: VmStack::default(),
-            env: Environment::default(),
+    pub fn record_trace(
+        &mut self,
+        phase: PhaseKind,
+        case_id: i32,
+        rule_index: usize,
+        rule: &dyn std::fmt::Display,
+        fired: bool,
+        score: i64
+    ) {
+        if let Some(events) = &mut self.trace {
+            events.push(TraceEvent {
+                workflow: self.current_workflow.clone(),
+                phase,
+                case_id,
+                rule_index,
+                rule: rule.to_string(),
+                fired,
+                score,
+            });
+        }
+    }
+
+    /// Apply the configured `on_case_error` policy to a failed case. Returns
+    /// the error under `Abort`; otherwise records it and returns whether the
+    /// case should stay in the batch.
+    pub fn handle_case_error(&mut self, phase: PhaseKind, case_id: i32, error: String) -> Result<bool, String> {
+        match self.options.on_case_error {
+            OnCaseError::Abort => Err(error),
+            OnCaseError::Skip => {
+                tracing::debug!("Skipping case {} after {:?} error: {}", case_id, phase, error);
+                self.case_errors.push(CaseError { case_id, phase, error });
+                Ok(!self.options.drop_failed_cases)
+            }
         }
     }
 
+    /// Whether a case that fails a phase stays in the batch as it was before
+    /// the phase, so the phase has to keep a copy of it.
+    pub fn keeps_failed_cases(&self) -> bool {
+        self.options.on_case_error == OnCaseError::Skip && !self.options.drop_failed_cases
+    }
+
     pub fn stack(&self) -> &VmStack {
         &self.stack
     }
@@ -1,7 +1,9 @@
+use std::sync::Arc;
 use crate::{
     engine::{
         vm::{
             context::VmContext,
+            environment::Environment,
             evaluators::{
                 expr_evaluator::ExprEvaluator,
                 workflow_evaluator::WorkflowEvaluator,
@@ -11,7 +13,7 @@ use crate::{
         },
         lang::ast::{Workflow, Expr, Value, FunctionDef, Program},
     },
-    models::case::CaseConfig,
+    models::{ case::CaseConfig, types::WorkflowResult },
 };
 
 
@@ -24,31 +26,45 @@ impl CoreVM {
         let mut vm = Self { 
             context: VmContext::default(),
         };
-        // Initialize with a global scope for built-in functions
-        vm.context.env.enter_scope();
-        // Register built-in functions in the environment
-        let builtin_functions = BuiltinFunctions::register_all();
-        for (name, func) in builtin_functions {
-            vm.context.env.insert(name, Value::BuiltinFunction(func));
-        }
+        vm.context.env = Self::builtin_environment();
         vm
     }
 
+    /// A fresh environment holding a single global scope with the built-in
+    /// functions registered.
+    pub fn builtin_environment() -> Environment {
+        let mut env = Environment::default();
+        env.enter_scope();
+        for (name, func) in BuiltinFunctions::register_all() {
+            env.insert(name, Value::BuiltinFunction(func));
+        }
+        env
+    }
+
     pub fn run(&mut self) -> Result<Vec<CaseConfig>, String> {
         Ok(self.context.stack.cases.clone())
     }
 
     /// Execute a workflow on the current cases in the stack
     pub fn execute_workflow(&mut self, workflow: &Workflow) -> Result<(), String> {
+        self.run_workflow(workflow, false)
+    }
+
+    /// Execute `workflow` even when its guard is falsy.
+    pub fn force_execute_workflow(&mut self, workflow: &Workflow) -> Result<(), String> {
+        self.run_workflow(workflow, true)
+    }
+
+    fn run_workflow(&mut self, workflow: &Workflow, force: bool) -> Result<(), String> {
         // Clone the cases to avoid borrowing issues
         let cases = self.context.stack.cases.clone();
         
         // Use the workflow evaluator
-        let processed_cases = WorkflowEvaluator::execute_workflow(
-            &mut self.context,
-            workflow,
-            cases,
-        )?;
+        let processed_cases = if force {
+            WorkflowEvaluator::execute_workflow_unguarded(&mut self.context, workflow, cases)?
+        } else {
+            WorkflowEvaluator::execute_workflow(&mut self.context, workflow, cases)?
+        };
         
         // Update the stack with processed cases
         self.context.stack.cases = processed_cases;
@@ -60,9 +76,9 @@ impl CoreVM {
         WorkflowEvaluator::setup_case_context(&mut self.context, case)
     }
 
-    /// Execute a score phase
+    /// Execute a score phase without an `otherwise` action
     pub fn execute_score_phase(&mut self, rules: &[crate::engine::lang::ast::Rule], case: &mut CaseConfig) -> Result<(), String> {
-        WorkflowEvaluator::execute_score_phase(&mut self.context, rules, case)
+        WorkflowEvaluator::execute_score_phase(&mut self.context, rules, None, case)
     }
 
     /// Execute a match phase
@@ -80,10 +96,14 @@ impl CoreVM {
         ExprEvaluator::evaluate_expr(&mut self.context, expr)
     }
 
-    /// Register a user-defined function
+    /// Register a user-defined function. Cached results of every function are
+    /// dropped, since a pure function may call the one being replaced.
     pub fn register_function(&mut self, function: FunctionDef) {
-        let name = function.name.clone();
-        self.context.env.insert(name, Value::UserFunction(function));
+        if BuiltinFunctions::is_builtin(&function.name) {
+            tracing::warn!("function '{}' shadows the built-in function of the same name", function.name);
+        }
+        self.context.memo.clear();
+        self.context.env.insert(function.name.clone(), Value::UserFunction(function));
     }
 
     /// Register multiple user-defined functions
@@ -93,10 +113,47 @@ impl CoreVM {
         }
     }
 
+    /// Remove every user function bound as `name`, in any scope. Returns
+    /// whether one was removed.
+    pub fn unregister_function(&mut self, name: &str) -> bool {
+        let mut removed = false;
+        for scope in &mut self.context.env.env {
+            if matches!(scope.get(name), Some(Value::UserFunction(_))) {
+                Arc::make_mut(scope).remove(name);
+                removed = true;
+            }
+        }
+        if removed {
+            self.context.memo.clear();
+        }
+        removed
+    }
+
+    /// Bind the program's `global` variables to their initial values in the
+    /// global scope, replacing values left by an earlier run.
+    pub fn declare_globals(&mut self, program: &Program) -> Result<(), String> {
+        for global in &program.globals {
+            let value = ExprEvaluator::evaluate_expr(&mut self.context, &global.value)
+                .map_err(|e| format!("global '{}': {}", global.name, e))?;
+            self.context.env.insert_global(global.name.clone(), value);
+            Arc::make_mut(&mut self.context.globals).insert(global.name.clone());
+        }
+        Ok(())
+    }
+
+    /// Remove all user functions, keeping built-ins and variables.
+    pub fn clear_user_functions(&mut self) {
+        for scope in &mut self.context.env.env {
+            Arc::make_mut(scope).retain(|_, value| !matches!(value, Value::UserFunction(_)));
+        }
+        self.context.memo.clear();
+    }
+
     /// Execute a program (functions + workflows)
     pub fn execute_program(&mut self, program: &Program) -> Result<(), String> {
         // Register user-defined functions first
         self.register_functions(program.functions.clone());
+        self.declare_globals(program)?;
         
         // Execute all workflows
         for workflow in &program.workflows {
@@ -106,16 +163,52 @@ impl CoreVM {
         Ok(())
     }
 
+    /// Like `execute_program`, but returns what each workflow routed and
+    /// logged, paired with the workflow's name. Routed cases are listed by
+    /// target name, then in assignment order.
+    pub fn execute_program_with_results(&mut self, program: &Program) -> Result<Vec<(String, WorkflowResult)>, String> {
+        self.register_functions(program.functions.clone());
+        self.declare_globals(program)?;
+
+        let mut results = Vec::with_capacity(program.workflows.len());
+        for workflow in &program.workflows {
+            let logs_before = self.context.logs.len();
+            let routed_before: Vec<(String, usize)> = self
+                .context
+                .routed
+                .iter()
+                .map(|(target, cases)| (target.clone(), cases.len()))
+                .collect();
+
+            self.execute_workflow(workflow)?;
+
+            let routed = self
+                .context
+                .routed
+                .iter()
+                .flat_map(|(target, cases)| {
+                    let seen = routed_before
+                        .iter()
+                        .find(|(name, _)| name == target)
+                        .map_or(0, |(_, len)| *len);
+                    cases[seen..].iter().cloned()
+                })
+                .collect();
+            let logs = self.context.logs[logs_before..].to_vec();
+            results.push((workflow.name.clone(), WorkflowResult { routed, logs }));
+        }
+
+        Ok(results)
+    }
+
     /// Get all function names (both built-in and user-defined)
     pub fn get_function_names(&self) -> Vec<String> {
         let mut names = Vec::new();
         for scope in &self.context.env.env {
-            for (key, value) in scope {
+            for (key, value) in scope.iter() {
                 match value {
-                    Value::BuiltinFunction(_) | Value::UserFunction(_) => {
-                        if !names.contains(key) {
-                            names.push(key.clone());
-                        }
+                    Value::BuiltinFunction(_) | Value::UserFunction(_) if !names.contains(key) => {
+                        names.push(key.clone());
                     }
                     _ => {}
                 }
@@ -129,11 +222,9 @@ impl CoreVM {
     pub fn get_user_function_names(&self) -> Vec<String> {
         let mut names = Vec::new();
         for scope in &self.context.env.env {
-            for (key, value) in scope {
-                if matches!(value, Value::UserFunction(_)) {
-                    if !names.contains(key) {
-                        names.push(key.clone());
-                    }
+            for (key, value) in scope.iter() {
+                if matches!(value, Value::UserFunction(_)) && !names.contains(key) {
+                    names.push(key.clone());
                 }
             }
         }
@@ -159,6 +250,12 @@ impl CoreVM {
     }
 }
 
+impl Default for CoreVM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 pub trait CoreEval {
     fn evaluate_expr(&mut self, expr: &Expr) -> Result<Value, String>;
     fn execute_workflow(&mut self, workflow: &Workflow) -> Result<(), String>;
@@ -1,27 +1,123 @@
-use std::collections::HashMap;
+use std::{ collections::HashMap, fmt::Write, sync::Arc };
 use crate::engine::lang::ast::Value;
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Environment {
-    pub env: Vec<HashMap<String, Value>>,
+    /// Scopes are shared between clones and copied on first write, so
+    /// cloning an environment only copies the scopes it then changes.
+    pub env: Vec<Arc<HashMap<String, Value>>>,
+    /// While the resolution cache is on, the scope that last bound each name.
+    /// An entry whose scope has since been closed or lacks the name is stale
+    /// and `lookup` walks the scopes instead; an inner binding always
+    /// updates the entry, so a live one is the innermost.
+    resolved: Option<HashMap<String, usize>>,
 }
 
 impl Environment {
     pub fn new() -> Self {
-        let mut env: Environment = Environment { env: Vec::new() };
+        let mut env: Environment = Environment { env: Vec::new(), resolved: None };
         env.enter_scope();
         env
     }
 
+    /// Remember where names are bound as they are inserted, so `lookup`
+    /// doesn't walk the scopes for them. Only worth it while many lookups
+    /// happen between scope changes, as in a workflow run.
+    pub fn enable_resolution_cache(&mut self) {
+        let mut resolved = HashMap::new();
+        for (index, scope) in self.env.iter().enumerate() {
+            for name in scope.keys() {
+                resolved.insert(name.clone(), index);
+            }
+        }
+        self.resolved = Some(resolved);
+    }
+
+    pub fn disable_resolution_cache(&mut self) {
+        self.resolved = None;
+    }
+
     pub fn enter_scope(&mut self) {
-        self.env.push(HashMap::new());
+        self.env.push(Arc::default());
     }
 
     pub fn exit_scope(&mut self) {
         self.env.pop();
     }
 
+    /// Leave the innermost scope, moving its bindings into the scope below
+    /// except `name`, which goes out of scope with it.
+    pub fn exit_scope_except(&mut self, name: &str) {
+        let Some(scope) = self.env.pop() else {
+            return;
+        };
+        let mut scope = Arc::unwrap_or_clone(scope);
+        scope.remove(name);
+        let parent_index = self.env.len().saturating_sub(1);
+        if let Some(resolved) = &mut self.resolved {
+            for moved in scope.keys() {
+                Self::resolve(resolved, moved, parent_index);
+            }
+        }
+        if let Some(parent) = self.env.last_mut() {
+            Arc::make_mut(parent).extend(scope);
+        }
+    }
+
+    /// Detach the scopes above `index`, innermost last, so that only the
+    /// scopes up to it stay visible until `restore_scopes`.
+    pub fn hide_scopes_above(&mut self, index: usize) -> Vec<Arc<HashMap<String, Value>>> {
+        self.env.split_off((index + 1).min(self.env.len()))
+    }
+
+    /// Put back scopes taken by `hide_scopes_above`.
+    pub fn restore_scopes(&mut self, hidden: Vec<Arc<HashMap<String, Value>>>) {
+        let base = self.env.len();
+        if let Some(resolved) = &mut self.resolved {
+            for (offset, scope) in hidden.iter().enumerate() {
+                for name in scope.keys() {
+                    Self::resolve(resolved, name, base + offset);
+                }
+            }
+        }
+        self.env.extend(hidden);
+    }
+
+    fn resolve(resolved: &mut HashMap<String, usize>, name: &str, index: usize) {
+        match resolved.get_mut(name) {
+            Some(entry) => *entry = index,
+            None => {
+                resolved.insert(name.to_string(), index);
+            }
+        }
+    }
+
+    /// Number of open scopes, including the global one.
+    pub fn depth(&self) -> usize {
+        self.env.len()
+    }
+
+    /// Render every scope, outermost first, with names sorted. Values use their
+    /// `Display` form, so functions appear as signatures rather than bodies.
+    pub fn describe(&self) -> String {
+        let mut out = String::new();
+        for (depth, scope) in self.env.iter().enumerate() {
+            let _ = writeln!(out, "scope {}:", depth);
+            let mut names: Vec<&String> = scope.keys().collect();
+            names.sort();
+            for name in names {
+                let _ = writeln!(out, "  {} = {}", name, scope[name]);
+            }
+        }
+        out
+    }
+
     pub fn lookup(&self, name: &str) -> Option<&Value> {
+        if let Some(index) = self.resolved.as_ref().and_then(|resolved| resolved.get(name))
+            && let Some(value) = self.env.get(*index).and_then(|scope| scope.get(name))
+        {
+            return Some(value);
+        }
         for scope in self.env.iter().rev() {
             if let Some(val) = scope.get(name) {
                 return Some(val);
@@ -30,17 +126,47 @@ impl Environment {
         None
     }
 
+    /// Index of the innermost scope binding `name`, counting from the global one.
+    pub fn scope_of(&self, name: &str) -> Option<usize> {
+        self.env.iter().rposition(|scope| scope.contains_key(name))
+    }
+
     pub fn insert(&mut self, name: impl Into<String>, value: Value) {
+        let depth = self.env.len();
         if let Some(scope) = self.env.last_mut() {
-            scope.insert(name.into(), value);
+            let name = name.into();
+            if let Some(resolved) = &mut self.resolved {
+                Self::resolve(resolved, &name, depth - 1);
+            }
+            Arc::make_mut(scope).insert(name, value);
+        }
+    }
+
+    /// Bind `name` in the outermost scope, so it outlives any nested scopes.
+    pub fn insert_global(&mut self, name: impl Into<String>, value: Value) {
+        if let Some(scope) = self.env.first_mut() {
+            Arc::make_mut(scope).insert(name.into(), value);
+        }
+    }
+
+    /// Merge all scopes into one map, outer to inner so inner bindings win.
+    /// Names whose visible binding is a function are left out.
+    pub fn flatten(&self) -> HashMap<String, Value> {
+        let mut merged = HashMap::new();
+        for scope in &self.env {
+            for (name, value) in scope.iter() {
+                merged.insert(name.clone(), value.clone());
+            }
         }
+        merged.retain(|_, value| !matches!(value, Value::BuiltinFunction(_) | Value::UserFunction(_)));
+        merged
     }
 
     pub fn set(&mut self, name: impl Into<String>, value: Value) {
         let name = name.into();
         for scope in self.env.iter_mut().rev() {
             if scope.contains_key(&name) {
-                scope.insert(name.clone(), value);
+                Arc::make_mut(scope).insert(name.clone(), value);
                 return;
             }
         }
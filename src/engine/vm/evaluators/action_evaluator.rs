@@ -1,11 +1,11 @@
 use crate::{
     engine::{
-        lang::ast::{Action, MatchAction, Value},
+        lang::ast::{Action, Expr, GlobalUpdate, MatchAction, Value},
         vm::{context::VmContext, evaluators::expr_evaluator::ExprEvaluator},
     },
-    models::case::CaseConfig,
+    models::{agent::AgentConfig, case::CaseConfig},
 };
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 
 pub struct ActionEvaluator;
 
@@ -24,6 +24,9 @@ impl ActionEvaluator {
                         context.env.set("score", Value::Number(n));
                         tracing::debug!("Assigned score: {}", n);
                     }
+                    Value::Bool(_) => {
+                        return Err("cannot assign boolean to score; wrap with an int() cast or a conditional".to_string());
+                    }
                     _ => {
                         return Err("Score must be a number".to_string());
                     }
@@ -31,10 +34,23 @@ impl ActionEvaluator {
             }
             Action::Log(message) => {
                 tracing::debug!("LOG: {}", message);
+                context.logs.push(message.clone());
             }
             Action::Assign(var_name) => {
                 context.env.insert(var_name, Value::Bool(true));
             }
+            Action::Assert(condition) => {
+                ExprEvaluator::check_assertion(context, condition)?;
+            }
+            Action::AssignSubScore(name, expr) => {
+                let Value::Number(n) = ExprEvaluator::evaluate_expr(context, expr)? else {
+                    return Err(format!("Sub-score '{}' must be a number", name));
+                };
+                case.scores.insert(name.clone(), n);
+                context.env.set("scores", Self::sub_scores_value(case));
+                tracing::debug!("Assigned sub-score {}: {}", name, n);
+            }
+            Action::UpdateGlobal(update) => Self::update_global(context, update)?,
         }
         Ok(())
     }
@@ -46,16 +62,162 @@ impl ActionEvaluator {
     ) -> Result<(), String> {
         match action {
             MatchAction::AssignTo(var_name) => {
-                let case_map = Self::case_to_map(case);
-                context.env.insert(var_name, Value::Map(case_map));
-                tracing::debug!("Assigned case to variable: {}", var_name);
+                Self::assign_case_to(context, var_name, case);
+            }
+            MatchAction::AssignToDynamic(expr) => {
+                let var_name = match ExprEvaluator::evaluate_expr(context, expr)? {
+                    Value::String(name) => name,
+                    other => {
+                        return Err(format!("Queue name must evaluate to a string, got {:?}", other));
+                    }
+                };
+                Self::validate_queue_name(&var_name)?;
+                Self::assign_case_to(context, &var_name, case);
+            }
+            MatchAction::UpdateGlobal(update) => Self::update_global(context, update)?,
+            MatchAction::RouteToLeastLoaded(filter) => {
+                // A case routed before gives up its agent while the next one is
+                // picked, so routing it again doesn't count it twice
+                let released = case.assigned_to.clone().filter(|agent_id| Self::release_agent(context, agent_id));
+                match Self::least_loaded_agent(context, filter.as_ref()) {
+                    Ok(Some(agent_id)) => {
+                        *context.agent_loads.entry(agent_id.clone()).or_default() += 1;
+                        context.env.set("assigned_to", Value::String(agent_id.clone()));
+                        tracing::debug!("Routed case {} to agent {}", case.id, agent_id);
+                        case.assigned_to = Some(agent_id);
+                    }
+                    // A case no agent qualifies for keeps the agent it had, if any
+                    result => {
+                        if let Some(agent_id) = released {
+                            *context.agent_loads.entry(agent_id).or_default() += 1;
+                        }
+                        result?;
+                    }
+                }
             }
         }
         Ok(())
     }
 
-    fn case_to_map(case: &CaseConfig) -> HashMap<String, Value> {
-        let mut map = HashMap::new();
+    /// Take one case off `agent_id`'s count, returning whether it had any.
+    fn release_agent(context: &mut VmContext, agent_id: &str) -> bool {
+        match context.agent_loads.get_mut(agent_id) {
+            Some(load) if *load > 0 => {
+                *load -= 1;
+                if *load == 0 {
+                    context.agent_loads.remove(agent_id);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The pool agent with the fewest cases routed to it, among those
+    /// `filter` holds for. Ties at a nonzero load go to the agent with the
+    /// larger `max_concurrent`, since its share of capacity is smaller; any
+    /// other tie, including every agent at zero, goes to the lowest id.
+    fn least_loaded_agent(context: &mut VmContext, filter: Option<&Expr>) -> Result<Option<String>, String> {
+        let mut best: Option<(usize, u32)> = None;
+        for index in 0..context.stack.agents.len() {
+            let agent = &context.stack.agents[index];
+            let load = context.agent_loads.get(&agent.id).copied().unwrap_or(0);
+            if let Some(filter) = filter {
+                let candidate = Self::agent_to_map(agent, load);
+                context.env.enter_scope();
+                context.env.insert("agent", candidate);
+                let result = ExprEvaluator::evaluate_expr(context, filter);
+                context.env.exit_scope();
+                let result = result.map_err(|e| format!("Agent '{}': {}", context.stack.agents[index].id, e))?;
+                if !ExprEvaluator::is_truthy(&result) {
+                    continue;
+                }
+            }
+            let agents = &context.stack.agents;
+            let agent = &agents[index];
+            let less_loaded = best.is_none_or(|(current, current_load)| {
+                let current = &agents[current];
+                load.cmp(&current_load)
+                    .then_with(|| {
+                        let share = u64::from(load) * u64::from(current.max_concurrent);
+                        share.cmp(&(u64::from(current_load) * u64::from(agent.max_concurrent)))
+                    })
+                    .then_with(|| agent.id.cmp(&current.id))
+                    .is_lt()
+            });
+            if less_loaded {
+                best = Some((index, load));
+            }
+        }
+        Ok(best.map(|(index, _)| context.stack.agents[index].id.clone()))
+    }
+
+    /// An agent as rules see it: its id, `max_concurrent`, skill lists, all
+    /// skills combined as `skills`, and the cases routed to it so far
+    pub fn agent_to_map(agent: &AgentConfig, load: u32) -> Value {
+        let strings = |items: &[String]| Value::List(items.iter().cloned().map(Value::String).collect());
+        let skills = &agent.skills;
+        let all: Vec<String> = skills.languages.iter().chain(&skills.services).chain(&skills.platforms).cloned().collect();
+        let mut map = BTreeMap::new();
+        map.insert("id".to_string(), Value::String(agent.id.clone()));
+        map.insert("max_concurrent".to_string(), Value::Number(i64::from(agent.max_concurrent)));
+        map.insert("languages".to_string(), strings(&skills.languages));
+        map.insert("services".to_string(), strings(&skills.services));
+        map.insert("platforms".to_string(), strings(&skills.platforms));
+        map.insert("skills".to_string(), strings(&all));
+        map.insert("assignments".to_string(), Value::Number(i64::from(load)));
+        Value::Map(map)
+    }
+
+    /// Write a variable declared with `global` in the global scope, so the
+    /// value survives the case's scope.
+    pub fn update_global(context: &mut VmContext, update: &GlobalUpdate) -> Result<(), String> {
+        let name = match update {
+            GlobalUpdate::Set(name, _) | GlobalUpdate::Increment(name) => name,
+        };
+        if !context.globals.contains(name) {
+            return Err(format!("'{}' is not a global; declare it with 'global {} = ...'", name, name));
+        }
+        let value = match update {
+            GlobalUpdate::Set(_, expr) => ExprEvaluator::evaluate_expr(context, expr)?,
+            GlobalUpdate::Increment(_) => match context.env.lookup(name) {
+                Some(Value::Number(n)) => Value::Number(n.checked_add(1).ok_or_else(|| format!("Incrementing '{}' overflowed", name))?),
+                other => {
+                    let found = other.map_or("nothing".to_string(), |value| value.value_type().to_string());
+                    return Err(format!("Cannot increment '{}': it holds {}", name, found));
+                }
+            },
+        };
+        context.env.insert_global(name.clone(), value);
+        Ok(())
+    }
+
+    fn assign_case_to(context: &mut VmContext, var_name: &str, case: &CaseConfig) {
+        let case_map = Self::case_to_map(case);
+        context.env.insert(var_name, Value::Map(case_map));
+        context.assignments.entry(var_name.to_string()).or_default().push(case.id);
+        context.routed.entry(var_name.to_string()).or_default().push(case.clone());
+        tracing::debug!("Assigned case to variable: {}", var_name);
+    }
+
+    /// Dynamic queue names become variables, so they must look like identifiers
+    fn validate_queue_name(name: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("Queue name must not be empty".to_string());
+        }
+        if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid queue name '{}': only letters, digits and '_' are allowed", name));
+        }
+        Ok(())
+    }
+
+    /// The case's sub-scores as a map of numbers
+    pub fn sub_scores_value(case: &CaseConfig) -> Value {
+        Value::Map(case.scores.iter().map(|(name, n)| (name.clone(), Value::Number(*n))).collect())
+    }
+
+    fn case_to_map(case: &CaseConfig) -> BTreeMap<String, Value> {
+        let mut map = BTreeMap::new();
         map.insert("id".to_string(), Value::String(case.id.to_string()));
         map.insert("category".to_string(), Value::String(case.category.clone()));
         map.insert("status".to_string(), Value::String(case.status.clone()));
@@ -64,8 +226,21 @@ impl ActionEvaluator {
             Value::String(case.priority.to_string()),
         );
         map.insert("score".to_string(), Value::String(case.score.to_string()));
-        if let Some(customer) = &case.customer {
-            map.insert("customer".to_string(), Value::String(customer.clone()));
+        // Always present, so `queue.customer` reads null rather than failing
+        // for customer-less cases
+        let customer = case.customer.clone().map_or(Value::Null, Value::String);
+        map.insert("customer".to_string(), customer);
+        if let Some(agent_id) = &case.assigned_to {
+            map.insert("assigned_to".to_string(), Value::String(agent_id.clone()));
+        }
+        if let Some(created_at) = case.created_at {
+            map.insert("created_at".to_string(), Value::String(created_at.to_string()));
+        }
+        if let Some(rank) = case.rank {
+            map.insert("rank".to_string(), Value::String(rank.to_string()));
+        }
+        if !case.scores.is_empty() {
+            map.insert("scores".to_string(), Self::sub_scores_value(case));
         }
         map
     }
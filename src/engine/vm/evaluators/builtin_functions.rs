@@ -1,38 +1,501 @@
-use crate::engine::lang::ast::Value;
-use std::collections::HashMap;
+use crate::engine::{
+    lang::ast::{ BuiltinFn, FunctionDef, Value },
+    vm::{ context::VmContext, evaluators::ExprEvaluator },
+};
+use std::{ collections::HashMap, fmt };
+
+/// Built-in that needs VM state (such as the RNG), so it can't live in the
+/// environment as a plain `BuiltinFn`.
+pub type ContextBuiltinFn = fn(&mut VmContext, &[Value]) -> Result<Value, String>;
 
 pub struct BuiltinFunctions;
 
+/// What a function parameter accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Any,
+    Number,
+    String,
+    List,
+    ListOrString,
+    NumberOrString,
+    StringOrBytes,
+    /// A list, string or bytes value
+    Sized,
+}
+
+impl ParamType {
+    pub fn accepts(&self, value: &Value) -> bool {
+        match self {
+            ParamType::Any => true,
+            ParamType::Number => matches!(value, Value::Number(_)),
+            ParamType::String => matches!(value, Value::String(_)),
+            ParamType::List => matches!(value, Value::List(_)),
+            ParamType::ListOrString => matches!(value, Value::List(_) | Value::String(_)),
+            ParamType::NumberOrString => matches!(value, Value::Number(_) | Value::String(_)),
+            ParamType::StringOrBytes => matches!(value, Value::String(_) | Value::Bytes(_)),
+            ParamType::Sized => matches!(value, Value::List(_) | Value::String(_) | Value::Bytes(_)),
+        }
+    }
+}
+
+impl fmt::Display for ParamType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ParamType::Any => "any value",
+            ParamType::Number => "number",
+            ParamType::String => "string",
+            ParamType::List => "list",
+            ParamType::ListOrString => "list or string",
+            ParamType::NumberOrString => "number or string",
+            ParamType::StringOrBytes => "string or bytes",
+            ParamType::Sized => "list, string or bytes",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub ty: ParamType,
+}
+
+/// Name, parameters and purpose of a callable function, used for error
+/// messages and `CoreEngine::describe_function`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionSignature {
+    pub name: String,
+    pub params: Vec<Param>,
+    /// The last parameter may be repeated (at least once)
+    pub variadic: bool,
+    pub description: String,
+}
+
+impl FunctionSignature {
+    fn new(name: &str, params: &[(&str, ParamType)], variadic: bool, description: &str) -> Self {
+        FunctionSignature {
+            name: name.to_string(),
+            params: params.iter().map(|(name, ty)| Param { name: name.to_string(), ty: *ty }).collect(),
+            variadic,
+            description: description.to_string(),
+        }
+    }
+
+    /// User functions accept any values; only their arity is checked.
+    pub fn for_user_function(function: &FunctionDef) -> Self {
+        FunctionSignature {
+            name: function.name.clone(),
+            params: function.params.iter().map(|name| Param { name: name.clone(), ty: ParamType::Any }).collect(),
+            variadic: false,
+            description: "user-defined function".to_string(),
+        }
+    }
+
+    /// Check the argument count and each argument against its declared type.
+    pub fn check_args(&self, args: &[Value]) -> Result<(), String> {
+        match find_mismatch(self.params.len(), self.variadic, |index| self.params[index].ty, args) {
+            None => Ok(()),
+            Some(mismatch) => Err(self.mismatch_error(mismatch, args)),
+        }
+    }
+
+    /// Check the argument count of a call to `function`. The signature is
+    /// only built when the count is wrong.
+    pub fn check_user_args(function: &FunctionDef, args: &[Value]) -> Result<(), String> {
+        if args.len() == function.params.len() {
+            return Ok(());
+        }
+        Err(Self::for_user_function(function).mismatch_error(Mismatch::Arity, args))
+    }
+
+    fn mismatch_error(&self, mismatch: Mismatch, args: &[Value]) -> String {
+        match mismatch {
+            Mismatch::Arity => {
+                let at_least = if self.variadic { "at least " } else { "" };
+                let noun = if self.params.len() == 1 { "argument" } else { "arguments" };
+                format!("{}: expects {}{} {}, got {}", self, at_least, self.params.len(), noun, args.len())
+            }
+            Mismatch::Argument(position, ty) => self.argument_error(position, &ty.to_string(), &args[position - 1]),
+        }
+    }
+
+    /// "name(params): argument N expected <expected>, got <type>", with `position` 1-based.
+    pub fn argument_error(&self, position: usize, expected: &str, got: &Value) -> String {
+        format!("{}: argument {} expected {}, got {}", self, position, expected, got.value_type())
+    }
+}
+
+impl fmt::Display for FunctionSignature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.params.iter().map(|p| p.name.as_str()).collect();
+        let ellipsis = if self.variadic { "..." } else { "" };
+        write!(f, "{}({}{})", self.name, names.join(", "), ellipsis)
+    }
+}
+
+/// Why arguments don't fit a signature. Nothing is formatted while
+/// checking, so a call that passes costs no allocation.
+enum Mismatch {
+    Arity,
+    /// 1-based position of the first argument of the wrong type
+    Argument(usize, ParamType),
+}
+
+/// Check `args` against a signature of `count` parameters, where `types`
+/// gives the type of each; a variadic signature repeats the last one.
+fn find_mismatch(count: usize, variadic: bool, types: impl Fn(usize) -> ParamType, args: &[Value]) -> Option<Mismatch> {
+    let arity_ok = if variadic { args.len() >= count } else { args.len() == count };
+    if !arity_ok {
+        return Some(Mismatch::Arity);
+    }
+    args.iter().enumerate().find_map(|(index, arg)| {
+        let ty = types(index.min(count.saturating_sub(1)));
+        (!ty.accepts(arg)).then_some(Mismatch::Argument(index + 1, ty))
+    })
+}
+
+/// How a built-in is called.
+#[derive(Clone, Copy)]
+enum Implementation {
+    /// Bound in the environment as a `Value::BuiltinFunction`
+    Plain(BuiltinFn),
+    /// Needs VM state, so it is looked up by name after the environment
+    Context(ContextBuiltinFn),
+}
+
+/// Signature and implementation of a built-in, as static data.
+struct Builtin {
+    name: &'static str,
+    params: &'static [(&'static str, ParamType)],
+    variadic: bool,
+    description: &'static str,
+    implementation: Implementation,
+}
+
+impl Builtin {
+    const fn plain(name: &'static str, params: &'static [(&'static str, ParamType)], variadic: bool, description: &'static str, function: BuiltinFn) -> Self {
+        Builtin { name, params, variadic, description, implementation: Implementation::Plain(function) }
+    }
+
+    const fn context(name: &'static str, params: &'static [(&'static str, ParamType)], variadic: bool, description: &'static str, function: ContextBuiltinFn) -> Self {
+        Builtin { name, params, variadic, description, implementation: Implementation::Context(function) }
+    }
+
+    fn signature(&self) -> FunctionSignature {
+        FunctionSignature::new(self.name, self.params, self.variadic, self.description)
+    }
+
+    fn check_args(&self, args: &[Value]) -> Result<(), String> {
+        // A common slip gets a more specific hint than the type error would give
+        if self.name == "contains" && args.len() == 2 && !matches!(args[0], Value::List(_)) && matches!(args[1], Value::List(_)) {
+            return Err(BuiltinFunctions::swapped_contains_error(&args[0]));
+        }
+        // `call(f)` calls a function that takes no arguments
+        if self.name == "call" && args.len() == 1 {
+            return Ok(());
+        }
+        match find_mismatch(self.params.len(), self.variadic, |index| self.params[index].1, args) {
+            None => Ok(()),
+            Some(mismatch) => Err(self.signature().mismatch_error(mismatch, args)),
+        }
+    }
+}
+
 impl BuiltinFunctions {
-    /// Register all built-in functions
-    pub fn register_all() -> HashMap<String, fn(&[Value]) -> Result<Value, String>> {
-        let mut functions = HashMap::new();
+    /// Every built-in, including the context-aware ones. Registration,
+    /// lookup and argument checking are all driven by this table.
+    const BUILTINS: &'static [Builtin] = {
+        use ParamType::*;
+        &[
+            Builtin::plain("len", &[("value", Sized)], false, "number of elements, characters or bytes", Self::len_function),
+            Builtin::plain("byte_len", &[("value", StringOrBytes)], false, "number of bytes in the UTF-8 string or bytes", Self::byte_len_function),
+            Builtin::plain("max", &[("numbers", Number)], true, "largest of the numbers", Self::max_function),
+            Builtin::plain("min", &[("numbers", Number)], true, "smallest of the numbers", Self::min_function),
+            Builtin::plain("contains", &[("list_or_string", ListOrString), ("item", Any)], false, "whether the list holds the item, or the string the substring", Self::contains_function),
+            Builtin::plain("matches_any", &[("list", List), ("candidates", List)], false, "whether the lists share an element", Self::matches_any_function),
+            Builtin::plain("matches_all", &[("required", List), ("available", List)], false, "whether every required element is available", Self::matches_all_function),
+            Builtin::plain("coalesce", &[("values", Any)], true, "first value that is neither null nor an empty string", Self::coalesce_function),
+            Builtin::plain("pad", &[("value", NumberOrString), ("width", Number)], false, "value left-padded with spaces to the width", Self::pad_function),
+            Builtin::plain("format_number", &[("number", Number), ("group_size", Number)], false, "number with digits grouped by commas", Self::format_number_function),
+            Builtin::plain("distinct_count", &[("list", List)], false, "number of unique elements", Self::distinct_count_function),
+            Builtin::plain("minutes", &[("n", Number)], false, "n minutes in seconds", Self::minutes_function),
+            Builtin::plain("hours", &[("n", Number)], false, "n hours in seconds", Self::hours_function),
+            Builtin::plain("days", &[("n", Number)], false, "n days in seconds", Self::days_function),
+            Builtin::plain("duration_between", &[("a", Number), ("b", Number)], false, "seconds between two timestamps", Self::duration_between_function),
+            Builtin::plain("format_duration", &[("seconds", Number)], false, "seconds rendered like \"1d 2h 5s\"", Self::format_duration_function),
+            Builtin::context("day_of_week", &[("timestamp", Number)], false, "ISO weekday of the timestamp in local time, 1 for Monday to 7 for Sunday", Self::day_of_week_function),
+            Builtin::context("is_business_hours", &[("timestamp", Number), ("hours", String), ("days", String)], false, "whether the timestamp falls in hours like \"09:00-17:00\" on days like \"Mon-Fri\", in local time", Self::is_business_hours_function),
+            Builtin::plain("sla_deadline", &[("created_at", Number), ("sla_hours", Number)], false, "timestamp sla_hours after created_at", Self::sla_deadline_function),
+            Builtin::context("hours_until", &[("deadline", Number)], false, "whole hours from now until the deadline, negative once it has passed", Self::hours_until_function),
+            Builtin::plain("is_empty", &[("value", Any)], false, "whether the value is null or an empty string, list, map or bytes", Self::is_empty_function),
+            Builtin::plain("is_not_empty", &[("value", Any)], false, "the opposite of is_empty", Self::is_not_empty_function),
+            Builtin::plain("abs_diff", &[("a", Number), ("b", Number)], false, "distance between two numbers", Self::abs_diff_function),
+            Builtin::plain("int", &[("value", Any)], false, "the number a bool (0 or 1), number or numeric string stands for", Self::int_function),
+            Builtin::plain("bool", &[("value", Any)], false, "whether the value is truthy, as a condition would treat it", Self::bool_function),
+            Builtin::plain("decay", &[("age", Number), ("half_life", Number), ("max", Number)], false, "max * age / (age + half_life), rounded down: half of max at the half-life, approaching max with age", Self::decay_function),
+            Builtin::plain("flatten", &[("list", List)], false, "nested lists spliced in, one level deep", Self::flatten_function),
+            Builtin::plain("max_score", &[("cases", List)], false, "highest score among case maps", Self::max_score_function),
+            Builtin::plain("avg_score", &[("cases", List)], false, "mean score of case maps", Self::avg_score_function),
+            #[cfg(feature = "base64")]
+            Builtin::plain("base64_encode", &[("data", StringOrBytes)], false, "base64 text of the bytes", Self::base64_encode_function),
+            #[cfg(feature = "base64")]
+            Builtin::plain("base64_decode", &[("text", String)], false, "bytes decoded from base64", Self::base64_decode_function),
+            Builtin::context("shuffle", &[("list", List)], false, "copy of the list in seeded random order", Self::shuffle_function),
+            Builtin::context("random_int", &[("low", Number), ("high", Number)], false, "seeded random number from low to high, both included", Self::random_int_function),
+            Builtin::context("assignments", &[("agent_id", String)], false, "cases routed to the pool agent so far by route to least_loaded", Self::assignments_function),
+            Builtin::context("now", &[], false, "current time in seconds since the Unix epoch", Self::now_function),
+            Builtin::context("priority_weight", &[], false, "weight of the current case's priority from the engine's table", Self::priority_weight_function),
+            Builtin::context("call", &[("function", Any), ("args", Any)], true, "result of calling the function, or the function named by a string, with the arguments", Self::call_function),
+        ]
+    };
 
-        functions.insert("len".to_string(), Self::len_function as fn(&[Value]) -> Result<Value, String>);
-        functions.insert("max".to_string(), Self::max_function as fn(&[Value]) -> Result<Value, String>);
-        functions.insert("min".to_string(), Self::min_function as fn(&[Value]) -> Result<Value, String>);
-        functions.insert("contains".to_string(), Self::contains_function as fn(&[Value]) -> Result<Value, String>);
+    fn builtin(name: &str) -> Option<&'static Builtin> {
+        Self::BUILTINS.iter().find(|builtin| builtin.name == name)
+    }
 
-        functions
+    /// Whether `name` is a built-in, including the context-aware ones.
+    pub fn is_builtin(name: &str) -> bool {
+        Self::builtin(name).is_some()
     }
 
-    /// len() function - get length of lists or strings
-    fn len_function(args: &[Value]) -> Result<Value, String> {
-        if args.len() != 1 {
-            return Err("len() takes exactly 1 argument".to_string());
+    /// Signature of a built-in, including the context-aware ones.
+    pub fn signature(name: &str) -> Option<FunctionSignature> {
+        Self::builtin(name).map(Builtin::signature)
+    }
+
+    /// Names of the context-aware built-ins, which are not bound in the environment.
+    pub fn context_function_names() -> impl Iterator<Item = &'static str> {
+        Self::BUILTINS
+            .iter()
+            .filter(|builtin| matches!(builtin.implementation, Implementation::Context(_)))
+            .map(|builtin| builtin.name)
+    }
+
+    /// Check `args` against the signature of built-in `name`, if it has one.
+    pub fn check_args(name: &str, args: &[Value]) -> Result<(), String> {
+        match Self::builtin(name) {
+            Some(builtin) => builtin.check_args(args),
+            None => Ok(()),
+        }
+    }
+
+    /// Check `args` against the built-in that `function` implements, whatever
+    /// name it is called by. Functions the host bound itself are not checked.
+    pub fn check_function_args(function: BuiltinFn, args: &[Value]) -> Result<(), String> {
+        let builtin = Self::BUILTINS.iter().find(|builtin| {
+            matches!(builtin.implementation, Implementation::Plain(f) if std::ptr::fn_addr_eq(f, function))
+        });
+        match builtin {
+            Some(builtin) => builtin.check_args(args),
+            None => Ok(()),
+        }
+    }
+
+    fn swapped_contains_error(value: &Value) -> String {
+        format!(
+            "contains() takes the list first: write contains(list, {}) instead of contains({}, list)",
+            value, value
+        )
+    }
+
+    /// Argument error for `name`, prefixed by its signature when it has one.
+    fn argument_error(name: &str, position: usize, expected: &str, got: &Value) -> String {
+        match Self::signature(name) {
+            Some(signature) => signature.argument_error(position, expected, got),
+            None => format!("{}(): argument {} expected {}, got {}", name, position, expected, got.value_type()),
+        }
+    }
+
+    /// Look up a context-aware built-in. These are consulted after the
+    /// environment, so user functions may shadow them.
+    pub fn context_function(name: &str) -> Option<ContextBuiltinFn> {
+        match Self::builtin(name)?.implementation {
+            Implementation::Context(function) => Some(function),
+            Implementation::Plain(_) => None,
+        }
+    }
+
+    /// shuffle() function - a copy of the list in random order, driven by the seeded RNG
+    fn shuffle_function(context: &mut VmContext, args: &[Value]) -> Result<Value, String> {
+        let Value::List(items) = &args[0] else {
+            return Err("shuffle() can only be applied to lists".to_string());
+        };
+        let mut items = items.clone();
+        for i in (1..items.len()).rev() {
+            let j = context.rng.below(i + 1);
+            items.swap(i, j);
+        }
+        Ok(Value::List(items))
+    }
+
+    /// random_int() function - a number in `low..=high` from the seeded RNG
+    fn random_int_function(context: &mut VmContext, args: &[Value]) -> Result<Value, String> {
+        let [Value::Number(low), Value::Number(high)] = args else {
+            return Err("random_int() takes exactly 2 numbers".to_string());
+        };
+        if low > high {
+            return Err(format!("random_int() needs low <= high, got {} and {}", low, high));
+        }
+        Ok(Value::Number(context.rng.in_range(*low, *high)))
+    }
+
+    /// now() function - current time in seconds since the Unix epoch, from the engine's clock
+    fn now_function(context: &mut VmContext, _args: &[Value]) -> Result<Value, String> {
+        Ok(Value::Number(context.clock.now()))
+    }
+
+    /// day_of_week() function - weekday of a Unix timestamp in local time, Monday = 1 ... Sunday = 7
+    fn day_of_week_function(context: &mut VmContext, args: &[Value]) -> Result<Value, String> {
+        let [Value::Number(secs)] = args else {
+            return Err("day_of_week() takes exactly 1 number".to_string());
+        };
+        let (weekday, _) = Self::local_time("day_of_week", context, *secs)?;
+        Ok(Value::Number(weekday))
+    }
+
+    /// is_business_hours() function - whether a timestamp falls within the
+    /// opening hours on one of the days, in local time. The opening time is
+    /// inclusive and the closing time exclusive.
+    fn is_business_hours_function(context: &mut VmContext, args: &[Value]) -> Result<Value, String> {
+        let [Value::Number(secs), Value::String(hours), Value::String(days)] = args else {
+            return Err("is_business_hours() takes a timestamp, hours and days".to_string());
+        };
+        let (open, close) = Self::parse_opening_hours(hours)?;
+        let open_days = Self::parse_weekdays(days)?;
+        let (weekday, minute) = Self::local_time("is_business_hours", context, *secs)?;
+        Ok(Value::Bool(open_days.contains(&weekday) && open <= minute && minute < close))
+    }
+
+    /// hours_until() function - whole hours from the engine clock to the
+    /// deadline, rounded down so a deadline passed by a second is already -1
+    fn hours_until_function(context: &mut VmContext, args: &[Value]) -> Result<Value, String> {
+        let [Value::Number(deadline)] = args else {
+            return Err("hours_until() takes exactly 1 number".to_string());
+        };
+        let remaining = deadline
+            .checked_sub(context.clock.now())
+            .ok_or_else(|| format!("hours_until() overflowed for {}", deadline))?;
+        Ok(Value::Number(remaining.div_euclid(3_600)))
+    }
+
+    /// assignments() function - cases routed to an agent of the engine's pool so far
+    fn assignments_function(context: &mut VmContext, args: &[Value]) -> Result<Value, String> {
+        let [Value::String(agent_id)] = args else {
+            return Err("assignments() takes exactly 1 agent id".to_string());
+        };
+        if !context.stack.agents.iter().any(|agent| &agent.id == agent_id) {
+            return Err(format!("assignments(): no agent '{}' in the pool", agent_id));
+        }
+        let load = context.agent_loads.get(agent_id).copied().unwrap_or(0);
+        Ok(Value::Number(i64::from(load)))
+    }
+
+    /// ISO weekday and minute of the day of `secs` shifted by the configured UTC offset
+    fn local_time(name: &str, context: &VmContext, secs: i64) -> Result<(i64, i64), String> {
+        let local = secs
+            .checked_add(i64::from(context.options.utc_offset_minutes) * 60)
+            .ok_or_else(|| format!("{}() overflowed for {}", name, secs))?;
+        // The epoch fell on a Thursday
+        let weekday = (local.div_euclid(86_400) + 3).rem_euclid(7) + 1;
+        Ok((weekday, local.rem_euclid(86_400) / 60))
+    }
+
+    /// Parse `"09:00-17:00"` into minutes of the day. `24:00` closes at midnight.
+    fn parse_opening_hours(hours: &str) -> Result<(i64, i64), String> {
+        let invalid = || format!(
+            "is_business_hours(): hours '{}' must look like \"09:00-17:00\", opening before closing",
+            hours
+        );
+        let minute_of_day = |time: &str| -> Option<i64> {
+            let (h, m) = time.trim().split_once(':')?;
+            let (h, m): (i64, i64) = (h.parse().ok()?, m.parse().ok()?);
+            (m < 60 && (h < 24 || (h == 24 && m == 0))).then_some(h * 60 + m)
+        };
+        let (open, close) = hours.split_once('-').ok_or_else(invalid)?;
+        match (minute_of_day(open), minute_of_day(close)) {
+            (Some(open), Some(close)) if open < close => Ok((open, close)),
+            _ => Err(invalid()),
         }
+    }
+
+    /// Parse days like `"Mon-Fri"` or `"Mon,Wed,Sat-Sun"` into ISO weekdays.
+    /// A range may wrap past Sunday, so `"Fri-Mon"` is a long weekend.
+    fn parse_weekdays(days: &str) -> Result<Vec<i64>, String> {
+        const NAMES: [&str; 7] = ["mon", "tue", "wed", "thu", "fri", "sat", "sun"];
+        let weekday = |name: &str| -> Result<i64, String> {
+            NAMES.iter()
+                .position(|n| n.eq_ignore_ascii_case(name.trim()))
+                .map(|i| i as i64 + 1)
+                .ok_or_else(|| format!("is_business_hours(): unknown day '{}', expected Mon to Sun", name.trim()))
+        };
+        let mut weekdays = Vec::new();
+        for part in days.split(',') {
+            let (first, last) = match part.split_once('-') {
+                Some((first, last)) => (weekday(first)?, weekday(last)?),
+                None => (weekday(part)?, weekday(part)?),
+            };
+            let mut day = first;
+            weekdays.push(day);
+            while day != last {
+                day = day % 7 + 1;
+                weekdays.push(day);
+            }
+        }
+        Ok(weekdays)
+    }
+
+    /// call() function - call a function value, or the function a string names, with the remaining arguments
+    fn call_function(context: &mut VmContext, args: &[Value]) -> Result<Value, String> {
+        let (callee, rest) = args.split_first().ok_or("call() takes at least 1 argument")?;
+        ExprEvaluator::call_function_value(context, callee, rest)
+    }
+
+    /// priority_weight() function - the current case's priority mapped through the engine's weight table
+    fn priority_weight_function(context: &mut VmContext, _args: &[Value]) -> Result<Value, String> {
+        let priority = match context.env.lookup("priority") {
+            Some(Value::Number(n)) => *n,
+            _ => return Err("priority_weight() can only be used while evaluating a case".to_string()),
+        };
+        let weight = i32::try_from(priority)
+            .ok()
+            .and_then(|p| context.priority_weights.get(&p).copied())
+            .unwrap_or(priority);
+        Ok(Value::Number(weight))
+    }
+
+    /// Register all built-in functions
+    pub fn register_all() -> HashMap<String, BuiltinFn> {
+        Self::BUILTINS
+            .iter()
+            .filter_map(|builtin| match builtin.implementation {
+                Implementation::Plain(function) => Some((builtin.name.to_string(), function)),
+                Implementation::Context(_) => None,
+            })
+            .collect()
+    }
+
+    /// len() function - get length of lists, strings (in characters) or bytes
+    fn len_function(args: &[Value]) -> Result<Value, String> {
         match &args[0] {
             Value::List(list) => Ok(Value::Number(list.len() as i64)),
+            Value::String(s) => Ok(Value::Number(s.chars().count() as i64)),
+            Value::Bytes(b) => Ok(Value::Number(b.len() as i64)),
+            _ => Err("len() can only be applied to lists, strings or bytes".to_string()),
+        }
+    }
+
+    /// byte_len() function - size of a string's UTF-8 encoding, or of bytes
+    fn byte_len_function(args: &[Value]) -> Result<Value, String> {
+        match &args[0] {
             Value::String(s) => Ok(Value::Number(s.len() as i64)),
-            _ => Err("len() can only be applied to lists or strings".to_string()),
+            Value::Bytes(b) => Ok(Value::Number(b.len() as i64)),
+            _ => Err("byte_len() can only be applied to strings or bytes".to_string()),
         }
     }
 
     /// max() function - find maximum value among numbers
     fn max_function(args: &[Value]) -> Result<Value, String> {
-        if args.is_empty() {
-            return Err("max() requires at least 1 argument".to_string());
-        }
         let mut max_val = match &args[0] {
             Value::Number(n) => *n,
             _ => return Err("max() can only be applied to numbers".to_string()),
@@ -52,9 +515,6 @@ impl BuiltinFunctions {
 
     /// min() function - find minimum value among numbers
     fn min_function(args: &[Value]) -> Result<Value, String> {
-        if args.is_empty() {
-            return Err("min() requires at least 1 argument".to_string());
-        }
         let mut min_val = match &args[0] {
             Value::Number(n) => *n,
             _ => return Err("min() can only be applied to numbers".to_string()),
@@ -74,9 +534,6 @@ impl BuiltinFunctions {
 
     /// contains() function - check if list/string contains a value
     fn contains_function(args: &[Value]) -> Result<Value, String> {
-        if args.len() != 2 {
-            return Err("contains() takes exactly 2 arguments".to_string());
-        }
         match (&args[0], &args[1]) {
             (Value::List(list), value) => {
                 for item in list {
@@ -87,16 +544,330 @@ impl BuiltinFunctions {
                 Ok(Value::Bool(false))
             }
             (Value::String(s), Value::String(substr)) => Ok(Value::Bool(s.contains(substr))),
+            (value, Value::List(_)) => Err(Self::swapped_contains_error(value)),
+            (Value::String(_), other) => Err(Self::argument_error("contains", 2, "string", other)),
             _ => Err("contains() first argument must be a list or string".to_string()),
         }
     }
 
+    /// base64_encode() function - encode bytes (or a string's UTF-8 bytes) as base64
+    #[cfg(feature = "base64")]
+    fn base64_encode_function(args: &[Value]) -> Result<Value, String> {
+        use base64::Engine;
+        match &args[0] {
+            Value::Bytes(b) => Ok(Value::String(base64::engine::general_purpose::STANDARD.encode(b))),
+            Value::String(s) => Ok(Value::String(base64::engine::general_purpose::STANDARD.encode(s))),
+            _ => Err("base64_encode() can only be applied to bytes or strings".to_string()),
+        }
+    }
+
+    /// base64_decode() function - decode a base64 string into bytes
+    #[cfg(feature = "base64")]
+    fn base64_decode_function(args: &[Value]) -> Result<Value, String> {
+        use base64::Engine;
+        match &args[0] {
+            Value::String(s) => base64::engine::general_purpose::STANDARD
+                .decode(s)
+                .map(Value::Bytes)
+                .map_err(|e| format!("base64_decode() received invalid input: {}", e)),
+            _ => Err("base64_decode() can only be applied to strings".to_string()),
+        }
+    }
+
+    /// matches_any() function - true if the two lists share at least one element
+    fn matches_any_function(args: &[Value]) -> Result<Value, String> {
+        let (left, right) = Self::two_lists("matches_any", args)?;
+        let found = left
+            .iter()
+            .any(|item| right.iter().any(|other| Self::values_equal(item, other)));
+        Ok(Value::Bool(found))
+    }
+
+    /// matches_all() function - true if every element of the first list is in the second
+    fn matches_all_function(args: &[Value]) -> Result<Value, String> {
+        let (required, available) = Self::two_lists("matches_all", args)?;
+        let found = required
+            .iter()
+            .all(|item| available.iter().any(|other| Self::values_equal(item, other)));
+        Ok(Value::Bool(found))
+    }
+
+    /// coalesce() function - first argument that is neither null nor an empty string
+    fn coalesce_function(args: &[Value]) -> Result<Value, String> {
+        let found = args
+            .iter()
+            .find(|value| !matches!(value, Value::Null) && !matches!(value, Value::String(s) if s.is_empty()));
+        Ok(found.cloned().unwrap_or(Value::Null))
+    }
+
+    /// Widest `pad()` accepts, so a rule can't allocate an arbitrarily large string
+    pub const MAX_PAD_WIDTH: i64 = 1024;
+
+    /// pad() function - left-pad a number or string with spaces to `width` characters
+    fn pad_function(args: &[Value]) -> Result<Value, String> {
+        let text = match &args[0] {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.clone(),
+            _ => return Err("pad() can only be applied to numbers or strings".to_string()),
+        };
+        let width = match &args[1] {
+            Value::Number(n) if *n > Self::MAX_PAD_WIDTH => {
+                return Err(format!("pad() width must be at most {}, got {}", Self::MAX_PAD_WIDTH, n));
+            }
+            Value::Number(n) if *n >= 0 => *n as usize,
+            _ => return Err("pad() width must be a non-negative number".to_string()),
+        };
+        // Inputs already at or beyond `width` are returned unchanged
+        Ok(Value::String(format!("{:>width$}", text, width = width)))
+    }
+
+    /// format_number() function - group digits with commas, `digits` per group
+    fn format_number_function(args: &[Value]) -> Result<Value, String> {
+        let n = match &args[0] {
+            Value::Number(n) => *n,
+            _ => return Err("format_number() can only be applied to numbers".to_string()),
+        };
+        let group = match &args[1] {
+            Value::Number(d) if *d > 0 => *d as usize,
+            _ => return Err("format_number() digits must be a positive number".to_string()),
+        };
+
+        let digits = n.unsigned_abs().to_string();
+        let mut grouped = String::with_capacity(digits.len() + digits.len() / group + 1);
+        if n < 0 {
+            grouped.push('-');
+        }
+        for (i, ch) in digits.chars().enumerate() {
+            if i > 0 && (digits.len() - i) % group == 0 {
+                grouped.push(',');
+            }
+            grouped.push(ch);
+        }
+        Ok(Value::String(grouped))
+    }
+
+    /// distinct_count() function - number of unique elements in a list
+    fn distinct_count_function(args: &[Value]) -> Result<Value, String> {
+        let Value::List(items) = &args[0] else {
+            return Err("distinct_count() can only be applied to lists".to_string());
+        };
+        let mut distinct: Vec<&Value> = Vec::new();
+        for item in items {
+            if !distinct.iter().any(|seen| Self::values_equal(seen, item)) {
+                distinct.push(item);
+            }
+        }
+        Ok(Value::Number(distinct.len() as i64))
+    }
+
+    /// minutes() function - `n` minutes in seconds
+    fn minutes_function(args: &[Value]) -> Result<Value, String> {
+        Self::scale_seconds("minutes", args, 60)
+    }
+
+    /// hours() function - `n` hours in seconds
+    fn hours_function(args: &[Value]) -> Result<Value, String> {
+        Self::scale_seconds("hours", args, 60 * 60)
+    }
+
+    /// days() function - `n` days in seconds
+    fn days_function(args: &[Value]) -> Result<Value, String> {
+        Self::scale_seconds("days", args, 24 * 60 * 60)
+    }
+
+    fn scale_seconds(name: &str, args: &[Value], unit: i64) -> Result<Value, String> {
+        match &args[0] {
+            Value::Number(n) => n
+                .checked_mul(unit)
+                .map(Value::Number)
+                .ok_or_else(|| format!("{}() overflowed for {}", name, n)),
+            _ => Err(format!("{}() can only be applied to numbers", name)),
+        }
+    }
+
+    /// duration_between() function - seconds between two timestamps, in either order
+    fn duration_between_function(args: &[Value]) -> Result<Value, String> {
+        match (&args[0], &args[1]) {
+            (Value::Number(a), Value::Number(b)) => a
+                .checked_sub(*b)
+                .map(|diff| Value::Number(diff.abs()))
+                .ok_or_else(|| "duration_between() overflowed".to_string()),
+            _ => Err("duration_between() can only be applied to numbers".to_string()),
+        }
+    }
+
+    /// format_duration() function - seconds as e.g. "2d 3h 5s", omitting zero units
+    fn format_duration_function(args: &[Value]) -> Result<Value, String> {
+        let Value::Number(secs) = &args[0] else {
+            return Err("format_duration() can only be applied to numbers".to_string());
+        };
+        if *secs == 0 {
+            return Ok(Value::String("0s".to_string()));
+        }
+
+        let mut remaining = secs.unsigned_abs();
+        let mut parts = Vec::new();
+        for (unit, suffix) in [(86_400, "d"), (3_600, "h"), (60, "m"), (1, "s")] {
+            if remaining >= unit {
+                parts.push(format!("{}{}", remaining / unit, suffix));
+                remaining %= unit;
+            }
+        }
+        let sign = if *secs < 0 { "-" } else { "" };
+        Ok(Value::String(format!("{}{}", sign, parts.join(" "))))
+    }
+
+    /// sla_deadline() function - the timestamp `sla_hours` after `created_at`
+    fn sla_deadline_function(args: &[Value]) -> Result<Value, String> {
+        let [Value::Number(created_at), Value::Number(sla_hours)] = args else {
+            return Err("sla_deadline() takes a timestamp and a number of hours".to_string());
+        };
+        sla_hours
+            .checked_mul(3_600)
+            .and_then(|secs| created_at.checked_add(secs))
+            .map(Value::Number)
+            .ok_or_else(|| format!("sla_deadline() overflowed for {} + {} hours", created_at, sla_hours))
+    }
+
+    /// is_empty() function - null, or a string, list, map or bytes with nothing in it
+    fn is_empty_function(args: &[Value]) -> Result<Value, String> {
+        Ok(Value::Bool(Self::value_is_empty(&args[0])))
+    }
+
+    /// is_not_empty() function - the opposite of is_empty()
+    fn is_not_empty_function(args: &[Value]) -> Result<Value, String> {
+        Ok(Value::Bool(!Self::value_is_empty(&args[0])))
+    }
+
+    fn value_is_empty(value: &Value) -> bool {
+        match value {
+            Value::Null => true,
+            Value::String(s) => s.is_empty(),
+            Value::List(list) => list.is_empty(),
+            Value::Map(map) => map.is_empty(),
+            Value::Bytes(bytes) => bytes.is_empty(),
+            _ => false,
+        }
+    }
+
+    /// abs_diff() function - |a - b|, in either order
+    fn abs_diff_function(args: &[Value]) -> Result<Value, String> {
+        match (&args[0], &args[1]) {
+            (Value::Number(a), Value::Number(b)) => i64::try_from(a.abs_diff(*b))
+                .map(Value::Number)
+                .map_err(|_| "abs_diff() overflowed".to_string()),
+            _ => Err("abs_diff() can only be applied to numbers".to_string()),
+        }
+    }
+
+    /// decay() function - a boost of `max * age / (age + half_life)`, rounded
+    /// down. It is 0 for a new case, half of `max` once `age` reaches
+    /// `half_life`, and approaches but never reaches `max` as `age` grows
+    fn decay_function(args: &[Value]) -> Result<Value, String> {
+        let [Value::Number(age), Value::Number(half_life), Value::Number(max)] = args else {
+            return Err("decay() takes exactly 3 numbers".to_string());
+        };
+        if *age < 0 || *max < 0 {
+            return Err(format!("decay() needs a non-negative age and max, got age {} and max {}", age, max));
+        }
+        if *half_life <= 0 {
+            return Err(format!("decay() needs a positive half_life, got {}", half_life));
+        }
+        // Wide enough that neither the product nor the sum can overflow
+        let boost = i128::from(*max) * i128::from(*age) / (i128::from(*age) + i128::from(*half_life));
+        Ok(Value::Number(boost as i64))
+    }
+
+    /// int() function - true/false as 1/0, a number unchanged, or a string parsed as a number
+    fn int_function(args: &[Value]) -> Result<Value, String> {
+        match &args[0] {
+            Value::Number(n) => Ok(Value::Number(*n)),
+            Value::Bool(b) => Ok(Value::Number(i64::from(*b))),
+            Value::String(s) => s
+                .trim()
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| format!("int(): \"{}\" is not a whole number", s)),
+            other => Err(format!("int() can't convert a {}", other.value_type())),
+        }
+    }
+
+    /// bool() function - the truthiness a condition would give the value
+    fn bool_function(args: &[Value]) -> Result<Value, String> {
+        Ok(Value::Bool(ExprEvaluator::is_truthy(&args[0])))
+    }
+
+    /// flatten() function - splice nested lists into the outer list, one level deep
+    fn flatten_function(args: &[Value]) -> Result<Value, String> {
+        let Value::List(items) = &args[0] else {
+            return Err("flatten() can only be applied to lists".to_string());
+        };
+        let mut flat = Vec::with_capacity(items.len());
+        for item in items {
+            match item {
+                Value::List(inner) => flat.extend(inner.iter().cloned()),
+                other => flat.push(other.clone()),
+            }
+        }
+        Ok(Value::List(flat))
+    }
+
+    /// max_score() function - highest `score` among a list of routed case maps, null if empty
+    fn max_score_function(args: &[Value]) -> Result<Value, String> {
+        let scores = Self::queue_scores("max_score", args)?;
+        Ok(scores.into_iter().max().map_or(Value::Null, Value::Number))
+    }
+
+    /// avg_score() function - mean `score` of a list of routed case maps, rounded
+    /// toward zero; null if empty
+    fn avg_score_function(args: &[Value]) -> Result<Value, String> {
+        let scores = Self::queue_scores("avg_score", args)?;
+        if scores.is_empty() {
+            return Ok(Value::Null);
+        }
+        // Summed wide so large scores can't overflow; the mean always fits back
+        let total: i128 = scores.iter().map(|&score| i128::from(score)).sum();
+        Ok(Value::Number((total / scores.len() as i128) as i64))
+    }
+
+    /// Read the `score` key of every case map in a list. Routed cases carry
+    /// their fields as strings, so numeric strings are accepted too.
+    fn queue_scores(name: &str, args: &[Value]) -> Result<Vec<i64>, String> {
+        let Value::List(items) = &args[0] else {
+            return Err(format!("{}() can only be applied to lists of cases", name));
+        };
+        items
+            .iter()
+            .map(|item| {
+                let Value::Map(map) = item else {
+                    return Err(format!("{}() expects case maps, got {}", name, item));
+                };
+                match map.get("score") {
+                    Some(Value::Number(n)) => Ok(*n),
+                    Some(Value::String(s)) => s
+                        .parse()
+                        .map_err(|_| format!("{}() found a non-numeric score {:?}", name, s)),
+                    Some(other) => Err(format!("{}() found a non-numeric score {}", name, other)),
+                    None => Err(format!("{}() found a case without a score", name)),
+                }
+            })
+            .collect()
+    }
+
+    fn two_lists<'a>(name: &str, args: &'a [Value]) -> Result<(&'a [Value], &'a [Value]), String> {
+        match (&args[0], &args[1]) {
+            (Value::List(a), Value::List(b)) => Ok((a, b)),
+            _ => Err(format!("{}() arguments must both be lists", name)),
+        }
+    }
+
     /// Helper function to compare values for equality
     fn values_equal(left: &Value, right: &Value) -> bool {
         match (left, right) {
             (Value::Number(a), Value::Number(b)) => a == b,
             (Value::String(a), Value::String(b)) => a == b,
             (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
             (Value::Null, Value::Null) => true,
             _ => false,
         }
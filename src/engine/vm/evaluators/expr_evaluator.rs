@@ -1,6 +1,7 @@
 use crate::engine::{
+    options::FunctionScope,
     lang::ast::{ Expr, BinaryOperator, UnaryOperator, Value },
-    vm::context::VmContext,
+    vm::{ context::VmContext, memo::MemoKey, evaluators::builtin_functions::{ BuiltinFunctions, FunctionSignature } },
 };
 
 pub struct ExprEvaluator;
@@ -38,6 +39,15 @@ impl ExprEvaluator {
             Expr::MemberAccess { object, property } => {
                 Self::evaluate_member_access(context, object, property)
             }
+            Expr::LetIn { name, value, body } => {
+                let bound = Self::evaluate_expr(context, value)?;
+                context.env.enter_scope();
+                context.env.insert(name, bound);
+                let result = Self::evaluate_expr(context, body);
+                context.env.exit_scope();
+                result
+            }
+            Expr::Range { .. } => Err("A range can only be used on the right of 'in'".to_string()),
         }
     }
 
@@ -48,8 +58,80 @@ impl ExprEvaluator {
         right: &Expr
     ) -> Result<Value, String> {
         let left_val = Self::evaluate_expr(context, left)?;
+        if matches!(op, BinaryOperator::Coalesce) {
+            // The right side is only evaluated when the left is missing
+            return if Self::is_missing(&left_val) { Self::evaluate_expr(context, right) } else { Ok(left_val) };
+        }
+        if let (BinaryOperator::In, Expr::Range { start, end, inclusive }) = (op, right) {
+            let start = Self::evaluate_expr(context, start)?;
+            let end = Self::evaluate_expr(context, end)?;
+            return Self::in_range(&left_val, &start, &end, *inclusive);
+        }
         let right_val = Self::evaluate_expr(context, right)?;
+        Self::apply_binary_op(op, left_val, right_val)
+    }
 
+    /// Evaluate `expr` like `evaluate_expr`, recording the value of every
+    /// sub-expression other than literals in `steps` as `(source, value)`,
+    /// innermost first. The expression itself is recorded last. The source is
+    /// the expression's `Display` text, so spacing and parentheses are
+    /// normalised rather than copied from the input.
+    pub fn evaluate_expr_traced(
+        context: &mut VmContext,
+        expr: &Expr,
+        steps: &mut Vec<(String, Value)>
+    ) -> Result<Value, String> {
+        let value = match expr {
+            Expr::Number(_) | Expr::String(_) | Expr::Bool(_) => return Self::evaluate_expr(context, expr),
+            Expr::List(exprs) => {
+                let mut values = Vec::new();
+                for expr in exprs {
+                    values.push(Self::evaluate_expr_traced(context, expr, steps)?);
+                }
+                Value::List(values)
+            }
+            Expr::BinaryOp { left, op, right } => {
+                let left_val = Self::evaluate_expr_traced(context, left, steps)?;
+                match (op, right.as_ref()) {
+                    (BinaryOperator::Coalesce, _) if !Self::is_missing(&left_val) => left_val,
+                    (BinaryOperator::Coalesce, _) => Self::evaluate_expr_traced(context, right, steps)?,
+                    (BinaryOperator::In, Expr::Range { start, end, inclusive }) => {
+                        let start = Self::evaluate_expr_traced(context, start, steps)?;
+                        let end = Self::evaluate_expr_traced(context, end, steps)?;
+                        Self::in_range(&left_val, &start, &end, *inclusive)?
+                    }
+                    _ => {
+                        let right_val = Self::evaluate_expr_traced(context, right, steps)?;
+                        Self::apply_binary_op(op, left_val, right_val)?
+                    }
+                }
+            }
+            Expr::UnaryOp { op, expr } => {
+                let val = Self::evaluate_expr_traced(context, expr, steps)?;
+                Self::apply_unary_op(op, val)?
+            }
+            Expr::FunctionCall { name, args } => {
+                let mut arg_values = Vec::new();
+                for arg in args {
+                    arg_values.push(Self::evaluate_expr_traced(context, arg, steps)?);
+                }
+                Self::call_named_function(context, name, &arg_values)?
+            }
+            Expr::LetIn { name, value, body } => {
+                let bound = Self::evaluate_expr_traced(context, value, steps)?;
+                context.env.enter_scope();
+                context.env.insert(name, bound);
+                let result = Self::evaluate_expr_traced(context, body, steps);
+                context.env.exit_scope();
+                result?
+            }
+            Expr::Ident(_) | Expr::MemberAccess { .. } | Expr::Range { .. } => Self::evaluate_expr(context, expr)?,
+        };
+        steps.push((expr.to_string(), value.clone()));
+        Ok(value)
+    }
+
+    fn apply_binary_op(op: &BinaryOperator, left_val: Value, right_val: Value) -> Result<Value, String> {
         match op {
             BinaryOperator::Add => Self::add_values(&left_val, &right_val),
             BinaryOperator::Sub => Self::sub_values(&left_val, &right_val),
@@ -68,9 +150,15 @@ impl ExprEvaluator {
                 if Self::is_truthy(&left_val) { Ok(left_val) } else { Ok(right_val) }
             }
             BinaryOperator::In => Self::in_operation(&left_val, &right_val),
+            BinaryOperator::Coalesce => unreachable!("handled by the callers"),
         }
     }
 
+    /// Null and the empty string count as missing for `??`
+    fn is_missing(value: &Value) -> bool {
+        matches!(value, Value::Null) || matches!(value, Value::String(s) if s.is_empty())
+    }
+
     /// Evaluate a unary operation
     fn evaluate_unary_op(
         context: &mut VmContext,
@@ -78,7 +166,10 @@ impl ExprEvaluator {
         expr: &Expr
     ) -> Result<Value, String> {
         let val = Self::evaluate_expr(context, expr)?;
+        Self::apply_unary_op(op, val)
+    }
 
+    fn apply_unary_op(op: &UnaryOperator, val: Value) -> Result<Value, String> {
         match op {
             UnaryOperator::Neg =>
                 match val {
@@ -99,40 +190,91 @@ impl ExprEvaluator {
             arg_values.push(Self::evaluate_expr(context, arg)?);
         }
 
+        Self::call_named_function(context, name, &arg_values)
+    }
+
+    /// Call the function bound to `name`, or the context-aware built-in of
+    /// that name, with evaluated arguments.
+    fn call_named_function(context: &mut VmContext, name: &str, args: &[Value]) -> Result<Value, String> {
         // Look up function in environment
         if let Some(function_value) = context.env.lookup(name) {
             match function_value {
                 Value::BuiltinFunction(func) => {
-                    return func(&arg_values);
+                    let func = *func;
+                    BuiltinFunctions::check_function_args(func, args)?;
+                    return func(args);
+                }
+                // A function passed in through a variable or parameter
+                Value::UserFunction(user_func) if user_func.name != name => {
+                    let callee = function_value.clone();
+                    return Self::call_function_value(context, &callee, args);
                 }
                 Value::UserFunction(user_func) => {
                     // Clone the function definition to avoid borrowing issues
                     let user_func_clone = user_func.clone();
-                    return Self::evaluate_user_function(context, &user_func_clone, &arg_values);
+                    return Self::evaluate_user_function(context, &user_func_clone, name, args);
                 }
-                _ => return Err(format!("'{}' is not a function", name)),
+                other => return Err(format!("'{}' is not a function, it holds a {}", name, other.value_type())),
             }
         }
 
+        if let Some(func) = BuiltinFunctions::context_function(name) {
+            BuiltinFunctions::check_args(name, args)?;
+            return func(context, args);
+        }
+
         Err(format!("Unknown function: {}", name))
     }
 
+    /// Call a function passed around as a value, as `call()` does. A string
+    /// names the function to call, so the choice can come from a variable.
+    pub fn call_function_value(context: &mut VmContext, callee: &Value, args: &[Value]) -> Result<Value, String> {
+        match callee {
+            Value::String(name) => Self::call_named_function(context, name, args),
+            Value::BuiltinFunction(func) => {
+                BuiltinFunctions::check_function_args(*func, args)?;
+                func(args)
+            }
+            Value::UserFunction(function) => {
+                Self::evaluate_user_function(context, function, &function.name, args)
+            }
+            other => Err(format!("call() expects a function or a function name, got a {}", other.value_type())),
+        }
+    }
+
+    /// Call `function`, which is bound as `binding`. Under
+    /// `FunctionScope::Lexical` the scopes above the one holding that binding
+    /// are set aside for the call, so the body only sees its own frame and the
+    /// definition site; a function no longer bound sees the current scope.
     fn evaluate_user_function(
         context: &mut VmContext,
         function: &crate::engine::lang::ast::FunctionDef,
+        binding: &str,
         args: &[Value]
     ) -> Result<Value, String> {
-        if args.len() != function.params.len() {
-            return Err(
-                format!(
-                    "Function '{}' expects {} arguments, got {}",
-                    function.name,
-                    function.params.len(),
-                    args.len()
-                )
-            );
+        FunctionSignature::check_user_args(function, args)?;
+
+        let memo_key = if function.pure || context.memoized.contains(&function.name) {
+            MemoKey::from_args(args)
+        } else {
+            None
+        };
+        if let Some(key) = &memo_key
+            && let Some(cached) = context.memo.get(&function.name, key)
+        {
+            return Ok(cached.clone());
         }
 
+        let hidden = match context.options.function_scope {
+            FunctionScope::Dynamic => Vec::new(),
+            FunctionScope::Lexical => {
+                let defined_in = match context.env.lookup(binding) {
+                    Some(Value::UserFunction(_)) => context.env.scope_of(binding).unwrap_or(0),
+                    _ => context.env.depth().saturating_sub(1),
+                };
+                context.env.hide_scopes_above(defined_in)
+            }
+        };
         context.env.enter_scope();
 
         for (param, arg) in function.params.iter().zip(args.iter()) {
@@ -149,7 +291,11 @@ impl ExprEvaluator {
         };
 
         context.env.exit_scope();
+        context.env.restore_scopes(hidden);
 
+        if let (Some(key), Ok(value)) = (memo_key, &result) {
+            context.memo.insert(&function.name, key, value.clone());
+        }
         result
     }
 
@@ -169,6 +315,10 @@ impl ExprEvaluator {
                     let val = Self::evaluate_expr(context, value)?;
                     context.env.insert(name, val);
                 }
+                crate::engine::lang::ast::Statement::MemberAssign { object, property, value } => {
+                    let val = Self::evaluate_expr(context, value)?;
+                    Self::assign_member(context, object, property, val)?;
+                }
                 crate::engine::lang::ast::Statement::If { condition, then_body, else_body } => {
                     let cond_val = Self::evaluate_expr(context, condition)?;
                     if Self::is_truthy(&cond_val) {
@@ -180,6 +330,9 @@ impl ExprEvaluator {
                 crate::engine::lang::ast::Statement::Return(expr) => {
                     return Self::evaluate_expr(context, expr);
                 }
+                crate::engine::lang::ast::Statement::Assert(expr) => {
+                    Self::check_assertion(context, expr)?;
+                }
                 crate::engine::lang::ast::Statement::Expression(expr) => {
                     last_value = Self::evaluate_expr(context, expr)?;
                 }
@@ -189,6 +342,31 @@ impl ExprEvaluator {
         Ok(last_value)
     }
 
+    /// Set `property` on the map bound to `object`, updating the binding in
+    /// whichever scope holds it so the change outlives the function call.
+    fn assign_member(context: &mut VmContext, object: &str, property: &str, value: Value) -> Result<(), String> {
+        let mut map = match context.env.lookup(object) {
+            Some(Value::Map(map)) => map.clone(),
+            Some(other) => {
+                return Err(format!("Cannot assign property '{}' on '{}': {} is not a map", property, object, other));
+            }
+            None => return Err(format!("Undefined variable: {}", object)),
+        };
+        map.insert(property.to_string(), value);
+        context.env.set(object, Value::Map(map));
+        Ok(())
+    }
+
+    /// Evaluate `condition` and fail with its source text when it is falsy.
+    pub fn check_assertion(context: &mut VmContext, condition: &Expr) -> Result<(), String> {
+        let value = Self::evaluate_expr(context, condition)?;
+        if Self::is_truthy(&value) {
+            Ok(())
+        } else {
+            Err(format!("assertion failed: {}", condition))
+        }
+    }
+
     fn add_values(left: &Value, right: &Value) -> Result<Value, String> {
         match (left, right) {
             (Value::Number(a), Value::Number(b)) => Ok(Value::Number(a + b)),
@@ -225,6 +403,16 @@ impl ExprEvaluator {
     {
         match (left, right) {
             (Value::Number(a), Value::Number(b)) => Ok(Value::Bool(op(*a, *b))),
+            // Attributes stored as strings (e.g. agent fields) compare numerically
+            // against numbers when they hold an integer; equality stays strict
+            (Value::String(s), Value::Number(b)) => match s.trim().parse::<i64>() {
+                Ok(a) => Ok(Value::Bool(op(a, *b))),
+                Err(_) => Err(format!("Cannot compare non-numeric string {:?} with a number", s)),
+            },
+            (Value::Number(a), Value::String(s)) => match s.trim().parse::<i64>() {
+                Ok(b) => Ok(Value::Bool(op(*a, b))),
+                Err(_) => Err(format!("Cannot compare a number with non-numeric string {:?}", s)),
+            },
             _ => Err("Cannot compare non-numbers".to_string()),
         }
     }
@@ -237,6 +425,7 @@ impl ExprEvaluator {
             (Value::List(a), Value::List(b)) => a == b,
             (Value::Null, Value::Null) => true,
             (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Bytes(a), Value::Bytes(b)) => a == b,
             (Value::BuiltinFunction(a), Value::BuiltinFunction(b)) => {
                 // Compare function pointers
                 std::ptr::eq(a as *const _, b as *const _)
@@ -268,6 +457,21 @@ impl ExprEvaluator {
         }
     }
 
+    /// Numeric containment for `x in start..end`; strings holding an integer
+    /// are compared numerically, as with `<` and `>`
+    fn in_range(value: &Value, start: &Value, end: &Value, inclusive: bool) -> Result<Value, String> {
+        let (Value::Number(_), Value::Number(_)) = (start, end) else {
+            return Err("Range bounds must be numbers".to_string());
+        };
+        let above_start = Self::compare_values(value, start, |a, b| a >= b)?;
+        let below_end = if inclusive {
+            Self::compare_values(value, end, |a, b| a <= b)?
+        } else {
+            Self::compare_values(value, end, |a, b| a < b)?
+        };
+        Ok(Value::Bool(matches!((above_start, below_end), (Value::Bool(true), Value::Bool(true)))))
+    }
+
     /// Evaluate member access expressions like agent.id, case.priority, etc.
     fn evaluate_member_access(
         context: &mut VmContext,
@@ -299,11 +503,19 @@ impl ExprEvaluator {
 
     /// Handle special member access for complex objects
     fn evaluate_special_member_access(
-        _context: &mut VmContext,
+        context: &mut VmContext,
         object: &str,
         property: &str,
-        _obj_value: &Value
+        obj_value: &Value
     ) -> Result<Value, String> {
+        // `score.<name>` reads a sub-score of the case; unset ones are 0 so
+        // they can be summed without guards
+        if object == "score" && matches!(obj_value, Value::Number(_)) {
+            return match context.env.lookup("scores") {
+                Some(Value::Map(scores)) => Ok(scores.get(property).cloned().unwrap_or(Value::Number(0))),
+                _ => Ok(Value::Number(0)),
+            };
+        }
         // This can be extended for custom object types in the future
         Err(format!("Cannot access property '{}' on object '{}' of this type", property, object))
     }
@@ -324,6 +536,8 @@ impl ExprEvaluator {
                     "priority" => context.env.lookup("priority").cloned().ok_or_else(|| "Case priority not available".to_string()),
                     "score" => context.env.lookup("score").cloned().ok_or_else(|| "Case score not available".to_string()),
                     "customer" => context.env.lookup("customer").cloned().ok_or_else(|| "Case customer not available".to_string()),
+                    "created_at" => context.env.lookup("created_at").cloned().ok_or_else(|| "Case created_at not available".to_string()),
+                    "rank" => context.env.lookup("rank").cloned().ok_or_else(|| "Case rank not available".to_string()),
                     _ => Err(format!("Unknown case property: {}", property))
                 }
             }
@@ -374,6 +588,7 @@ impl ExprEvaluator {
             Value::List(l) => !l.is_empty(),
             Value::Null => false,
             Value::Map(m) => !m.is_empty(),
+            Value::Bytes(b) => !b.is_empty(),
             Value::BuiltinFunction(_) => true,
             Value::UserFunction(_) => true,
         }
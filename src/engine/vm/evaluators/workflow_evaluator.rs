@@ -1,6 +1,6 @@
 use crate::{
     engine::{
-        lang::ast::{ Workflow, Phase, Rule, MatchRule, FilterRule, SortRule, SortOrder, Value },
+        lang::ast::{ Workflow, Phase, PhaseKind, Rule, Action, MatchRule, MatchAction, MatchStrategy, FilterRule, SortRule, SortOrder, GroupRule, SampleRule, RankRule, RankMode, NormalizeRule, Expr, Value },
         vm::{
             context::VmContext,
             evaluators::{ expr_evaluator::ExprEvaluator, action_evaluator::ActionEvaluator },
@@ -8,55 +8,103 @@ use crate::{
     },
     models::case::CaseConfig,
 };
+use std::{ collections::{ BTreeMap, HashMap }, time::Instant };
+
+/// Variable that receives the counts of the most recent group phase
+pub const GROUP_COUNTS_VAR: &str = "group_counts";
+
+/// Variable that receives the batch aggregates when `EngineOptions::batch_aggregates` is set
+pub const BATCH_AGGREGATES_VAR: &str = "cases";
+
+/// Separator placed between key values when grouping by several keys
+const GROUP_KEY_SEPARATOR: &str = "|";
 
 pub struct WorkflowEvaluator;
 
 impl WorkflowEvaluator {
+    /// Run `workflow` on `cases`, unless it has a guard that is falsy, in
+    /// which case the cases are returned as they are and a log line says so.
     pub fn execute_workflow(
         context: &mut VmContext,
         workflow: &Workflow,
         cases: Vec<CaseConfig>
+    ) -> Result<Vec<CaseConfig>, String> {
+        if let Some(guard) = &workflow.guard {
+            let value = ExprEvaluator::evaluate_expr(context, guard)
+                .map_err(|e| format!("workflow '{}': guard '{}' failed: {}", workflow.name, guard, e))?;
+            if !ExprEvaluator::is_truthy(&value) {
+                tracing::debug!("Skipping workflow {}: guard is false", workflow.name);
+                context.logs.push(format!("skipped workflow '{}': guard '{}' is false", workflow.name, guard));
+                return Ok(cases);
+            }
+        }
+        Self::execute_workflow_unguarded(context, workflow, cases)
+    }
+
+    /// Run `workflow` on `cases` whatever its guard says.
+    pub fn execute_workflow_unguarded(
+        context: &mut VmContext,
+        workflow: &Workflow,
+        cases: Vec<CaseConfig>
     ) -> Result<Vec<CaseConfig>, String> {
         tracing::debug!("Executing workflow: {}", workflow.name);
+        context.current_workflow = workflow.name.clone();
 
+        if !context.options.resolution_cache {
+            return Self::execute_phases(context, workflow, cases);
+        }
+        context.env.enable_resolution_cache();
+        let result = Self::execute_phases(context, workflow, cases);
+        context.env.disable_resolution_cache();
+        result
+    }
+
+    fn execute_phases(
+        context: &mut VmContext,
+        workflow: &Workflow,
+        cases: Vec<CaseConfig>
+    ) -> Result<Vec<CaseConfig>, String> {
         let mut processed_cases = cases;
 
         for phase in &workflow.phases {
-            match phase {
-                Phase::Score(rules) => {
-                    processed_cases = Self::execute_score_phase_on_cases(
-                        context,
-                        rules,
-                        processed_cases
-                    )?;
-                }
-                Phase::Match(rules) => {
-                    processed_cases = Self::execute_match_phase_on_cases(
-                        context,
-                        rules,
-                        processed_cases
-                    )?;
-                }
-                Phase::Filter(filter_rule) => {
-                    processed_cases = Self::execute_filter_phase(
-                        context,
-                        filter_rule,
-                        processed_cases
-                    )?;
-                }
-                Phase::Sort(sort_rule) => {
-                    processed_cases = Self::execute_sort_phase(
-                        context,
-                        sort_rule,
-                        processed_cases
-                    )?;
-                }
+            let started = context.timing.then(Instant::now);
+            // The aggregates are bound in a scope of their own for the phase,
+            // so they never overwrite a variable the caller bound
+            let aggregates = context.options.batch_aggregates;
+            if aggregates {
+                context.env.enter_scope();
+                context.env.insert(BATCH_AGGREGATES_VAR, Self::batch_aggregates(&processed_cases));
+            }
+            let result = Self::execute_phase(context, phase, processed_cases);
+            if aggregates {
+                context.env.exit_scope_except(BATCH_AGGREGATES_VAR);
+            }
+            processed_cases = result?;
+
+            if let Some(started) = started {
+                context.phase_timings.push((phase.kind(), started.elapsed()));
             }
         }
 
         Ok(processed_cases)
     }
 
+    fn execute_phase(context: &mut VmContext, phase: &Phase, cases: Vec<CaseConfig>) -> Result<Vec<CaseConfig>, String> {
+        match phase {
+            Phase::Score { rules, otherwise } => {
+                Self::execute_score_phase_on_cases(context, rules, otherwise.as_ref(), cases)
+            }
+            Phase::Match(rules, strategy) => Self::execute_match_phase_on_cases(context, rules, *strategy, cases),
+            Phase::Filter(filter_rule) => Self::execute_filter_phase(context, filter_rule, cases),
+            Phase::Sort(sort_rule) => Self::execute_sort_phase(context, sort_rule, cases),
+            Phase::Sample(sample_rule) => Ok(Self::execute_sample_phase(context, sample_rule, cases)),
+            Phase::Group(group_rule) => Self::execute_group_phase(context, group_rule, cases),
+            Phase::Rank(rank_rule) => Self::execute_rank_phase(context, rank_rule, cases),
+            Phase::Combine(expr) => Self::execute_combine_phase(context, expr, cases),
+            Phase::Normalize(normalize_rule) => Ok(Self::execute_normalize_phase(normalize_rule, cases)),
+        }
+    }
+
     pub fn setup_case_context(context: &mut VmContext, case: &CaseConfig) -> Result<(), String> {
         context.env.enter_scope();
 
@@ -65,6 +113,10 @@ impl WorkflowEvaluator {
         context.env.insert("status", Value::String(case.status.clone()));
         context.env.insert("priority", Value::Number(case.priority as i64));
         context.env.insert("score", Value::Number(case.score));
+        context.env.insert("created_at", case.created_at.map_or(Value::Null, Value::Number));
+        context.env.insert("rank", case.rank.map_or(Value::Null, |rank| Value::Number(rank as i64)));
+        context.env.insert("scores", ActionEvaluator::sub_scores_value(case));
+        context.env.insert("assigned_to", case.assigned_to.clone().map_or(Value::Null, Value::String));
 
         if let Some(customer) = &case.customer {
             context.env.insert("customer", Value::String(customer.clone()));
@@ -75,17 +127,60 @@ impl WorkflowEvaluator {
         Ok(())
     }
 
+    /// Count of the cases plus the average (truncated), maximum and minimum of
+    /// their priorities and scores. Averages and extremes are null for an
+    /// empty batch.
+    pub fn batch_aggregates(cases: &[CaseConfig]) -> Value {
+        let mut aggregates = BTreeMap::new();
+        aggregates.insert("count".to_string(), Value::Number(cases.len() as i64));
+
+        let priorities: Vec<i64> = cases.iter().map(|case| case.priority as i64).collect();
+        let scores: Vec<i64> = cases.iter().map(|case| case.score).collect();
+        for (field, values) in [("priority", priorities), ("score", scores)] {
+            let sum: i128 = values.iter().map(|&v| v as i128).sum();
+            let avg = (!values.is_empty()).then(|| (sum / values.len() as i128) as i64);
+            let number = |n: Option<i64>| n.map_or(Value::Null, Value::Number);
+            aggregates.insert(format!("avg_{}", field), number(avg));
+            aggregates.insert(format!("max_{}", field), number(values.iter().max().copied()));
+            aggregates.insert(format!("min_{}", field), number(values.iter().min().copied()));
+        }
+        Value::Map(aggregates)
+    }
+
+    /// Evaluate `expr` for `case` in the environment score rules see: the
+    /// case fields over globals, the agent and user functions.
+    pub fn evaluate_for_case(context: &mut VmContext, case: &CaseConfig, expr: &Expr) -> Result<Value, String> {
+        Self::setup_case_context(context, case)?;
+        let result = ExprEvaluator::evaluate_expr(context, expr);
+        context.env.exit_scope();
+        result
+    }
+
     pub fn execute_score_phase(
         context: &mut VmContext,
         rules: &[Rule],
+        otherwise: Option<&Action>,
         case: &mut CaseConfig
     ) -> Result<(), String> {
-        for rule in rules {
+        let mut any_fired = false;
+        for (index, rule) in rules.iter().enumerate() {
             let condition_result = ExprEvaluator::evaluate_expr(context, &rule.condition)?;
+            let fired = ExprEvaluator::is_truthy(&condition_result);
 
-            if ExprEvaluator::is_truthy(&condition_result) {
+            if fired {
+                any_fired = true;
                 ActionEvaluator::execute_action(context, &rule.action, case)?;
             }
+            context.record_trace(PhaseKind::Score, case.id, index, rule, fired, case.score);
+        }
+
+        if let Some(action) = otherwise {
+            if !any_fired {
+                ActionEvaluator::execute_action(context, action, case)?;
+            }
+            // Formatted lazily, so nothing is built unless tracing is on
+            let rule = format_args!("otherwise then {}", action);
+            context.record_trace(PhaseKind::Score, case.id, rules.len(), &rule, !any_fired, case.score);
         }
         Ok(())
     }
@@ -95,32 +190,77 @@ impl WorkflowEvaluator {
         rules: &[MatchRule],
         case: &mut CaseConfig
     ) -> Result<(), String> {
-        for rule in rules {
+        for (index, rule) in rules.iter().enumerate() {
             let condition_result = ExprEvaluator::evaluate_expr(context, &rule.condition)?;
+            let fired = ExprEvaluator::is_truthy(&condition_result);
+            context.record_trace(PhaseKind::Match, case.id, index, rule, fired, case.score);
 
-            if ExprEvaluator::is_truthy(&condition_result) {
+            if fired {
                 ActionEvaluator::execute_match_action(context, &rule.action, case)?;
-                break;
+                if !matches!(rule.action, MatchAction::UpdateGlobal(_)) {
+                    break;
+                }
             }
         }
         Ok(())
     }
 
+    /// Like `execute_match_phase`, but the case takes the first satisfied
+    /// assignment rule at or after `context.round_robin_next`, wrapping
+    /// around. Global updates run for every rule that fires, as usual.
+    pub fn execute_round_robin_match_phase(
+        context: &mut VmContext,
+        rules: &[MatchRule],
+        case: &mut CaseConfig
+    ) -> Result<(), String> {
+        let mut satisfied = Vec::new();
+        for (index, rule) in rules.iter().enumerate() {
+            let condition_result = ExprEvaluator::evaluate_expr(context, &rule.condition)?;
+            let fired = ExprEvaluator::is_truthy(&condition_result);
+            context.record_trace(PhaseKind::Match, case.id, index, rule, fired, case.score);
+
+            if fired {
+                if matches!(rule.action, MatchAction::UpdateGlobal(_)) {
+                    ActionEvaluator::execute_match_action(context, &rule.action, case)?;
+                } else {
+                    satisfied.push(index);
+                }
+            }
+        }
+
+        let next = context.round_robin_next;
+        let Some(&chosen) = satisfied.iter().find(|&&index| index >= next).or(satisfied.first()) else {
+            return Ok(());
+        };
+        context.round_robin_next = chosen + 1;
+        ActionEvaluator::execute_match_action(context, &rules[chosen].action, case)
+    }
+
     pub fn execute_score_phase_on_cases(
         context: &mut VmContext,
         rules: &[Rule],
+        otherwise: Option<&Action>,
         cases: Vec<CaseConfig>
     ) -> Result<Vec<CaseConfig>, String> {
         let mut processed_cases = Vec::new();
 
-        for case in cases {
-            let mut case_copy = case;
-            Self::setup_case_context(context, &case_copy)?;
+        for mut case in cases {
+            let original = context.keeps_failed_cases().then(|| case.clone());
+            Self::setup_case_context(context, &case)?;
 
-            Self::execute_score_phase(context, rules, &mut case_copy)?;
+            let result = Self::execute_score_phase(context, rules, otherwise, &mut case);
 
             context.env.exit_scope();
-            processed_cases.push(case_copy);
+            match result {
+                Ok(()) => processed_cases.push(case),
+                Err(error) => {
+                    if context.handle_case_error(PhaseKind::Score, case.id, error)?
+                        && let Some(original) = original
+                    {
+                        processed_cases.push(original);
+                    }
+                }
+            }
         }
 
         Ok(processed_cases)
@@ -129,17 +269,31 @@ impl WorkflowEvaluator {
     pub fn execute_match_phase_on_cases(
         context: &mut VmContext,
         rules: &[MatchRule],
+        strategy: MatchStrategy,
         cases: Vec<CaseConfig>
     ) -> Result<Vec<CaseConfig>, String> {
         let mut processed_cases = Vec::new();
+        context.round_robin_next = 0;
 
-        for case in cases {
-            let mut case_copy = case;
-            Self::setup_case_context(context, &case_copy)?;
+        for mut case in cases {
+            let original = context.keeps_failed_cases().then(|| case.clone());
+            Self::setup_case_context(context, &case)?;
 
             let pre_match_vars = Self::get_persistent_variables(context);
 
-            Self::execute_match_phase(context, rules, &mut case_copy)?;
+            let result = match strategy {
+                MatchStrategy::FirstMatch => Self::execute_match_phase(context, rules, &mut case),
+                MatchStrategy::RoundRobin => Self::execute_round_robin_match_phase(context, rules, &mut case),
+            };
+            if let Err(error) = result {
+                context.env.exit_scope();
+                if context.handle_case_error(PhaseKind::Match, case.id, error)?
+                    && let Some(original) = original
+                {
+                    processed_cases.push(original);
+                }
+                continue;
+            }
 
             let post_match_vars = Self::get_persistent_variables(context);
 
@@ -151,7 +305,7 @@ impl WorkflowEvaluator {
                 }
             }
 
-            processed_cases.push(case_copy);
+            processed_cases.push(case);
         }
 
         Ok(processed_cases)
@@ -166,15 +320,20 @@ impl WorkflowEvaluator {
         let original_count = cases.len();
 
         for case in cases {
-            Self::setup_case_context(context, &case)?;
+            let condition_result = Self::evaluate_for_case(context, &case, &filter_rule.condition);
 
-            let condition_result = ExprEvaluator::evaluate_expr(context, &filter_rule.condition)?;
+            let keep = match condition_result {
+                Ok(value) => {
+                    let keep = ExprEvaluator::is_truthy(&value);
+                    context.record_trace(PhaseKind::Filter, case.id, 0, &filter_rule.condition, keep, case.score);
+                    keep
+                }
+                Err(error) => context.handle_case_error(PhaseKind::Filter, case.id, error)?,
+            };
 
-            if ExprEvaluator::is_truthy(&condition_result) {
+            if keep {
                 filtered_cases.push(case);
             }
-
-            context.env.exit_scope();
         }
 
         tracing::debug!("Filtered {} cases to {} cases", original_count, filtered_cases.len());
@@ -188,14 +347,19 @@ impl WorkflowEvaluator {
         cases: Vec<CaseConfig>
     ) -> Result<Vec<CaseConfig>, String> {
         let mut case_key_pairs = Vec::new();
+        // Cases whose key failed to evaluate keep their relative order at the end
+        let mut unkeyed_cases = Vec::new();
 
         for case in cases {
-            Self::setup_case_context(context, &case)?;
-
-            let sort_key = ExprEvaluator::evaluate_expr(context, &sort_rule.key)?;
-
-            case_key_pairs.push((case, sort_key));
-            context.env.exit_scope();
+            match Self::evaluate_for_case(context, &case, &sort_rule.key) {
+                Ok(sort_key) => case_key_pairs.push((case, sort_key)),
+                Err(error) => {
+                    let error = format!("Case {}: sort key '{}' failed: {}", case.id, sort_rule.key, error);
+                    if context.handle_case_error(PhaseKind::Sort, case.id, error)? {
+                        unkeyed_cases.push(case);
+                    }
+                }
+            }
         }
 
         case_key_pairs.sort_by(|(_, a), (_, b)| {
@@ -209,6 +373,7 @@ impl WorkflowEvaluator {
         let sorted_cases: Vec<CaseConfig> = case_key_pairs
             .into_iter()
             .map(|(case, _)| case)
+            .chain(unkeyed_cases)
             .collect();
 
         tracing::debug!("Sorted {} cases by key expression", sorted_cases.len());
@@ -216,6 +381,222 @@ impl WorkflowEvaluator {
         Ok(sorted_cases)
     }
 
+    /// Store each case's 1-based position by key in `rank`. Cases with equal
+    /// keys share a rank. The cases keep their order unless the rule is
+    /// `sorted`; a case whose key failed gets no rank and, when sorted, goes last.
+    pub fn execute_rank_phase(
+        context: &mut VmContext,
+        rank_rule: &RankRule,
+        cases: Vec<CaseConfig>
+    ) -> Result<Vec<CaseConfig>, String> {
+        let mut ranked_cases = Vec::with_capacity(cases.len());
+        // Index into `ranked_cases` and key of every case whose key evaluated
+        let mut keys = Vec::with_capacity(cases.len());
+
+        for mut case in cases {
+            case.rank = None;
+            match Self::evaluate_for_case(context, &case, &rank_rule.key) {
+                Ok(key) => keys.push((ranked_cases.len(), key)),
+                Err(error) => {
+                    let error = format!("Case {}: rank key '{}' failed: {}", case.id, rank_rule.key, error);
+                    if !context.handle_case_error(PhaseKind::Rank, case.id, error)? {
+                        continue;
+                    }
+                }
+            }
+            ranked_cases.push(case);
+        }
+
+        keys.sort_by(|(_, a), (_, b)| {
+            let cmp = Self::compare_values(a, b);
+            match rank_rule.order {
+                SortOrder::Asc => cmp,
+                SortOrder::Desc => cmp.reverse(),
+            }
+        });
+
+        let mut rank = 0;
+        for (position, (index, key)) in keys.iter().enumerate() {
+            let tied = position > 0 && Self::compare_values(&keys[position - 1].1, key).is_eq();
+            if !tied {
+                rank = match rank_rule.mode {
+                    RankMode::Competition => position + 1,
+                    RankMode::Dense => rank + 1,
+                };
+            }
+            ranked_cases[*index].rank = Some(rank as i32);
+        }
+
+        tracing::debug!("Ranked {} cases", keys.len());
+
+        if !rank_rule.sorted {
+            return Ok(ranked_cases);
+        }
+        let mut slots: Vec<Option<CaseConfig>> = ranked_cases.into_iter().map(Some).collect();
+        let mut sorted_cases: Vec<CaseConfig> = keys
+            .iter()
+            .filter_map(|(index, _)| slots[*index].take())
+            .collect();
+        sorted_cases.extend(slots.into_iter().flatten());
+        Ok(sorted_cases)
+    }
+
+    /// Set each case's score to `expr`, evaluated like a score rule.
+    pub fn execute_combine_phase(
+        context: &mut VmContext,
+        expr: &Expr,
+        cases: Vec<CaseConfig>
+    ) -> Result<Vec<CaseConfig>, String> {
+        let mut combined_cases = Vec::with_capacity(cases.len());
+
+        for mut case in cases {
+            match Self::evaluate_for_case(context, &case, expr) {
+                Ok(Value::Number(score)) => {
+                    case.score = score;
+                    combined_cases.push(case);
+                }
+                result => {
+                    let error = match result {
+                        Err(error) => error,
+                        Ok(value) => format!("Combined score must be a number, got {}", value.value_type()),
+                    };
+                    if context.handle_case_error(PhaseKind::Combine, case.id, error)? {
+                        combined_cases.push(case);
+                    }
+                }
+            }
+        }
+
+        Ok(combined_cases)
+    }
+
+    /// Map the batch's scores linearly from their own minimum and maximum onto
+    /// `low..high`, rounding halves towards `high`. When every score is the
+    /// same they all become the middle of the range.
+    pub fn execute_normalize_phase(normalize_rule: &NormalizeRule, mut cases: Vec<CaseConfig>) -> Vec<CaseConfig> {
+        let (Some(min), Some(max)) = (cases.iter().map(|c| c.score).min(), cases.iter().map(|c| c.score).max()) else {
+            return cases;
+        };
+        let span = normalize_rule.high.abs_diff(normalize_rule.low) as u128;
+        let descending = normalize_rule.high < normalize_rule.low;
+
+        for case in &mut cases {
+            let offset = if min == max {
+                Self::div_round(span, 2)
+            } else {
+                Self::div_round(case.score.abs_diff(min) as u128 * span, max.abs_diff(min) as u128)
+            };
+            let offset = if descending { -(offset as i128) } else { offset as i128 };
+            case.score = (normalize_rule.low as i128 + offset) as i64;
+        }
+
+        tracing::debug!("Normalized {} scores from {}..{} to {}..{}", cases.len(), min, max, normalize_rule.low, normalize_rule.high);
+
+        cases
+    }
+
+    /// `numerator / denominator` with halves rounded up
+    fn div_round(numerator: u128, denominator: u128) -> u128 {
+        let quotient = numerator / denominator;
+        if 2 * (numerator % denominator) >= denominator { quotient + 1 } else { quotient }
+    }
+
+    /// Count cases per composite key and store the counts as a map in
+    /// `group_counts`. Key values are joined with `|`, e.g. `"bug|open"`, after
+    /// escaping any `|` or `\` inside them with a `\`, so different
+    /// combinations never share a key. Cases pass through unchanged.
+    pub fn execute_group_phase(
+        context: &mut VmContext,
+        group_rule: &GroupRule,
+        cases: Vec<CaseConfig>
+    ) -> Result<Vec<CaseConfig>, String> {
+        let mut counts: HashMap<Vec<String>, i64> = HashMap::new();
+        let mut grouped_cases = Vec::with_capacity(cases.len());
+
+        for case in cases {
+            Self::setup_case_context(context, &case)?;
+
+            let key = group_rule.keys
+                .iter()
+                .map(|key| ExprEvaluator::evaluate_expr(context, key).map(|v| Self::value_to_string(&v)))
+                .collect::<Result<Vec<_>, _>>();
+            context.env.exit_scope();
+
+            match key {
+                Ok(parts) => {
+                    *counts.entry(parts).or_insert(0) += 1;
+                    grouped_cases.push(case);
+                }
+                Err(error) => {
+                    if context.handle_case_error(PhaseKind::Group, case.id, error)? {
+                        grouped_cases.push(case);
+                    }
+                }
+            }
+        }
+
+        tracing::debug!("Grouped {} cases into {} groups", grouped_cases.len(), counts.len());
+
+        let counts = counts
+            .into_iter()
+            .map(|(parts, count)| (Self::group_key(&parts), Value::Number(count)))
+            .collect();
+        context.env.insert(GROUP_COUNTS_VAR, Value::Map(counts));
+
+        Ok(grouped_cases)
+    }
+
+    /// Join key values with `GROUP_KEY_SEPARATOR`, escaping it and the escape
+    /// character inside each value.
+    fn group_key(parts: &[String]) -> String {
+        parts
+            .iter()
+            .map(|part| part.replace('\\', "\\\\").replace(GROUP_KEY_SEPARATOR, "\\|"))
+            .collect::<Vec<_>>()
+            .join(GROUP_KEY_SEPARATOR)
+    }
+
+    /// Keep a random subset of the cases, chosen by reservoir sampling with the
+    /// context's RNG. The kept cases stay in their original order.
+    pub fn execute_sample_phase(
+        context: &mut VmContext,
+        sample_rule: &SampleRule,
+        cases: Vec<CaseConfig>
+    ) -> Vec<CaseConfig> {
+        let size = match sample_rule {
+            SampleRule::Count(count) => *count,
+            SampleRule::Percent(percent) => {
+                let percent = (*percent).min(100) as usize;
+                (cases.len() * percent + 50) / 100
+            }
+        };
+        if size >= cases.len() {
+            return cases;
+        }
+
+        let mut reservoir: Vec<usize> = (0..size).collect();
+        for index in size..cases.len() {
+            let slot = context.rng.below(index + 1);
+            if slot < size {
+                reservoir[slot] = index;
+            }
+        }
+        reservoir.sort_unstable();
+
+        let original_count = cases.len();
+        let mut picks = reservoir.into_iter().peekable();
+        let sampled: Vec<CaseConfig> = cases
+            .into_iter()
+            .enumerate()
+            .filter(|(index, _)| picks.next_if_eq(index).is_some())
+            .map(|(_, case)| case)
+            .collect();
+
+        tracing::debug!("Sampled {} of {} cases", sampled.len(), original_count);
+
+        sampled
+    }
+
     fn compare_values(a: &Value, b: &Value) -> std::cmp::Ordering {
         match (a, b) {
             (Value::Number(a), Value::Number(b)) => a.cmp(b),
@@ -237,6 +618,7 @@ impl WorkflowEvaluator {
             Value::Null => "null".to_string(),
             Value::List(_) => "list".to_string(),
             Value::Map(_) => "map".to_string(),
+            Value::Bytes(_) => "bytes".to_string(),
             Value::BuiltinFunction(_) => "builtin_function".to_string(),
             Value::UserFunction(f) => format!("user_function_{}", f.name),
         }
@@ -247,7 +629,7 @@ impl WorkflowEvaluator {
         let mut persistent_vars = HashMap::new();
 
         if let Some(current_scope) = context.env.env.last() {
-            for (name, value) in current_scope {
+            for (name, value) in current_scope.iter() {
                 if
                     !matches!(
                         name.as_str(),
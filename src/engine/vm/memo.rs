@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+use crate::engine::lang::ast::Value;
+
+/// A hashable copy of one argument. Lists, maps and functions have no key,
+/// so calls taking them are never cached.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MemoKey {
+    Number(i64),
+    String(String),
+    Bool(bool),
+    Null,
+    Bytes(Vec<u8>),
+}
+
+impl MemoKey {
+    pub fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Number(n) => Some(MemoKey::Number(*n)),
+            Value::String(s) => Some(MemoKey::String(s.clone())),
+            Value::Bool(b) => Some(MemoKey::Bool(*b)),
+            Value::Null => Some(MemoKey::Null),
+            Value::Bytes(bytes) => Some(MemoKey::Bytes(bytes.clone())),
+            Value::List(_) | Value::Map(_) | Value::BuiltinFunction(_) | Value::UserFunction(_) => None,
+        }
+    }
+
+    /// Keys for a whole argument list, or `None` if any argument can't be keyed.
+    pub fn from_args(args: &[Value]) -> Option<Vec<Self>> {
+        args.iter().map(Self::from_value).collect()
+    }
+}
+
+/// Most results kept per function; storing one more drops that function's
+/// earlier results.
+pub const MEMO_CAPACITY: usize = 4096;
+
+/// Results of memoized user functions, keyed by function name and then by
+/// arguments. A cached result may depend on other functions, so callers
+/// clear the whole cache whenever any function is registered or removed.
+#[derive(Debug, Clone, Default)]
+pub struct MemoCache {
+    results: HashMap<String, HashMap<Vec<MemoKey>, Value>>,
+}
+
+impl MemoCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, function: &str, args: &[MemoKey]) -> Option<&Value> {
+        self.results.get(function).and_then(|results| results.get(args))
+    }
+
+    pub fn insert(&mut self, function: &str, args: Vec<MemoKey>, value: Value) {
+        let results = match self.results.get_mut(function) {
+            Some(results) => results,
+            None => self.results.entry(function.to_string()).or_default(),
+        };
+        if results.len() >= MEMO_CAPACITY {
+            results.clear();
+        }
+        results.insert(args, value);
+    }
+
+    /// Number of cached results, over all functions.
+    pub fn len(&self) -> usize {
+        self.results.values().map(HashMap::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.values().all(HashMap::is_empty)
+    }
+
+    pub fn clear(&mut self) {
+        self.results.clear();
+    }
+}
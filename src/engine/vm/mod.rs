@@ -3,6 +3,10 @@ pub mod context;
 pub mod stack;
 pub mod environment;
 pub mod evaluators;
+pub mod trace;
+pub mod rng;
+pub mod clock;
+pub mod memo;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,49 @@
+/// Seed used until one is set explicitly, so runs are reproducible by default.
+pub const DEFAULT_SEED: u64 = 0x5EED_2077;
+
+/// Small deterministic PRNG (SplitMix64). Not suitable for anything
+/// security-related; it only needs to be fast and reproducible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A generator for `stream`, seeded from this one's state without
+    /// advancing it. Different streams draw independently and the same
+    /// stream always draws the same numbers.
+    pub fn derive(&self, stream: u64) -> Self {
+        let mut mixer = Self::new(self.state ^ stream.wrapping_mul(0xD6E8_FEB8_6659_FD93));
+        Self::new(mixer.next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform value in `0..bound`. `bound` must be non-zero.
+    pub fn below(&mut self, bound: usize) -> usize {
+        ((self.next_u64() as u128 * bound as u128) >> 64) as usize
+    }
+
+    /// Uniform value in `low..=high`. `low` must not exceed `high`.
+    pub fn in_range(&mut self, low: i64, high: i64) -> i64 {
+        let span = (i128::from(high) - i128::from(low) + 1) as u128;
+        let offset = (self.next_u64() as u128 * span) >> 64;
+        (i128::from(low) + offset as i128) as i64
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new(DEFAULT_SEED)
+    }
+}
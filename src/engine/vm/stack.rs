@@ -4,11 +4,13 @@ use crate::models::{ agent::AgentConfig, case::CaseConfig };
 pub struct VmStack {
     pub agent: Option<AgentConfig>,
     pub cases: Vec<CaseConfig>,
+    /// Agents `route to least_loaded` chooses from
+    pub agents: Vec<AgentConfig>,
 }
 
 impl VmStack {
     pub fn new(agent: Option<AgentConfig>, cases: Vec<CaseConfig>) -> Self {
-        VmStack { agent, cases }
+        VmStack { agent, cases, agents: Vec::new() }
     }
 
     pub fn set_agent(&mut self, agent: AgentConfig) {
@@ -27,6 +29,27 @@ impl VmStack {
         self.cases.last()
     }
 
+    /// Whether any case has `id`.
+    pub fn contains_id(&self, id: i32) -> bool {
+        self.cases.iter().any(|case| case.id == id)
+    }
+
+    /// Ids are not required to be unique; lookups by id use the first match.
+    /// There is no id index, so each lookup is a linear scan over the cases.
+    pub fn case_by_id(&self, id: i32) -> Option<&CaseConfig> {
+        self.cases.iter().find(|case| case.id == id)
+    }
+
+    pub fn case_by_id_mut(&mut self, id: i32) -> Option<&mut CaseConfig> {
+        self.cases.iter_mut().find(|case| case.id == id)
+    }
+
+    /// Remove the first case with `id`, keeping the order of the rest.
+    pub fn remove_case(&mut self, id: i32) -> Option<CaseConfig> {
+        let index = self.cases.iter().position(|case| case.id == id)?;
+        Some(self.cases.remove(index))
+    }
+
     pub fn is_empty(&self) -> bool {
         self.cases.is_empty()
     }
@@ -2,9 +2,10 @@
 mod tests {
     use crate::{
         engine::{
-            vm::corevm::CoreVM,
+            vm::{ corevm::CoreVM, evaluators::builtin_functions::BuiltinFunctions },
+            lang::parser::parse_expression,
             lang::ast::{
-                Workflow, Phase, Rule, MatchRule, Action, MatchAction,
+                Workflow, Phase, Rule, MatchRule, MatchStrategy, Action, MatchAction,
                 Expr, BinaryOperator, UnaryOperator, Value
             }
         },
@@ -19,6 +20,7 @@ mod tests {
             priority: 3,
             customer: Some("test_customer".to_string()),
             score: 0,
+            ..Default::default()
         }
     }
 
@@ -254,6 +256,384 @@ mod tests {
         assert_eq!(result, Value::Bool(true));
     }
 
+    #[test]
+    fn test_matches_any_and_all() {
+        let mut vm = CoreVM::new();
+        let list = |items: &[&str]| Expr::List(items.iter().map(|s| Expr::String(s.to_string())).collect());
+        let call = |name: &str, a: Expr, b: Expr| Expr::FunctionCall { name: name.to_string(), args: vec![a, b] };
+
+        // Overlap
+        let expr = call("matches_any", list(&["billing", "vip"]), list(&["vip", "network"]));
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Bool(true));
+        let expr = call("matches_all", list(&["billing", "vip"]), list(&["vip", "network"]));
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Bool(false));
+
+        // Subset
+        let expr = call("matches_all", list(&["vip"]), list(&["vip", "network"]));
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Bool(true));
+        let expr = call("matches_all", list(&[]), list(&["vip"]));
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Bool(true));
+
+        // Disjoint
+        let expr = call("matches_any", list(&["billing"]), list(&["network"]));
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Bool(false));
+
+        let expr = call("matches_any", Expr::String("billing".to_string()), list(&["billing"]));
+        assert!(vm.evaluate_expr(&expr).is_err());
+    }
+
+    #[test]
+    fn test_let_in_expression() {
+        let mut vm = CoreVM::new();
+        vm.context.env.insert("priority", Value::Number(3));
+
+        let expr = parse_expression("let base = priority * 10 in base + base").unwrap();
+        assert!(matches!(expr, Expr::LetIn { ref name, .. } if name == "base"));
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Number(60));
+
+        // The binding is gone once the body has been evaluated
+        assert!(vm.context.env.lookup("base").is_none());
+        let leaked = parse_expression("base").unwrap();
+        assert!(vm.evaluate_expr(&leaked).is_err());
+
+        // Inner bindings shadow outer ones and nest to the right
+        let expr = parse_expression("let priority = 1 in let x = priority + 1 in x * 10").unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Number(20));
+        assert_eq!(vm.context.env.lookup("priority"), Some(&Value::Number(3)));
+
+        // Membership tests in the bound value need parentheses
+        let expr = parse_expression("let hit = (\"a\" in [\"a\"]) in hit and true").unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Bool(true));
+        assert_eq!(expr.to_string(), "let hit = (\"a\" in [\"a\"]) in hit and true");
+    }
+
+    #[test]
+    fn test_coalesce() {
+        let mut vm = CoreVM::new();
+        vm.context.env.insert("customer", Value::String("".to_string()));
+        vm.context.env.insert("region", Value::Null);
+        let call = |args: Vec<Expr>| Expr::FunctionCall { name: "coalesce".to_string(), args };
+        let ident = |name: &str| Expr::Ident(name.to_string());
+        let string = |s: &str| Expr::String(s.to_string());
+
+        // Null and empty arguments are skipped
+        let expr = call(vec![ident("region"), ident("customer"), string("unknown")]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::String("unknown".to_string()));
+
+        // A present first argument wins, whatever its type
+        vm.context.env.insert("customer", Value::String("acme".to_string()));
+        let expr = call(vec![ident("customer"), string("unknown")]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::String("acme".to_string()));
+        let expr = call(vec![Expr::Number(0), string("unknown")]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Number(0));
+
+        let expr = call(vec![ident("region")]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Null);
+        assert!(vm.evaluate_expr(&call(vec![])).is_err());
+    }
+
+    #[test]
+    fn test_pad_and_format_number() {
+        let mut vm = CoreVM::new();
+        let call = |name: &str, args: Vec<Expr>| Expr::FunctionCall { name: name.to_string(), args };
+        let string = |s: &str| Value::String(s.to_string());
+
+        // Shorter inputs are padded on the left, longer ones left untouched
+        let expr = call("pad", vec![Expr::Number(42), Expr::Number(5)]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), string("   42"));
+        let expr = call("pad", vec![Expr::String("bug".to_string()), Expr::Number(4)]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), string(" bug"));
+        let expr = call("pad", vec![Expr::Number(123456), Expr::Number(3)]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), string("123456"));
+        let expr = call("pad", vec![Expr::Bool(true), Expr::Number(3)]);
+        assert!(vm.evaluate_expr(&expr).is_err());
+        let expr = call("pad", vec![Expr::Number(1), Expr::Number(BuiltinFunctions::MAX_PAD_WIDTH)]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), string(&format!("{:>1024}", 1)));
+        let expr = call("pad", vec![Expr::Number(1), Expr::Number(i64::MAX)]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap_err(), format!("pad() width must be at most 1024, got {}", i64::MAX));
+
+        let expr = call("format_number", vec![Expr::Number(1234567), Expr::Number(3)]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), string("1,234,567"));
+        let expr = call("format_number", vec![Expr::Number(999), Expr::Number(3)]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), string("999"));
+        vm.context.env.insert("delta", Value::Number(-1234567));
+        let expr = call("format_number", vec![Expr::Ident("delta".to_string()), Expr::Number(4)]);
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), string("-123,4567"));
+        let expr = call("format_number", vec![Expr::Number(1000), Expr::Number(0)]);
+        assert!(vm.evaluate_expr(&expr).is_err());
+    }
+
+    #[test]
+    fn test_null_coalescing_operator() {
+        let mut vm = CoreVM::new();
+        vm.context.env.insert("customer", Value::String("".to_string()));
+        vm.context.env.insert("region", Value::Null);
+        vm.context.env.insert("owner", Value::String("acme".to_string()));
+
+        // Missing left sides fall through to the right
+        let expr = parse_expression(r#"customer ?? "unknown""#).unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::String("unknown".to_string()));
+        let expr = parse_expression(r#"region ?? customer ?? "unknown""#).unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::String("unknown".to_string()));
+
+        // A present left side wins, and the right side is never evaluated
+        let expr = parse_expression("owner ?? undefined_function()").unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::String("acme".to_string()));
+        let expr = parse_expression("0 ?? undefined_function()").unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Number(0));
+        let expr = parse_expression("region ?? undefined_function()").unwrap();
+        assert!(vm.evaluate_expr(&expr).is_err());
+
+        // Binds tighter than comparisons, looser than arithmetic
+        let expr = parse_expression(r#"customer ?? "acme" == owner"#).unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Bool(true));
+        assert_eq!(expr.to_string(), r#"customer ?? "acme" == owner"#);
+        let expr = parse_expression("region ?? 1 + 2").unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Number(3));
+    }
+
+    #[test]
+    fn test_in_range() {
+        let mut vm = CoreVM::new();
+        let eval = |vm: &mut CoreVM, source: &str| vm.evaluate_expr(&parse_expression(source).unwrap());
+
+        // Exclusive ranges stop before the end
+        assert_eq!(eval(&mut vm, "2 in 2..5").unwrap(), Value::Bool(true));
+        assert_eq!(eval(&mut vm, "4 in 2..5").unwrap(), Value::Bool(true));
+        assert_eq!(eval(&mut vm, "5 in 2..5").unwrap(), Value::Bool(false));
+        assert_eq!(eval(&mut vm, "1 in 2..5").unwrap(), Value::Bool(false));
+
+        // Inclusive ranges keep both ends
+        assert_eq!(eval(&mut vm, "2 in 2..=5").unwrap(), Value::Bool(true));
+        assert_eq!(eval(&mut vm, "5 in 2..=5").unwrap(), Value::Bool(true));
+        assert_eq!(eval(&mut vm, "6 in 2..=5").unwrap(), Value::Bool(false));
+
+        // Bounds may be expressions, and numeric strings compare as numbers
+        vm.context.env.insert("level", Value::String("3".to_string()));
+        vm.context.env.insert("top", Value::Number(3));
+        assert_eq!(eval(&mut vm, "level in 1 + 1..top + 1").unwrap(), Value::Bool(true));
+        let expr = parse_expression("level in 1 + 1..=4").unwrap();
+        assert_eq!(expr.to_string(), "level in 1 + 1..=4");
+
+        assert_eq!(eval(&mut vm, "\"a\" in 1..3").unwrap_err(), "Cannot compare non-numeric string \"a\" with a number");
+        assert_eq!(eval(&mut vm, "1 in \"a\"..3").unwrap_err(), "Range bounds must be numbers");
+    }
+
+    #[test]
+    fn test_builtin_errors_show_signature() {
+        let mut vm = CoreVM::new();
+        let error = |vm: &mut CoreVM, source: &str| vm.evaluate_expr(&parse_expression(source).unwrap()).unwrap_err();
+
+        assert_eq!(
+            error(&mut vm, r#"contains("bug report", 3)"#),
+            "contains(list_or_string, item): argument 2 expected string, got number"
+        );
+        assert_eq!(
+            error(&mut vm, r#"contains([1, 2])"#),
+            "contains(list_or_string, item): expects 2 arguments, got 1"
+        );
+        assert_eq!(
+            error(&mut vm, r#"max(3, "7", 5)"#),
+            "max(numbers...): argument 2 expected number, got string"
+        );
+        assert_eq!(error(&mut vm, "min()"), "min(numbers...): expects at least 1 argument, got 0");
+        assert_eq!(
+            error(&mut vm, "pad(true, 4)"),
+            "pad(value, width): argument 1 expected number or string, got bool"
+        );
+        assert_eq!(error(&mut vm, "now(1)"), "now(): expects 0 arguments, got 1");
+    }
+
+    #[test]
+    fn test_contains_swapped_arguments() {
+        let mut vm = CoreVM::new();
+
+        let expr = parse_expression(r#"contains("bug", ["bug", "feature"])"#).unwrap();
+        let err = vm.evaluate_expr(&expr).unwrap_err();
+        assert!(
+            err.contains(r#"write contains(list, "bug") instead of contains("bug", list)"#),
+            "{}",
+            err
+        );
+
+        let expr = parse_expression("contains(3, [1, 2, 3])").unwrap();
+        let err = vm.evaluate_expr(&expr).unwrap_err();
+        assert!(err.contains("takes the list first"), "{}", err);
+
+        let expr = parse_expression(r#"contains(["bug", "feature"], "bug")"#).unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn test_is_empty() {
+        let mut vm = CoreVM::new();
+        let eval = |vm: &mut CoreVM, source: &str| vm.evaluate_expr(&parse_expression(source).unwrap());
+        vm.context.env.insert("nothing", Value::Null);
+        vm.context.env.insert("no_fields", Value::Map(Default::default()));
+        vm.context.env.insert("fields", Value::Map([("a".to_string(), Value::Number(1))].into()));
+
+        for (source, empty) in [
+            (r#""""#, true),
+            (r#""a""#, false),
+            ("[]", true),
+            ("[0]", false),
+            ("no_fields", true),
+            ("fields", false),
+            ("nothing", true),
+            ("0", false),
+            ("false", false),
+        ] {
+            let is_empty = eval(&mut vm, &format!("is_empty({})", source)).unwrap();
+            assert_eq!(is_empty, Value::Bool(empty), "is_empty({})", source);
+            let is_not_empty = eval(&mut vm, &format!("is_not_empty({})", source)).unwrap();
+            assert_eq!(is_not_empty, Value::Bool(!empty), "is_not_empty({})", source);
+        }
+        assert!(eval(&mut vm, "is_empty()").is_err());
+    }
+
+    #[test]
+    fn test_abs_diff() {
+        let mut vm = CoreVM::new();
+        let eval = |vm: &mut CoreVM, source: &str| vm.evaluate_expr(&parse_expression(source).unwrap());
+
+        assert_eq!(eval(&mut vm, "abs_diff(10, 3)").unwrap(), Value::Number(7));
+        assert_eq!(eval(&mut vm, "abs_diff(3, 10)").unwrap(), Value::Number(7));
+        assert_eq!(eval(&mut vm, "abs_diff(4, 4)").unwrap(), Value::Number(0));
+
+        vm.context.env.insert("low", Value::Number(-5));
+        assert_eq!(eval(&mut vm, "abs_diff(low, 5)").unwrap(), Value::Number(10));
+        vm.context.env.insert("floor", Value::Number(i64::MIN));
+        assert!(eval(&mut vm, "abs_diff(floor, 1)").unwrap_err().contains("overflowed"));
+        assert!(eval(&mut vm, r#"abs_diff("3", 1)"#).unwrap_err().contains("expected number"));
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut vm = CoreVM::new();
+        let eval = |vm: &mut CoreVM, source: &str| vm.evaluate_expr(&parse_expression(source).unwrap());
+
+        assert_eq!(eval(&mut vm, "decay(0, 24, 100)").unwrap(), Value::Number(0));
+        assert_eq!(eval(&mut vm, "decay(24, 24, 100)").unwrap(), Value::Number(50));
+        assert_eq!(eval(&mut vm, "decay(24, 24, 75)").unwrap(), Value::Number(37));
+        assert_eq!(eval(&mut vm, "decay(72, 24, 100)").unwrap(), Value::Number(75));
+        assert_eq!(eval(&mut vm, "decay(1000000000, 24, 100)").unwrap(), Value::Number(99));
+        vm.context.env.insert("forever", Value::Number(i64::MAX));
+        vm.context.env.insert("huge", Value::Number(i64::MAX));
+        assert_eq!(eval(&mut vm, "decay(forever, 1, huge)").unwrap(), Value::Number(i64::MAX - 1));
+
+        assert!(eval(&mut vm, "decay(0 - 1, 24, 100)").unwrap_err().contains("non-negative"));
+        assert!(eval(&mut vm, "decay(1, 24, 0 - 100)").unwrap_err().contains("non-negative"));
+        assert!(eval(&mut vm, "decay(1, 0, 100)").unwrap_err().contains("positive half_life"));
+        assert!(eval(&mut vm, r#"decay("1", 24, 100)"#).unwrap_err().contains("expected number"));
+    }
+
+    #[test]
+    fn test_int_and_bool_casts() {
+        let mut vm = CoreVM::new();
+        let eval = |vm: &mut CoreVM, source: &str| vm.evaluate_expr(&parse_expression(source).unwrap());
+
+        assert_eq!(eval(&mut vm, "int(true)").unwrap(), Value::Number(1));
+        assert_eq!(eval(&mut vm, "int(3 > 4)").unwrap(), Value::Number(0));
+        assert_eq!(eval(&mut vm, "int(42)").unwrap(), Value::Number(42));
+        assert_eq!(eval(&mut vm, r#"int(" -17 ")"#).unwrap(), Value::Number(-17));
+        assert!(eval(&mut vm, r#"int("1.5")"#).unwrap_err().contains("is not a whole number"));
+        assert!(eval(&mut vm, "int([1])").unwrap_err().contains("can't convert a list"));
+
+        assert_eq!(eval(&mut vm, "bool(0)").unwrap(), Value::Bool(false));
+        assert_eq!(eval(&mut vm, r#"bool("no")"#).unwrap(), Value::Bool(true));
+        assert_eq!(eval(&mut vm, "bool([])").unwrap(), Value::Bool(false));
+        assert_eq!(eval(&mut vm, "int(bool(7))").unwrap(), Value::Number(1));
+    }
+
+    #[test]
+    fn test_len_counts_characters() {
+        let mut vm = CoreVM::new();
+        let eval = |vm: &mut CoreVM, source: &str| vm.evaluate_expr(&parse_expression(source).unwrap());
+
+        assert_eq!(eval(&mut vm, r#"len("documentation")"#).unwrap(), Value::Number(13));
+        assert_eq!(eval(&mut vm, r#"byte_len("documentation")"#).unwrap(), Value::Number(13));
+
+        // Two-byte and four-byte UTF-8 characters each count once
+        assert_eq!(eval(&mut vm, r#"len("café")"#).unwrap(), Value::Number(4));
+        assert_eq!(eval(&mut vm, r#"byte_len("café")"#).unwrap(), Value::Number(5));
+        assert_eq!(eval(&mut vm, r#"len("🎫 ticket")"#).unwrap(), Value::Number(8));
+        assert_eq!(eval(&mut vm, r#"byte_len("🎫 ticket")"#).unwrap(), Value::Number(11));
+
+        assert!(eval(&mut vm, "byte_len([1, 2])").unwrap_err().contains("expected string or bytes"));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let mut vm = CoreVM::new();
+        let eval = |vm: &mut CoreVM, source: &str| vm.evaluate_expr(&parse_expression(source).unwrap());
+
+        assert_eq!(eval(&mut vm, "flatten([[1, 2], [3]])").unwrap(), eval(&mut vm, "[1, 2, 3]").unwrap());
+        assert_eq!(eval(&mut vm, "flatten([])").unwrap(), Value::List(vec![]));
+
+        // Scalars stay in place and only one level is removed
+        assert_eq!(
+            eval(&mut vm, r#"flatten([1, ["a", [2]], [], true])"#).unwrap(),
+            eval(&mut vm, r#"[1, "a", [2], true]"#).unwrap()
+        );
+        assert!(eval(&mut vm, "flatten(1)").is_err());
+    }
+
+    #[test]
+    fn test_distinct_count() {
+        let mut vm = CoreVM::new();
+
+        let expr = parse_expression(r#"distinct_count(["bug", "feature", "bug", 1, 1, true, "1"])"#).unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Number(5));
+
+        let expr = parse_expression("distinct_count([])").unwrap();
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Number(0));
+
+        let expr = parse_expression(r#"distinct_count("bug")"#).unwrap();
+        assert!(vm.evaluate_expr(&expr).is_err());
+    }
+
+    #[test]
+    fn test_bytes_value() {
+        let mut vm = CoreVM::new();
+        vm.context.env.insert("payload", Value::Bytes(vec![0x68, 0x69]));
+
+        assert_eq!(Value::Bytes(vec![1, 2]), Value::Bytes(vec![1, 2]));
+        assert_ne!(Value::Bytes(vec![1, 2]), Value::List(vec![Value::Number(1), Value::Number(2)]));
+        assert_eq!(Value::Bytes(vec![0x0a, 0xff]).to_string(), "0x0aff");
+
+        let expr = Expr::FunctionCall {
+            name: "len".to_string(),
+            args: vec![Expr::Ident("payload".to_string())],
+        };
+        assert_eq!(vm.evaluate_expr(&expr).unwrap(), Value::Number(2));
+    }
+
+    #[cfg(feature = "base64")]
+    #[test]
+    fn test_base64_round_trip() {
+        let mut vm = CoreVM::new();
+        vm.context.env.insert("payload", Value::Bytes(b"routix\x00\xff".to_vec()));
+
+        let encode = Expr::FunctionCall {
+            name: "base64_encode".to_string(),
+            args: vec![Expr::Ident("payload".to_string())],
+        };
+        let encoded = vm.evaluate_expr(&encode).unwrap();
+        assert_eq!(encoded, Value::String("cm91dGl4AP8=".to_string()));
+
+        let decode = Expr::FunctionCall {
+            name: "base64_decode".to_string(),
+            args: vec![encode],
+        };
+        assert_eq!(vm.evaluate_expr(&decode).unwrap(), Value::Bytes(b"routix\x00\xff".to_vec()));
+
+        let invalid = Expr::FunctionCall {
+            name: "base64_decode".to_string(),
+            args: vec![Expr::String("not base64!".to_string())],
+        };
+        assert!(vm.evaluate_expr(&invalid).is_err());
+    }
+
     #[test]
     fn test_variable_lookup() {
         let mut vm = CoreVM::new();
@@ -334,8 +714,9 @@ mod tests {
         // Create a workflow with both score and match phases
         let workflow = Workflow {
             name: "test_workflow".to_string(),
+            guard: None,
             phases: vec![
-                Phase::Score(vec![
+                Phase::Score { rules: vec![
                     Rule {
                         condition: Expr::BinaryOp {
                             left: Box::new(Expr::Ident("priority".to_string())),
@@ -356,7 +737,7 @@ mod tests {
                             right: Box::new(Expr::Number(5)),
                         }),
                     },
-                ]),
+                ], otherwise: None },
                 Phase::Match(vec![
                     MatchRule {
                         condition: Expr::BinaryOp {
@@ -366,7 +747,7 @@ mod tests {
                         },
                         action: MatchAction::AssignTo("high_priority".to_string()),
                     },
-                ]),
+                ], MatchStrategy::FirstMatch),
             ],
         };
         
@@ -459,6 +840,7 @@ mod tests {
             priority: 1,
             customer: Some("customer1".to_string()),
             score: 0,
+            ..Default::default()
         };
         
         let case2 = CaseConfig {
@@ -468,6 +850,7 @@ mod tests {
             priority: 5,
             customer: Some("customer2".to_string()),
             score: 0,
+            ..Default::default()
         };
         
         vm.add_case(case1);
@@ -476,8 +859,9 @@ mod tests {
         // Create a workflow that scores based on priority
         let workflow = Workflow {
             name: "priority_scoring".to_string(),
+            guard: None,
             phases: vec![
-                Phase::Score(vec![
+                Phase::Score { rules: vec![
                     Rule {
                         condition: Expr::Bool(true), // Always true
                         action: Action::AssignScore(Expr::BinaryOp {
@@ -486,7 +870,7 @@ mod tests {
                             right: Box::new(Expr::Number(10)),
                         }),
                     },
-                ]),
+                ], otherwise: None },
             ],
         };
         
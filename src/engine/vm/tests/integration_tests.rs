@@ -34,7 +34,7 @@ mod tests {
         // Parse the workflow
         let pairs = WorkflowParser::parse(Rule::program, workflow_source)
             .expect("Failed to parse workflow");
-        let workflows = builder_workflow::build_workflows(pairs);
+        let workflows = builder_workflow::build_workflows(pairs).expect("Failed to build workflows");
         
         assert_eq!(workflows.len(), 1);
         let workflow = &workflows[0];
@@ -49,6 +49,7 @@ mod tests {
                 priority: 5,
                 customer: Some("important_customer".to_string()),
                 score: 0,
+                ..Default::default()
             },
             CaseConfig {
                 id: 2,
@@ -57,6 +58,7 @@ mod tests {
                 priority: 2,
                 customer: None,
                 score: 0,
+                ..Default::default()
             },
             CaseConfig {
                 id: 3,
@@ -65,6 +67,7 @@ mod tests {
                 priority: 1,
                 customer: Some("regular_customer".to_string()),
                 score: 0,
+                ..Default::default()
             },
         ];
 
@@ -111,7 +114,7 @@ mod tests {
 
         let workflows = builder_workflow::build_workflows(
             WorkflowParser::parse(Rule::program, source).unwrap()
-        );
+        ).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -124,6 +127,7 @@ mod tests {
             priority: 8,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -133,6 +137,7 @@ mod tests {
             priority: 7,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -142,6 +147,7 @@ mod tests {
             priority: 3,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -172,7 +178,7 @@ mod tests {
 
         let workflows = builder_workflow::build_workflows(
             WorkflowParser::parse(Rule::program, source).unwrap()
-        );
+        ).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -185,6 +191,7 @@ mod tests {
             priority: 3,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -194,6 +201,7 @@ mod tests {
             priority: 8,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -203,6 +211,7 @@ mod tests {
             priority: 5,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -232,7 +241,7 @@ mod tests {
 
         let workflows = builder_workflow::build_workflows(
             WorkflowParser::parse(Rule::program, source).unwrap()
-        );
+        ).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -245,6 +254,7 @@ mod tests {
             priority: 7,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -254,6 +264,7 @@ mod tests {
             priority: 2,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -263,6 +274,7 @@ mod tests {
             priority: 5,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -297,7 +309,7 @@ mod tests {
 
         let workflows = builder_workflow::build_workflows(
             WorkflowParser::parse(Rule::program, source).unwrap()
-        );
+        ).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -310,6 +322,7 @@ mod tests {
             priority: 8,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -319,6 +332,7 @@ mod tests {
             priority: 9,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -328,6 +342,7 @@ mod tests {
             priority: 3,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -337,6 +352,7 @@ mod tests {
             priority: 6,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -353,6 +369,50 @@ mod tests {
         assert_eq!(cases[2].score, 15); // priority 3 * 5
     }
 
+    #[test]
+    fn test_phase_timing() {
+        use crate::engine::lang::ast::PhaseKind;
+
+        let workflow_source = r#"
+            workflow timed {
+                score {
+                    when priority > 2 then score = priority * 10
+                }
+                filter {
+                    when score > 0
+                }
+                sort {
+                    by score desc
+                }
+            }
+        "#;
+
+        let workflows = builder_workflow::build_workflows(
+            WorkflowParser::parse(Rule::program, workflow_source).unwrap()
+        ).expect("Failed to build workflows");
+
+        let mut vm = CoreVM::new();
+        vm.add_case(CaseConfig {
+            id: 1,
+            category: "bug".to_string(),
+            status: "open".to_string(),
+            priority: 4,
+            customer: None,
+            score: 0,
+            ..Default::default()
+        });
+
+        // Timing is off by default
+        vm.execute_workflow(&workflows[0]).unwrap();
+        assert!(vm.context.phase_timings.is_empty());
+
+        vm.context.timing = true;
+        vm.execute_workflow(&workflows[0]).unwrap();
+
+        let kinds: Vec<PhaseKind> = vm.context.phase_timings.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, vec![PhaseKind::Score, PhaseKind::Filter, PhaseKind::Sort]);
+    }
+
     #[test]
     fn test_dot_notation_case_properties() {
         let source = r#"
@@ -366,7 +426,7 @@ mod tests {
 
         let workflows = builder_workflow::build_workflows(
             WorkflowParser::parse(Rule::program, source).unwrap()
-        );
+        ).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -378,6 +438,7 @@ mod tests {
             priority: 8,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -387,6 +448,7 @@ mod tests {
             priority: 3,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -402,7 +464,7 @@ mod tests {
 
     #[test]
     fn test_dot_notation_agent_properties() {
-        use std::collections::HashMap;
+        use std::collections::BTreeMap;
         
         let source = r#"
             workflow agent_test {
@@ -415,13 +477,13 @@ mod tests {
 
         let workflows = builder_workflow::build_workflows(
             WorkflowParser::parse(Rule::program, source).unwrap()
-        );
+        ).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
         
         // Set up agent object
-        let mut agent_map = HashMap::new();
+        let mut agent_map = BTreeMap::new();
         agent_map.insert("id".to_string(), crate::engine::lang::ast::Value::String("agent_001".to_string()));
         agent_map.insert("department".to_string(), crate::engine::lang::ast::Value::String("support".to_string()));
         agent_map.insert("level".to_string(), crate::engine::lang::ast::Value::String("3".to_string()));
@@ -435,6 +497,7 @@ mod tests {
             priority: 1,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -446,6 +509,50 @@ mod tests {
         assert_eq!(cases[0].score, 150);
     }
 
+    #[test]
+    fn test_numeric_string_agent_properties_compare_as_numbers() {
+        use std::collections::BTreeMap;
+        use crate::engine::lang::ast::Value;
+
+        let source = r#"
+            workflow agent_level {
+                score {
+                    when agent.level > 2 then score = score + 10
+                    when 5 > agent.level then score = score + 1
+                    when agent.level == 3 then score = score + 100
+                }
+            }
+        "#;
+        let workflows = builder_workflow::build_workflows(
+            WorkflowParser::parse(Rule::program, source).unwrap()
+        ).expect("Failed to build workflows");
+
+        let mut vm = CoreVM::new();
+        let mut agent_map = BTreeMap::new();
+        agent_map.insert("level".to_string(), Value::String("3".to_string()));
+        vm.context.env.insert("agent", Value::Map(agent_map));
+        vm.add_case(CaseConfig {
+            id: 1,
+            category: "test".to_string(),
+            status: "open".to_string(),
+            priority: 1,
+            customer: None,
+            score: 0,
+            ..Default::default()
+        });
+
+        vm.execute_workflow(&workflows[0]).unwrap();
+
+        // Ordering coerces the numeric string; equality does not
+        assert_eq!(vm.get_cases()[0].score, 11);
+
+        let mut agent_map = BTreeMap::new();
+        agent_map.insert("level".to_string(), Value::String("senior".to_string()));
+        vm.context.env.insert("agent", Value::Map(agent_map));
+        let err = vm.execute_workflow(&workflows[0]).unwrap_err();
+        assert!(err.contains("non-numeric string"), "{}", err);
+    }
+
     #[test]
     fn test_dot_notation_in_filter_and_sort() {
         let source = r#"
@@ -466,7 +573,7 @@ mod tests {
 
         let workflows = builder_workflow::build_workflows(
             WorkflowParser::parse(Rule::program, source).unwrap()
-        );
+        ).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -478,6 +585,7 @@ mod tests {
             priority: 8,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -487,6 +595,7 @@ mod tests {
             priority: 7,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -496,6 +605,7 @@ mod tests {
             priority: 5,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -505,6 +615,7 @@ mod tests {
             priority: 2,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -521,7 +632,7 @@ mod tests {
 
     #[test]
     fn test_agent_skills_dot_notation() {
-        use std::collections::HashMap;
+        use std::collections::BTreeMap;
         
         let source = r#"
             workflow agent_skills_test {
@@ -534,13 +645,13 @@ mod tests {
 
         let workflows = builder_workflow::build_workflows(
             WorkflowParser::parse(Rule::program, source).unwrap()
-        );
+        ).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
         
         // Set up agent object with skills as list
-        let mut agent_map = HashMap::new();
+        let mut agent_map = BTreeMap::new();
         agent_map.insert("id".to_string(), crate::engine::lang::ast::Value::String("agent_001".to_string()));
         let skills_list = vec![
             crate::engine::lang::ast::Value::String("bug".to_string()),
@@ -559,6 +670,7 @@ mod tests {
             priority: 5,
             customer: None,
             score: 0,
+            ..Default::default()
         });
         
         vm.add_case(CaseConfig {
@@ -568,6 +680,7 @@ mod tests {
             priority: 3,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -595,7 +708,7 @@ mod tests {
 
         let pairs = WorkflowParser::parse(Rule::program, workflow_source)
             .expect("Failed to parse workflow");
-        let workflows = builder_workflow::build_workflows(pairs);
+        let workflows = builder_workflow::build_workflows(pairs).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -606,6 +719,7 @@ mod tests {
             priority: 4,
             customer: Some("test".to_string()),
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -633,7 +747,7 @@ mod tests {
 
         let pairs = WorkflowParser::parse(Rule::program, workflow_source)
             .expect("Failed to parse workflow");
-        let workflows = builder_workflow::build_workflows(pairs);
+        let workflows = builder_workflow::build_workflows(pairs).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -644,6 +758,7 @@ mod tests {
             priority: 5,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -663,6 +778,56 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_dynamic_match_assignment() {
+        let workflow_source = r#"
+            workflow per_category {
+                match {
+                    when priority > 0 then assign to case.category
+                }
+            }
+        "#;
+
+        let pairs = WorkflowParser::parse(Rule::program, workflow_source)
+            .expect("Failed to parse workflow");
+        let workflows = builder_workflow::build_workflows(pairs).expect("Failed to build workflows");
+        let workflow = &workflows[0];
+
+        let mut vm = CoreVM::new();
+        for (id, category) in [(1, "billing"), (2, "network"), (3, "billing")] {
+            vm.add_case(CaseConfig {
+                id,
+                category: category.to_string(),
+                status: "open".to_string(),
+                priority: 1,
+                customer: None,
+                score: 0,
+                ..Default::default()
+            });
+        }
+
+        vm.execute_workflow(workflow).expect("Failed to execute workflow");
+
+        assert!(vm.context.env.lookup("billing").is_some());
+        assert!(vm.context.env.lookup("network").is_some());
+        assert_eq!(vm.context.assignments.get("billing"), Some(&vec![1, 3]));
+        assert_eq!(vm.context.assignments.get("network"), Some(&vec![2]));
+
+        // A computed name that isn't a valid identifier is rejected
+        vm.clear_cases();
+        vm.add_case(CaseConfig {
+            id: 4,
+            category: "".to_string(),
+            status: "open".to_string(),
+            priority: 1,
+            customer: None,
+            score: 0,
+            ..Default::default()
+        });
+        let result = vm.execute_workflow(workflow);
+        assert!(result.unwrap_err().contains("Queue name must not be empty"));
+    }
+
     #[test]
     fn test_function_calls_in_workflow() {
         let workflow_source = r#"
@@ -677,7 +842,7 @@ mod tests {
 
         let pairs = WorkflowParser::parse(Rule::program, workflow_source)
             .expect("Failed to parse workflow");
-        let workflows = builder_workflow::build_workflows(pairs);
+        let workflows = builder_workflow::build_workflows(pairs).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -688,6 +853,7 @@ mod tests {
             priority: 3,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         vm.execute_workflow(workflow).expect("Failed to execute workflow");
@@ -716,7 +882,7 @@ mod tests {
 
         let pairs = WorkflowParser::parse(Rule::program, workflow_source)
             .expect("Failed to parse workflow");
-        let workflows = builder_workflow::build_workflows(pairs);
+        let workflows = builder_workflow::build_workflows(pairs).expect("Failed to build workflows");
         
         assert_eq!(workflows.len(), 2);
 
@@ -728,6 +894,7 @@ mod tests {
             priority: 4,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         // Execute first workflow
@@ -741,6 +908,89 @@ mod tests {
         assert_eq!(after_second, 80); // (20 + 20) * 2
     }
 
+    #[test]
+    fn test_score_otherwise_rule() {
+        let source = r#"
+            workflow defaults {
+                score {
+                    when category == "bug" then score = 50
+                    when priority > 4 then score = score + 10
+                    otherwise then score = 7
+                }
+            }
+        "#;
+
+        let pairs = WorkflowParser::parse(Rule::program, source).expect("Failed to parse workflow");
+        let workflows = builder_workflow::build_workflows(pairs).expect("Failed to build workflows");
+
+        let mut vm = CoreVM::new();
+        for (id, category) in [(1, "bug"), (2, "feature")] {
+            vm.add_case(CaseConfig {
+                id,
+                category: category.to_string(),
+                status: "open".to_string(),
+                priority: 1,
+                customer: None,
+                score: 0,
+                ..Default::default()
+            });
+        }
+        vm.execute_workflow(&workflows[0]).expect("Failed to execute workflow");
+
+        let scores: Vec<i64> = vm.get_cases().iter().map(|c| c.score).collect();
+        assert_eq!(scores, [50, 7]);
+    }
+
+    #[test]
+    fn test_program_results_per_workflow() {
+        let source = r#"
+            workflow triage {
+                score {
+                    when category == "bug" then log "bug seen"
+                }
+                match {
+                    when category == "bug" then assign to bugs
+                }
+            }
+
+            workflow escalate {
+                match {
+                    when priority > 3 then assign to urgent
+                    when priority > 0 then assign to bugs
+                }
+            }
+        "#;
+
+        let pairs = WorkflowParser::parse(Rule::program, source).expect("Failed to parse program");
+        let program = builder_workflow::build_program(pairs).expect("Failed to build program");
+
+        let mut vm = CoreVM::new();
+        for (id, category, priority) in [(1, "bug", 5), (2, "feature", 1)] {
+            vm.add_case(CaseConfig {
+                id,
+                category: category.to_string(),
+                status: "open".to_string(),
+                priority,
+                customer: None,
+                score: 0,
+                ..Default::default()
+            });
+        }
+
+        let results = vm.execute_program_with_results(&program).expect("Failed to execute program");
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["triage", "escalate"]);
+
+        let (_, triage) = &results[0];
+        assert_eq!(triage.routed.iter().map(|c| c.id).collect::<Vec<_>>(), [1]);
+        assert_eq!(triage.logs, ["bug seen"]);
+
+        // Only the second workflow's own routing, grouped by target name
+        let (_, escalate) = &results[1];
+        assert_eq!(escalate.routed.iter().map(|c| c.id).collect::<Vec<_>>(), [2, 1]);
+        assert!(escalate.logs.is_empty());
+    }
+
     #[test]
     fn test_error_handling_in_workflow() {
         let workflow_source = r#"
@@ -753,7 +1003,7 @@ mod tests {
 
         let pairs = WorkflowParser::parse(Rule::program, workflow_source)
             .expect("Failed to parse workflow");
-        let workflows = builder_workflow::build_workflows(pairs);
+        let workflows = builder_workflow::build_workflows(pairs).expect("Failed to build workflows");
         let workflow = &workflows[0];
 
         let mut vm = CoreVM::new();
@@ -764,6 +1014,7 @@ mod tests {
             priority: 1,
             customer: None,
             score: 0,
+            ..Default::default()
         });
 
         // This should fail due to undefined variable
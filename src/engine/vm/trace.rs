@@ -0,0 +1,15 @@
+use serde::Serialize;
+use crate::engine::lang::ast::PhaseKind;
+
+/// One rule evaluation against one case, recorded while tracing is enabled.
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceEvent {
+    pub workflow: String,
+    pub phase: PhaseKind,
+    pub case_id: i32,
+    pub rule_index: usize,
+    pub rule: String,
+    pub fired: bool,
+    /// The case score after the rule was applied
+    pub score: i64,
+}
@@ -0,0 +1,229 @@
+//! C-compatible bindings. Every entry point catches panics and reports them as
+//! `ROUTIX_ERR_PANIC`; failures store a message readable via `engine_last_error`,
+//! which the next call on the same engine clears.
+//! Strings returned by the engine must be released with `engine_string_free`.
+
+use std::{
+    ffi::{ CStr, CString, c_char },
+    panic::{ self, AssertUnwindSafe },
+    ptr,
+};
+use crate::{
+    engine::{ CoreEngine, lang::ast::Program },
+    models::case::CaseConfig,
+};
+
+#[cfg(test)]
+mod tests;
+
+pub const ROUTIX_OK: i32 = 0;
+pub const ROUTIX_ERR_NULL_POINTER: i32 = 1;
+pub const ROUTIX_ERR_INVALID_UTF8: i32 = 2;
+pub const ROUTIX_ERR_PARSE: i32 = 3;
+pub const ROUTIX_ERR_INVALID_INPUT: i32 = 4;
+pub const ROUTIX_ERR_RUNTIME: i32 = 5;
+pub const ROUTIX_ERR_PANIC: i32 = 6;
+
+/// Opaque handle handed to C callers.
+pub struct FfiEngine {
+    engine: CoreEngine,
+    program: Option<Program>,
+    last_error: Option<CString>,
+}
+
+impl FfiEngine {
+    fn fail(&mut self, code: i32, message: impl Into<String>) -> i32 {
+        // Interior NULs would truncate the message on the C side, so drop them.
+        let message: String = message.into().chars().filter(|c| *c != '\0').collect();
+        self.last_error = CString::new(message).ok();
+        code
+    }
+}
+
+/// Run `f` against the engine behind `handle`, translating null handles and
+/// panics into error codes. Only called from the `unsafe extern` entry points,
+/// whose contracts require `handle` to be null or a live pointer from `engine_new`.
+fn with_engine(handle: *mut FfiEngine, f: impl FnOnce(&mut FfiEngine) -> i32) -> i32 {
+    if handle.is_null() {
+        return ROUTIX_ERR_NULL_POINTER;
+    }
+    // SAFETY: non-null handles are live and unaliased per the entry point contracts.
+    let engine = unsafe { &mut *handle };
+    // A message left by an earlier call must not be read as this call's error
+    engine.last_error = None;
+    match panic::catch_unwind(AssertUnwindSafe(|| f(engine))) {
+        Ok(code) => code,
+        Err(_) => engine.fail(ROUTIX_ERR_PANIC, "engine panicked"),
+    }
+}
+
+/// Borrow a C string as UTF-8, recording the failure on the engine otherwise.
+///
+/// # Safety
+/// `input` must be null or point to a NUL-terminated string.
+unsafe fn read_str<'a>(engine: &mut FfiEngine, input: *const c_char) -> Result<&'a str, i32> {
+    if input.is_null() {
+        return Err(engine.fail(ROUTIX_ERR_NULL_POINTER, "input string is null"));
+    }
+    // SAFETY: guaranteed by the caller.
+    unsafe { CStr::from_ptr(input) }
+        .to_str()
+        .map_err(|_| engine.fail(ROUTIX_ERR_INVALID_UTF8, "input string is not valid UTF-8"))
+}
+
+/// Create an engine. Returns null if construction panicked.
+#[unsafe(no_mangle)]
+pub extern "C" fn engine_new() -> *mut FfiEngine {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(FfiEngine {
+            engine: CoreEngine::new(),
+            program: None,
+            last_error: None,
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Destroy an engine created by `engine_new`. Null is ignored.
+///
+/// # Safety
+/// `handle` must be null or a pointer returned by `engine_new` that has not
+/// already been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_free(handle: *mut FfiEngine) {
+    if !handle.is_null() {
+        // SAFETY: guaranteed by the caller.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(handle) })));
+    }
+}
+
+/// Parse `source` and keep it as the program for `engine_run`.
+///
+/// # Safety
+/// `handle` must come from `engine_new`; `source` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_load_program(handle: *mut FfiEngine, source: *const c_char) -> i32 {
+    with_engine(handle, |engine| {
+        // SAFETY: guaranteed by the caller.
+        let source = match unsafe { read_str(engine, source) } {
+            Ok(source) => source,
+            Err(code) => return code,
+        };
+        match engine.engine.parse_program_cached(source, None) {
+            Ok(program) => {
+                engine.program = Some(program);
+                ROUTIX_OK
+            }
+            Err(e) => engine.fail(ROUTIX_ERR_PARSE, e.to_string()),
+        }
+    })
+}
+
+/// Add cases from a JSON array of case objects.
+///
+/// # Safety
+/// `handle` must come from `engine_new`; `json` must be a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_add_cases_json(handle: *mut FfiEngine, json: *const c_char) -> i32 {
+    with_engine(handle, |engine| {
+        // SAFETY: guaranteed by the caller.
+        let json = match unsafe { read_str(engine, json) } {
+            Ok(json) => json,
+            Err(code) => return code,
+        };
+        match serde_json::from_str::<Vec<CaseConfig>>(json) {
+            Ok(cases) => match engine.engine.add_cases(cases) {
+                Ok(()) => ROUTIX_OK,
+                Err(e) => engine.fail(ROUTIX_ERR_INVALID_INPUT, e.to_string()),
+            },
+            Err(e) => engine.fail(ROUTIX_ERR_INVALID_INPUT, format!("Invalid cases JSON: {}", e)),
+        }
+    })
+}
+
+/// Execute the loaded program over the added cases.
+///
+/// # Safety
+/// `handle` must come from `engine_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_run(handle: *mut FfiEngine) -> i32 {
+    with_engine(handle, |engine| {
+        let Some(program) = engine.program.take() else {
+            return engine.fail(ROUTIX_ERR_RUNTIME, "No program loaded");
+        };
+        let result = engine.engine.execute_program(&program);
+        engine.program = Some(program);
+        match result {
+            Ok(()) => ROUTIX_OK,
+            Err(e) => engine.fail(ROUTIX_ERR_RUNTIME, e),
+        }
+    })
+}
+
+/// Return the execution report as a JSON string, or null on failure. The
+/// caller owns the string and must release it with `engine_string_free`.
+///
+/// # Safety
+/// `handle` must come from `engine_new`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_get_results_json(handle: *mut FfiEngine) -> *mut c_char {
+    let mut output = ptr::null_mut();
+    with_engine(handle, |engine| match engine.engine.export_results_json() {
+        Ok(json) => match CString::new(json) {
+            Ok(json) => {
+                output = json.into_raw();
+                ROUTIX_OK
+            }
+            Err(_) => engine.fail(ROUTIX_ERR_RUNTIME, "results contain a NUL byte"),
+        },
+        Err(e) => engine.fail(ROUTIX_ERR_RUNTIME, e.to_string()),
+    });
+    output
+}
+
+/// Copy the last error message into `buf` (NUL-terminated, truncated to
+/// `buf_len`). Returns the full message length in bytes, 0 when there is no
+/// error, or -1 for a null handle.
+///
+/// # Safety
+/// `handle` must come from `engine_new`; `buf` must be null or valid for
+/// `buf_len` bytes of writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_last_error(handle: *mut FfiEngine, buf: *mut c_char, buf_len: usize) -> i64 {
+    if handle.is_null() {
+        return -1;
+    }
+    // SAFETY: guaranteed by the caller.
+    let engine = unsafe { &*handle };
+    let Some(message) = &engine.last_error else {
+        if !buf.is_null() && buf_len > 0 {
+            // SAFETY: `buf` holds at least one byte.
+            unsafe { *buf = 0 };
+        }
+        return 0;
+    };
+
+    let bytes = message.as_bytes();
+    if !buf.is_null() && buf_len > 0 {
+        let copied = bytes.len().min(buf_len - 1);
+        // SAFETY: `copied + 1 <= buf_len` bytes are written into `buf`.
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr().cast::<c_char>(), buf, copied);
+            *buf.add(copied) = 0;
+        }
+    }
+    bytes.len() as i64
+}
+
+/// Release a string returned by the engine. Null is ignored.
+///
+/// # Safety
+/// `s` must be null or a pointer returned by `engine_get_results_json` that
+/// has not already been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn engine_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        // SAFETY: guaranteed by the caller.
+        drop(unsafe { CString::from_raw(s) });
+    }
+}
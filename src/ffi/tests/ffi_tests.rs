@@ -0,0 +1,128 @@
+#[cfg(test)]
+mod tests {
+    use crate::ffi::*;
+    use std::ffi::{ CStr, CString, c_char };
+    use std::ptr;
+
+    fn last_error(engine: *mut FfiEngine) -> String {
+        let mut buf = vec![0 as c_char; 256];
+        let len = unsafe { engine_last_error(engine, buf.as_mut_ptr(), buf.len()) };
+        assert!(len >= 0);
+        unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_run_and_fetch_results() {
+        let engine = engine_new();
+        assert!(!engine.is_null());
+
+        let source = CString::new(r#"
+            workflow triage {
+                score {
+                    when priority > 2 then score = priority * 10
+                }
+            }
+        "#).unwrap();
+        let cases = CString::new(r#"[{"id": 1, "category": "bug", "status": "open", "priority": 3}]"#).unwrap();
+
+        unsafe {
+            assert_eq!(engine_load_program(engine, source.as_ptr()), ROUTIX_OK);
+            assert_eq!(engine_add_cases_json(engine, cases.as_ptr()), ROUTIX_OK);
+            assert_eq!(engine_run(engine), ROUTIX_OK);
+
+            let results = engine_get_results_json(engine);
+            assert!(!results.is_null());
+            let json: serde_json::Value =
+                serde_json::from_str(CStr::from_ptr(results).to_str().unwrap()).unwrap();
+            assert_eq!(json["cases"][0]["score"], 30);
+
+            engine_string_free(results);
+            assert_eq!(engine_last_error(engine, ptr::null_mut(), 0), 0);
+            engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_error_codes_and_messages() {
+        let engine = engine_new();
+        let broken = CString::new("workflow broken {\n  score { when then }\n}").unwrap();
+        let bad_json = CString::new("[{\"id\": true}]").unwrap();
+
+        unsafe {
+            assert_eq!(engine_run(engine), ROUTIX_ERR_RUNTIME);
+            assert_eq!(last_error(engine), "No program loaded");
+
+            assert_eq!(engine_load_program(engine, broken.as_ptr()), ROUTIX_ERR_PARSE);
+            assert!(last_error(engine).starts_with("Parse error at 2:"));
+
+            assert_eq!(engine_add_cases_json(engine, bad_json.as_ptr()), ROUTIX_ERR_INVALID_INPUT);
+            assert!(last_error(engine).contains("Invalid cases JSON"));
+
+            assert_eq!(engine_load_program(engine, ptr::null()), ROUTIX_ERR_NULL_POINTER);
+
+            let invalid_utf8 = [0xffu8, 0xfe, 0x00];
+            assert_eq!(
+                engine_load_program(engine, invalid_utf8.as_ptr().cast()),
+                ROUTIX_ERR_INVALID_UTF8
+            );
+
+            engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_successful_calls_clear_the_last_error() {
+        let engine = engine_new();
+        let source = CString::new("workflow w { score { when true then score = 1 } }").unwrap();
+        let cases = CString::new("[]").unwrap();
+
+        unsafe {
+            assert_eq!(engine_run(engine), ROUTIX_ERR_RUNTIME);
+            assert_eq!(last_error(engine), "No program loaded");
+
+            assert_eq!(engine_load_program(engine, source.as_ptr()), ROUTIX_OK);
+            assert_eq!(engine_last_error(engine, ptr::null_mut(), 0), 0);
+
+            assert_eq!(engine_add_cases_json(engine, ptr::null()), ROUTIX_ERR_NULL_POINTER);
+            assert_eq!(last_error(engine), "input string is null");
+            assert_eq!(engine_add_cases_json(engine, cases.as_ptr()), ROUTIX_OK);
+            assert_eq!(engine_run(engine), ROUTIX_OK);
+            assert_eq!(last_error(engine), "");
+
+            engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_last_error_truncates_to_buffer() {
+        let engine = engine_new();
+        unsafe {
+            engine_run(engine);
+            let mut buf = [0 as c_char; 5];
+            let len = engine_last_error(engine, buf.as_mut_ptr(), buf.len());
+            assert_eq!(len, "No program loaded".len() as i64);
+            assert_eq!(CStr::from_ptr(buf.as_ptr()).to_str().unwrap(), "No p");
+            engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_panics_become_error_codes() {
+        let engine = engine_new();
+        let code = crate::ffi::with_engine(engine, |_| panic!("boom"));
+        assert_eq!(code, ROUTIX_ERR_PANIC);
+        assert_eq!(last_error(engine), "engine panicked");
+        unsafe { engine_free(engine) };
+    }
+
+    #[test]
+    fn test_null_handles() {
+        unsafe {
+            assert_eq!(engine_run(ptr::null_mut()), ROUTIX_ERR_NULL_POINTER);
+            assert!(engine_get_results_json(ptr::null_mut()).is_null());
+            assert_eq!(engine_last_error(ptr::null_mut(), ptr::null_mut(), 0), -1);
+            engine_free(ptr::null_mut());
+            engine_string_free(ptr::null_mut());
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1 @@
+pub mod ffi_tests;
\ No newline at end of file
@@ -1,5 +1,13 @@
 pub mod engine;
+#[cfg(not(target_arch = "wasm32"))]
 pub mod logging;
 pub mod models;
+pub mod repl;
+#[cfg(feature = "cli")]
+pub mod cli;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+#[cfg(feature = "ffi")]
+pub mod ffi;
 
 pub use engine::{CoreEngine, vm::CoreVM};
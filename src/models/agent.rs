@@ -1,14 +1,18 @@
+use serde::{ Deserialize, Serialize };
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentConfig {
     pub id: String,
     pub skills: Skills,
     pub max_concurrent: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Skills {
+    #[serde(default)]
     pub languages: Vec<String>,
+    #[serde(default)]
     pub services: Vec<String>,
+    #[serde(default)]
     pub platforms: Vec<String>,
 }
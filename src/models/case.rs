@@ -1,9 +1,26 @@
-#[derive(Debug, Clone)]
+use std::collections::BTreeMap;
+use serde::{ Deserialize, Serialize };
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct CaseConfig {
     pub id: i32,
     pub category: String,
     pub status: String,
     pub priority: i32,
+    #[serde(default)]
     pub customer: Option<String>,
+    #[serde(default)]
     pub score: i64,
+    /// Creation time in seconds since the Unix epoch, when known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<i64>,
+    /// 1-based position assigned by the most recent `rank` phase
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank: Option<i32>,
+    /// Named sub-scores written by `score.<name> = ...` actions
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scores: BTreeMap<String, i64>,
+    /// Id of the agent a `route to least_loaded` action picked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub assigned_to: Option<String>,
 }
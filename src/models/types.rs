@@ -1,6 +1,7 @@
 use crate::models::case::CaseConfig;
 
-#[derive(Debug)]
+/// What a single workflow routed and logged during a run.
+#[derive(Debug, Clone)]
 pub struct WorkflowResult {
     pub routed: Vec<CaseConfig>,
     pub logs: Vec<String>,
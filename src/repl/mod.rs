@@ -0,0 +1,146 @@
+use std::io::{ self, BufRead, Write };
+use crate::{
+    engine::{ CoreEngine, lang::ast::Value },
+    models::case::CaseConfig,
+};
+
+#[cfg(test)]
+mod tests;
+
+const HELP: &str = "\
+<expr>            evaluate an expression
+let <name> = <expr>  evaluate and bind a variable (`let ... in ...` is an expression)
+:vars             list variables and their values
+:load <file>      register the functions defined in a workflow file
+:case <json>      bind a case so expressions can read its fields
+:help             show this message
+:quit             leave the session";
+
+/// A line-oriented expression evaluator over a persistent engine. Errors are
+/// reported back to the caller and never end the session.
+pub struct Repl {
+    engine: CoreEngine,
+    case: Option<CaseConfig>,
+}
+
+impl Repl {
+    pub fn new() -> Self {
+        Self {
+            engine: CoreEngine::new(),
+            case: None,
+        }
+    }
+
+    pub fn engine(&self) -> &CoreEngine {
+        &self.engine
+    }
+
+    pub fn engine_mut(&mut self) -> &mut CoreEngine {
+        &mut self.engine
+    }
+
+    /// Evaluate one line of input and return the text to display.
+    pub fn eval_line(&mut self, line: &str) -> Result<String, String> {
+        let line = line.trim();
+
+        if line.is_empty() {
+            return Ok(String::new());
+        }
+        if let Some(command) = line.strip_prefix(':') {
+            return self.eval_command(command);
+        }
+        // `let x = 2 in x + 1` is an expression; only a `let` that doesn't
+        // parse as one binds a variable
+        if let Some(binding) = line.strip_prefix("let ")
+            && self.engine.parse_expression(line).is_err()
+        {
+            return self.eval_let(binding);
+        }
+
+        Ok(self.evaluate(line)?.to_string())
+    }
+
+    /// Evaluate an expression, against the bound case when there is one.
+    fn evaluate(&mut self, source: &str) -> Result<Value, String> {
+        let expr = self.engine.parse_expression(source)?;
+        match &self.case {
+            Some(case) => self.engine.evaluate_expression_for_case(&expr, case),
+            None => self.engine.evaluate_expression(&expr),
+        }
+    }
+
+    fn eval_let(&mut self, binding: &str) -> Result<String, String> {
+        let (name, source) = binding
+            .split_once('=')
+            .ok_or("Expected 'let <name> = <expr>'")?;
+        let name = name.trim();
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("Invalid variable name '{}'", name));
+        }
+
+        let value = self.evaluate(source.trim())?;
+        let output = format!("{} = {}", name, value);
+        self.engine.set_variable(name, value);
+        Ok(output)
+    }
+
+    fn eval_command(&mut self, command: &str) -> Result<String, String> {
+        let (name, argument) = command
+            .split_once(char::is_whitespace)
+            .map(|(name, arg)| (name, arg.trim()))
+            .unwrap_or((command, ""));
+
+        match name {
+            "vars" => {
+                let lines: Vec<String> = self
+                    .engine
+                    .dump_variables()
+                    .iter()
+                    .map(|(name, value)| format!("{} = {}", name, value))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            "load" => {
+                if argument.is_empty() {
+                    return Err("Usage: :load <file>".to_string());
+                }
+                let program = self.engine.parse_program_from_file(argument).map_err(|e| e.to_string())?;
+                let names: Vec<String> = program.functions.iter().map(|f| f.name.clone()).collect();
+                self.engine.register_functions(program.functions);
+                Ok(format!("Loaded {} function(s): {}", names.len(), names.join(", ")))
+            }
+            "case" => {
+                let case: CaseConfig = serde_json::from_str(argument)
+                    .map_err(|e| format!("Invalid case JSON: {}", e))?;
+                let output = format!("Case {} bound", case.id);
+                self.case = Some(case);
+                Ok(output)
+            }
+            "help" => Ok(HELP.to_string()),
+            other => Err(format!("Unknown command ':{}' (try :help)", other)),
+        }
+    }
+
+    /// Read lines until EOF or `:quit`, writing results and errors to `out`.
+    pub fn run(&mut self, input: impl BufRead, out: &mut dyn Write) -> io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if matches!(line.trim(), ":quit" | ":q") {
+                break;
+            }
+            match self.eval_line(&line) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => writeln!(out, "{}", output)?,
+                Err(error) => writeln!(out, "error: {}", error)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1 @@
+pub mod repl_tests;
\ No newline at end of file
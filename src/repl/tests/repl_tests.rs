@@ -0,0 +1,80 @@
+#[cfg(test)]
+mod tests {
+    use crate::repl::Repl;
+
+    #[test]
+    fn test_eval_expression() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval_line("1 + 2 * 3").unwrap(), "7");
+        assert_eq!(repl.eval_line("\"a\" + \"b\"").unwrap(), "\"ab\"");
+        assert_eq!(repl.eval_line("").unwrap(), "");
+    }
+
+    #[test]
+    fn test_let_binding_persists() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval_line("let threshold = 40 + 2").unwrap(), "threshold = 42");
+        assert_eq!(repl.eval_line("threshold > 10").unwrap(), "true");
+        assert_eq!(repl.eval_line(":vars").unwrap(), "threshold = 42");
+    }
+
+    #[test]
+    fn test_let_in_expression_is_not_a_binding() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.eval_line("let x = 2 in x + 1").unwrap(), "3");
+        assert_eq!(repl.eval_line(":vars").unwrap(), "");
+        assert!(repl.eval_line("x").unwrap_err().contains("Undefined variable"));
+    }
+
+    #[test]
+    fn test_errors_do_not_end_session() {
+        let mut repl = Repl::new();
+        assert!(repl.eval_line("1 +").is_err());
+        assert!(repl.eval_line("missing").unwrap_err().contains("Undefined variable"));
+        assert!(repl.eval_line(":nope").is_err());
+        assert_eq!(repl.eval_line("2").unwrap(), "2");
+    }
+
+    #[test]
+    fn test_case_binding() {
+        let mut repl = Repl::new();
+        let bound = repl
+            .eval_line(r#":case {"id": 7, "category": "bug", "status": "open", "priority": 4}"#)
+            .unwrap();
+        assert_eq!(bound, "Case 7 bound");
+        assert_eq!(repl.eval_line("priority * 10").unwrap(), "40");
+        assert_eq!(repl.eval_line("category == \"bug\"").unwrap(), "true");
+        // Case fields are scoped to each evaluation
+        assert!(repl.engine().get_variable("priority").is_none());
+    }
+
+    #[test]
+    fn test_load_functions() {
+        let dir = std::env::temp_dir().join(format!("routix_repl_tests_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("helpers.rtx");
+        std::fs::write(&path, "function double(x) = x * 2").unwrap();
+
+        let mut repl = Repl::new();
+        let loaded = repl.eval_line(&format!(":load {}", path.display())).unwrap();
+        assert!(loaded.contains("double"));
+        assert_eq!(repl.eval_line("double(21)").unwrap(), "42");
+        assert!(repl.eval_line(":load missing.rtx").is_err());
+    }
+
+    #[test]
+    fn test_run_scripted_session() {
+        let mut repl = Repl::new();
+        let input = "let x = 5\nx * x\nbogus +\n:quit\nx\n";
+        let mut out = Vec::new();
+        repl.run(input.as_bytes(), &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0], "x = 5");
+        assert_eq!(lines[1], "25");
+        assert!(lines[2].starts_with("error: "));
+        // Input after :quit is never evaluated
+        assert!(!lines.contains(&"5"));
+    }
+}
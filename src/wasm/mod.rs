@@ -0,0 +1,126 @@
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use crate::{
+    engine::{ CoreEngine, EngineError, Severity, ValidationOptions, lang::ast::Program, vm::clock::Clock },
+    models::case::CaseConfig,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// A problem found in workflow source, positioned for display in an editor.
+/// `line` and `column` are 0 when the problem has no source position, and a
+/// problem tied to a workflow names it at the start of `message`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl From<crate::engine::Diagnostic> for Diagnostic {
+    fn from(diagnostic: crate::engine::Diagnostic) -> Self {
+        let message = match &diagnostic.workflow {
+            Some(workflow) => format!("workflow '{}': {}", workflow, diagnostic.message),
+            None => diagnostic.message,
+        };
+        Diagnostic {
+            line: diagnostic.line.unwrap_or(0),
+            column: diagnostic.column.unwrap_or(0),
+            message,
+            severity: diagnostic.severity,
+        }
+    }
+}
+
+/// Thin wasm-bindgen wrapper over `CoreEngine` for previewing workflows in the
+/// browser. Results and diagnostics cross the boundary as JSON strings.
+#[wasm_bindgen]
+pub struct WasmEngine {
+    engine: CoreEngine,
+    program: Option<Program>,
+}
+
+#[wasm_bindgen]
+impl WasmEngine {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmEngine {
+        WasmEngine {
+            engine: CoreEngine::new(),
+            program: None,
+        }
+    }
+
+    /// Parse and keep `source` for later runs. Parse errors carry line and column.
+    pub fn load_program(&mut self, source: &str) -> Result<(), JsValue> {
+        self.load(source).map_err(to_js_error)
+    }
+
+    /// Add cases from a JSON array of case objects.
+    pub fn add_cases_json(&mut self, json: &str) -> Result<(), JsValue> {
+        self.add_cases(json).map_err(to_js_error)
+    }
+
+    /// Run the loaded program over the added cases and return the execution
+    /// report as JSON.
+    pub fn run(&mut self) -> Result<JsValue, JsValue> {
+        self.run_to_json().map(JsValue::from).map_err(to_js_error)
+    }
+
+    /// Check `source` without loading it, returning a JSON array of diagnostics.
+    pub fn validate(&self, source: &str) -> JsValue {
+        JsValue::from(self.validate_to_json(source))
+    }
+
+    /// Fix the time `now()` returns, in seconds since the Unix epoch, e.g.
+    /// `Date.now() / 1000`; fractions are dropped. The browser has no system
+    /// clock for the engine, so set this before running a program that reads
+    /// the time.
+    pub fn set_time(&mut self, secs: f64) {
+        self.engine.set_clock(Clock::Fixed(secs as i64));
+    }
+}
+
+impl WasmEngine {
+    pub(crate) fn load(&mut self, source: &str) -> Result<(), EngineError> {
+        self.program = Some(self.engine.parse_program_cached(source, None)?);
+        Ok(())
+    }
+
+    pub(crate) fn add_cases(&mut self, json: &str) -> Result<(), EngineError> {
+        let cases: Vec<CaseConfig> = serde_json::from_str(json)?;
+        self.engine.add_cases(cases)
+    }
+
+    pub(crate) fn run_to_json(&mut self) -> Result<String, EngineError> {
+        let program = self
+            .program
+            .as_ref()
+            .ok_or_else(|| EngineError::Runtime("No program loaded".to_string()))?;
+        self.engine.execute_program(program).map_err(EngineError::Runtime)?;
+        self.engine.export_results_json()
+    }
+
+    pub(crate) fn diagnostics(&self, source: &str) -> Vec<Diagnostic> {
+        self.engine
+            .validate_program(source, &ValidationOptions::default())
+            .into_iter()
+            .map(Diagnostic::from)
+            .collect()
+    }
+
+    pub(crate) fn validate_to_json(&self, source: &str) -> String {
+        serde_json::to_string(&self.diagnostics(source)).unwrap_or_else(|_| "[]".to_string())
+    }
+}
+
+impl Default for WasmEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn to_js_error(error: EngineError) -> JsValue {
+    JsValue::from(error.to_string())
+}
@@ -0,0 +1 @@
+pub mod wasm_tests;
\ No newline at end of file
@@ -0,0 +1,81 @@
+#[cfg(test)]
+mod tests {
+    use crate::{ engine::EngineError, wasm::WasmEngine };
+
+    #[cfg(target_arch = "wasm32")]
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    const SCORING: &str = r#"
+        workflow preview {
+            score {
+                when priority > 2 then score = priority * 10
+            }
+            match {
+                when score > 0 then assign to urgent
+            }
+        }
+    "#;
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_load_program_reports_position() {
+        let mut engine = WasmEngine::new();
+        match engine.load("workflow broken {\n  score { when then }\n}") {
+            Err(EngineError::Parse { line, column, .. }) => {
+                assert_eq!(line, 2);
+                assert!(column > 0);
+            }
+            other => panic!("Expected parse error, got {:?}", other),
+        }
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_validate_returns_diagnostics() {
+        let engine = WasmEngine::new();
+        assert_eq!(engine.validate_to_json(SCORING), "[]");
+
+        let diagnostics: serde_json::Value =
+            serde_json::from_str(&engine.validate_to_json("workflow {}")).unwrap();
+        assert_eq!(diagnostics[0]["line"], 1);
+        assert_eq!(diagnostics[0]["column"], 10);
+        assert!(diagnostics[0]["message"].is_string());
+        assert_eq!(diagnostics[0]["severity"], "error");
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_scoring_run() {
+        let mut engine = WasmEngine::new();
+        engine.load(SCORING).unwrap();
+        engine.add_cases(r#"[
+            {"id": 1, "category": "bug", "status": "open", "priority": 4},
+            {"id": 2, "category": "bug", "status": "open", "priority": 1}
+        ]"#).unwrap();
+
+        let report: serde_json::Value = serde_json::from_str(&engine.run_to_json().unwrap()).unwrap();
+        assert_eq!(report["cases"][0]["score"], 40);
+        assert_eq!(report["cases"][1]["score"], 0);
+        assert_eq!(report["assignments"]["urgent"], serde_json::json!([1]));
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_set_time() {
+        let mut engine = WasmEngine::new();
+        engine.set_time(1_700_000_042.9);
+        engine.load("workflow clock { score { when true then score = now() - 1700000000 } }").unwrap();
+        engine.add_cases(r#"[{"id": 1, "category": "bug", "status": "open", "priority": 1}]"#).unwrap();
+
+        let report: serde_json::Value = serde_json::from_str(&engine.run_to_json().unwrap()).unwrap();
+        assert_eq!(report["cases"][0]["score"], 42);
+    }
+
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test)]
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    fn test_run_without_program() {
+        let mut engine = WasmEngine::new();
+        assert!(engine.run_to_json().unwrap_err().to_string().contains("No program loaded"));
+        assert!(engine.add_cases("not json").is_err());
+    }
+}
\ No newline at end of file